@@ -0,0 +1,9 @@
+-- Outstanding password reset and onboarding links --
+CREATE TABLE password_resets
+(
+    token_hash VARCHAR(64) PRIMARY KEY,
+    club_id    INTEGER     NOT NULL REFERENCES clubs ON DELETE CASCADE,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX password_resets_club_id_idx ON password_resets (club_id);
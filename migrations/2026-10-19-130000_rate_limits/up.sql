@@ -0,0 +1,9 @@
+-- Request counters for rate limiting, shared between instances --
+CREATE TABLE rate_limits
+(
+    key       TEXT         PRIMARY KEY,
+    hits      INTEGER      NOT NULL,
+    resets_at TIMESTAMPTZ  NOT NULL
+);
+
+CREATE INDEX rate_limits_resets_at_idx ON rate_limits (resets_at);
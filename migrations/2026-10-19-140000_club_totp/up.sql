@@ -0,0 +1,13 @@
+-- TOTP two-factor authentication, one row per club that has started enrolling --
+CREATE TABLE club_totp
+(
+    club_id        INTEGER PRIMARY KEY REFERENCES clubs ON DELETE CASCADE,
+    -- base32, as shown to authenticator apps
+    secret         VARCHAR(64) NOT NULL,
+    -- false until the club confirms a code from its app
+    enabled        BOOLEAN     NOT NULL DEFAULT FALSE,
+    -- each code only works once
+    last_used_step BIGINT      NOT NULL DEFAULT 0,
+    -- sha256 hashes of the unused recovery codes
+    recovery_codes TEXT[]      NOT NULL DEFAULT '{}'
+);
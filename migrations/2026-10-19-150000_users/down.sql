@@ -0,0 +1,48 @@
+-- clubs get the password of their first president back, other officers lose access
+ALTER TABLE clubs
+    ADD COLUMN password_hash VARCHAR NOT NULL DEFAULT '';
+UPDATE clubs
+SET password_hash = presidents.password_hash
+FROM (SELECT DISTINCT ON (club_officers.club_id) club_officers.club_id, users.password_hash
+      FROM club_officers
+               JOIN users ON users.id = club_officers.user_id
+      WHERE club_officers.role = 'president'
+      ORDER BY club_officers.club_id, users.id) presidents
+WHERE presidents.club_id = clubs.id;
+ALTER TABLE clubs
+    ALTER COLUMN password_hash DROP DEFAULT;
+
+ALTER TABLE user_totp
+    ADD COLUMN club_id INTEGER REFERENCES clubs ON DELETE CASCADE;
+UPDATE user_totp
+SET club_id = clubs.id
+FROM users
+         JOIN clubs ON clubs.username = users.username
+WHERE users.id = user_totp.user_id;
+DELETE
+FROM user_totp
+WHERE club_id IS NULL;
+ALTER TABLE user_totp
+    DROP COLUMN user_id,
+    ALTER COLUMN club_id SET NOT NULL,
+    ADD PRIMARY KEY (club_id);
+ALTER TABLE user_totp
+    RENAME TO club_totp;
+
+ALTER TABLE password_resets
+    ADD COLUMN club_id INTEGER REFERENCES clubs ON DELETE CASCADE;
+UPDATE password_resets
+SET club_id = clubs.id
+FROM users
+         JOIN clubs ON clubs.username = users.username
+WHERE users.id = password_resets.user_id;
+DELETE
+FROM password_resets
+WHERE club_id IS NULL;
+ALTER TABLE password_resets
+    DROP COLUMN user_id,
+    ALTER COLUMN club_id SET NOT NULL;
+CREATE INDEX password_resets_club_id_idx ON password_resets (club_id);
+
+DROP TABLE club_officers;
+DROP TABLE users;
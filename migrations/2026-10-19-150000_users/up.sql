@@ -0,0 +1,58 @@
+-- People who log in, each managing any number of clubs --
+CREATE TABLE users
+(
+    id            SERIAL PRIMARY KEY,
+    username      VARCHAR NOT NULL UNIQUE,
+    email         VARCHAR NOT NULL,
+    password_hash VARCHAR NOT NULL
+);
+
+CREATE TABLE club_officers
+(
+    club_id INTEGER NOT NULL REFERENCES clubs ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users ON DELETE CASCADE,
+    role    VARCHAR NOT NULL CHECK (role IN ('president', 'officer', 'editor')),
+    PRIMARY KEY (club_id, user_id)
+);
+
+CREATE INDEX club_officers_user_id_idx ON club_officers (user_id);
+
+-- every shared club login becomes a user who is president of the club, keeping the
+-- username and password so nobody has to be told anything
+INSERT INTO users (username, email, password_hash)
+SELECT username, email, password_hash
+FROM clubs
+ORDER BY id;
+
+INSERT INTO club_officers (club_id, user_id, role)
+SELECT clubs.id, users.id, 'president'
+FROM clubs
+         JOIN users ON users.username = clubs.username;
+
+-- reset links and two-factor authentication belong to the login, so they move along
+ALTER TABLE password_resets
+    ADD COLUMN user_id INTEGER REFERENCES users ON DELETE CASCADE;
+UPDATE password_resets
+SET user_id = club_officers.user_id
+FROM club_officers
+WHERE club_officers.club_id = password_resets.club_id;
+ALTER TABLE password_resets
+    ALTER COLUMN user_id SET NOT NULL,
+    DROP COLUMN club_id;
+CREATE INDEX password_resets_user_id_idx ON password_resets (user_id);
+
+ALTER TABLE club_totp
+    RENAME TO user_totp;
+ALTER TABLE user_totp
+    ADD COLUMN user_id INTEGER REFERENCES users ON DELETE CASCADE;
+UPDATE user_totp
+SET user_id = club_officers.user_id
+FROM club_officers
+WHERE club_officers.club_id = user_totp.club_id;
+ALTER TABLE user_totp
+    DROP COLUMN club_id,
+    ALTER COLUMN user_id SET NOT NULL,
+    ADD PRIMARY KEY (user_id);
+
+ALTER TABLE clubs
+    DROP COLUMN password_hash;
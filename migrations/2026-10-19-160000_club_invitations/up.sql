@@ -0,0 +1,14 @@
+-- Invitations for someone to become an officer of a club, accepted through an emailed link --
+CREATE TABLE club_invitations
+(
+    id         SERIAL PRIMARY KEY,
+    club_id    INTEGER     NOT NULL REFERENCES clubs ON DELETE CASCADE,
+    email      VARCHAR     NOT NULL,
+    role       VARCHAR     NOT NULL CHECK (role IN ('president', 'officer', 'editor')),
+    -- sha256 of the token in the link, which is replaced when the invitation is resent
+    token_hash VARCHAR     NOT NULL UNIQUE,
+    invited_by INTEGER REFERENCES users ON DELETE SET NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX club_invitations_club_id_idx ON club_invitations (club_id);
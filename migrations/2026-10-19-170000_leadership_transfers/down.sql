@@ -0,0 +1,4 @@
+ALTER TABLE users
+    DROP COLUMN sessions_revoked_at;
+
+DROP TABLE leadership_transfers;
@@ -0,0 +1,27 @@
+-- Handing a club's presidency and contact email to someone new, confirmed by them through
+-- an emailed link. Completed transfers are kept as a record of who led the club --
+CREATE TABLE leadership_transfers
+(
+    id           SERIAL PRIMARY KEY,
+    club_id      INTEGER     NOT NULL REFERENCES clubs ON DELETE CASCADE,
+    new_email    VARCHAR     NOT NULL,
+    -- sha256 of the token in the link, cleared once the transfer is confirmed
+    token_hash   VARCHAR UNIQUE,
+    -- null when an admin started the transfer
+    requested_by INTEGER REFERENCES users ON DELETE SET NULL,
+    requested_at TIMESTAMPTZ NOT NULL,
+    expires_at   TIMESTAMPTZ NOT NULL,
+    -- filled in once the transfer is confirmed
+    old_email    VARCHAR,
+    from_user    INTEGER REFERENCES users ON DELETE SET NULL,
+    to_user      INTEGER REFERENCES users ON DELETE SET NULL,
+    completed_at TIMESTAMPTZ
+);
+
+-- a club has at most one transfer waiting to be confirmed
+CREATE UNIQUE INDEX leadership_transfers_pending_idx ON leadership_transfers (club_id)
+    WHERE completed_at IS NULL;
+
+-- tokens issued before this stop working, e.g. for a president who handed over their club
+ALTER TABLE users
+    ADD COLUMN sessions_revoked_at TIMESTAMPTZ;
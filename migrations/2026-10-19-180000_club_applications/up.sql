@@ -0,0 +1,20 @@
+-- Clubs asking to be listed, waiting for an admin to approve or reject them --
+CREATE TABLE club_applications
+(
+    id               SERIAL PRIMARY KEY,
+    club_name        VARCHAR     NOT NULL,
+    description      VARCHAR     NOT NULL,
+    meet_time        VARCHAR     NOT NULL,
+    advisor          VARCHAR     NOT NULL,
+    email            VARCHAR     NOT NULL,
+    status           VARCHAR     NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'approved', 'rejected')),
+    submitted_at     TIMESTAMPTZ NOT NULL,
+    reviewed_at      TIMESTAMPTZ,
+    -- sent to the applicant when rejected
+    rejection_reason VARCHAR,
+    -- the club registered for an approved application
+    club_id          INTEGER REFERENCES clubs ON DELETE SET NULL
+);
+
+CREATE INDEX club_applications_status_idx ON club_applications (status);
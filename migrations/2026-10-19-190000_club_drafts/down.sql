@@ -0,0 +1,4 @@
+DROP TABLE club_drafts;
+
+ALTER TABLE clubs
+    DROP COLUMN trusted;
@@ -0,0 +1,32 @@
+-- Trusted clubs publish their edits straight away even when edits are moderated --
+ALTER TABLE clubs
+    ADD COLUMN trusted BOOLEAN NOT NULL DEFAULT false;
+
+-- Profile edits waiting for an admin to publish or reject them --
+CREATE TABLE club_drafts
+(
+    id               SERIAL PRIMARY KEY,
+    club_id          INTEGER     NOT NULL REFERENCES clubs ON DELETE CASCADE,
+    submitted_by     INTEGER REFERENCES users ON DELETE SET NULL,
+    submitted_at     TIMESTAMPTZ NOT NULL,
+    club_name        VARCHAR     NOT NULL,
+    description      VARCHAR     NOT NULL,
+    about            TEXT        NOT NULL,
+    meet_time        VARCHAR     NOT NULL,
+    category_ids     INTEGER[]   NOT NULL,
+    website          VARCHAR,
+    google_classroom VARCHAR,
+    discord          VARCHAR,
+    instagram        VARCHAR,
+    -- superseded drafts were replaced by a newer one before being reviewed
+    status           VARCHAR     NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'approved', 'rejected', 'superseded')),
+    reviewed_at      TIMESTAMPTZ,
+    -- shown to the club
+    review_comment   VARCHAR
+);
+
+CREATE INDEX club_drafts_club_id_idx ON club_drafts (club_id);
+CREATE INDEX club_drafts_status_idx ON club_drafts (status);
+-- a club has at most one draft waiting for review
+CREATE UNIQUE INDEX club_drafts_pending_idx ON club_drafts (club_id) WHERE status = 'pending';
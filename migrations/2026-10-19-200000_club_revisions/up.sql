@@ -0,0 +1,22 @@
+-- Every published version of a club's profile, newest last --
+CREATE TABLE club_revisions
+(
+    id               SERIAL PRIMARY KEY,
+    club_id          INTEGER     NOT NULL REFERENCES clubs ON DELETE CASCADE,
+    -- null for the profile as it was before its first recorded edit
+    edited_by        INTEGER REFERENCES users ON DELETE SET NULL,
+    created_at       TIMESTAMPTZ NOT NULL,
+    club_name        VARCHAR     NOT NULL,
+    description      VARCHAR     NOT NULL,
+    about            TEXT        NOT NULL,
+    meet_time        VARCHAR     NOT NULL,
+    category_ids     INTEGER[]   NOT NULL,
+    website          VARCHAR,
+    google_classroom VARCHAR,
+    discord          VARCHAR,
+    instagram        VARCHAR,
+    -- the revision this one restored, for reverts
+    reverted_from    INTEGER REFERENCES club_revisions ON DELETE SET NULL
+);
+
+CREATE INDEX club_revisions_club_id_idx ON club_revisions (club_id);
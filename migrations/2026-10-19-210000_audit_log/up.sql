@@ -0,0 +1,25 @@
+-- Who did what to which club, and from where --
+CREATE TABLE audit_log
+(
+    id         SERIAL PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL,
+    -- the logged in user, null for the admin key and people who aren't logged in
+    actor_id   INTEGER REFERENCES users ON DELETE SET NULL,
+    -- the username at the time, "admin" for the admin key, or the username a failed
+    -- login tried
+    actor      VARCHAR     NOT NULL,
+    -- like club.edit or auth.login_failed
+    action     VARCHAR     NOT NULL,
+    club_id    INTEGER REFERENCES clubs ON DELETE SET NULL,
+    -- what was acted on, like a club or user's username, kept when it is deleted
+    target     VARCHAR,
+    ip         VARCHAR,
+    user_agent VARCHAR,
+    before     JSONB,
+    after      JSONB
+);
+
+CREATE INDEX audit_log_created_at_idx ON audit_log (created_at);
+CREATE INDEX audit_log_club_id_idx ON audit_log (club_id);
+CREATE INDEX audit_log_actor_idx ON audit_log (actor);
+CREATE INDEX audit_log_action_idx ON audit_log (action);
@@ -0,0 +1,3 @@
+ALTER TABLE clubs
+    DROP COLUMN deleted_at,
+    DROP COLUMN status;
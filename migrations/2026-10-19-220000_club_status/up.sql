@@ -0,0 +1,9 @@
+-- Inactive clubs stopped meeting and archived ones are closed for good. Only active
+-- clubs are listed publicly --
+ALTER TABLE clubs
+    ADD COLUMN status     VARCHAR NOT NULL DEFAULT 'active'
+        CHECK (status IN ('active', 'inactive', 'archived')),
+    -- soft deleted clubs are hidden everywhere but can still be restored
+    ADD COLUMN deleted_at TIMESTAMPTZ;
+
+CREATE INDEX clubs_status_idx ON clubs (status);
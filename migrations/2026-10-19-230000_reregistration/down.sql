@@ -0,0 +1,7 @@
+DROP TABLE club_reminders;
+
+ALTER TABLE clubs
+    DROP COLUMN missed_deadline,
+    DROP COLUMN confirmed_at,
+    DROP COLUMN updated_at,
+    DROP COLUMN created_at;
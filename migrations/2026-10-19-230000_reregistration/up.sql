@@ -0,0 +1,34 @@
+-- When clubs were registered and their profile last changed. Clubs from before this
+-- have no known registration date and count as registered long ago, so they are asked
+-- to confirm like every other club; their profiles are dated by their revisions --
+ALTER TABLE clubs
+    ADD COLUMN created_at      TIMESTAMPTZ NOT NULL DEFAULT 'epoch',
+    ADD COLUMN updated_at      TIMESTAMPTZ NOT NULL DEFAULT 'epoch',
+    -- last time an officer confirmed the profile is still accurate
+    ADD COLUMN confirmed_at    TIMESTAMPTZ,
+    -- made inactive by the confirmation deadline, confirming makes it active again
+    ADD COLUMN missed_deadline BOOLEAN     NOT NULL DEFAULT false;
+
+UPDATE clubs
+SET updated_at = revisions.last
+FROM (SELECT club_id, MAX(created_at) AS last
+      FROM club_revisions
+      GROUP BY club_id) AS revisions
+WHERE revisions.club_id = clubs.id;
+
+ALTER TABLE clubs
+    ALTER COLUMN created_at SET DEFAULT now(),
+    ALTER COLUMN updated_at SET DEFAULT now();
+
+-- Emails asking a club to confirm its profile for the school year --
+CREATE TABLE club_reminders
+(
+    id          SERIAL PRIMARY KEY,
+    club_id     INTEGER     NOT NULL REFERENCES clubs ON DELETE CASCADE,
+    -- start of the school year the reminder is for, its link only works during that year
+    school_year TIMESTAMPTZ NOT NULL,
+    token_hash  VARCHAR     NOT NULL UNIQUE,
+    sent_at     TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX club_reminders_club_id_idx ON club_reminders (club_id);
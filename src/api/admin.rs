@@ -1,27 +1,40 @@
-use std::{
-    sync::Arc,
-    time::{Duration, Instant},
-};
+use std::time::Duration;
 
-use super::{password::Resets, DEFAULT_BANNER_URL, DEFAULT_PROFILE_PICTURE_URL};
+use super::{
+    applications, directory, drafts, lifecycle, password, reregistration, transfer, two_factor,
+    DEFAULT_BANNER_URL, DEFAULT_PROFILE_PICTURE_URL,
+};
 use crate::{
-    auth::{self, AdminOnly},
-    email::{self, EMAIL_ADDRESS, FRONTEND_HOST},
+    audit::{self, Actor, Client, Entry},
+    auth::{AdminOnly, HashSettings},
+    backup,
     error::{AppError, AppResult},
-    models::Club,
+    models::{ApplicationStatus, Club, ClubOfficer, ClubStatus, DraftStatus, Role, User},
     schema::*,
-    DbPool,
+    state::AppState,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::{
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+        StatusCode,
+    },
+    response::IntoResponse,
+    routing::{delete, get, post},
+    Json, Router,
 };
-use axum::{http::StatusCode, routing::post, Extension, Json, Router};
 use diesel::prelude::*;
-use diesel_async::RunQueryDsl;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
 use lettre::{message::Mailbox, Address, Message};
 use nanoid::nanoid;
 use serde::{Deserialize, Serialize};
-use tokio::sync::Mutex;
+use std::collections::BTreeMap;
 
-#[derive(Deserialize)]
-struct ClubRegisterRequest {
+// 7 days
+pub const ONBOARDING_ALLOWED_TIME: Duration = Duration::from_secs(60 * 60 * 24 * 7);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClubRegisterRequest {
     pub username: String,
     pub email: String,
     pub name: String,
@@ -30,7 +43,7 @@ struct ClubRegisterRequest {
 }
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
-struct ClubRegisterResponse {
+pub struct ClubRegisterResponse {
     pub message: String,
 }
 
@@ -47,19 +60,50 @@ impl ClubRegisterResponse {
     }
 }
 
-// TODO: email users after registering
 async fn register(
-    Extension(pool): Extension<DbPool>,
-    Extension(resets): Extension<Arc<Mutex<Resets>>>,
-    Json(req): Json<ClubRegisterRequest>,
+    State(state): State<AppState>,
     AdminOnly: AdminOnly,
+    client: Client,
+    Json(req): Json<ClubRegisterRequest>,
 ) -> AppResult<Json<ClubRegisterResponse>> {
+    let new_club = register_club(&state, req).await?;
+    audit::record(
+        &state,
+        &client,
+        Entry::new(Actor::Admin, "club.register")
+            .club(&new_club)
+            .after(&new_club),
+    )
+    .await;
+    Ok(Json(ClubRegisterResponse::from_club(&new_club)?))
+}
+
+/// Creates a club along with a user to be its president, who gets a random password
+/// and an email with a link to set one.
+pub async fn register_club(state: &AppState, req: ClubRegisterRequest) -> AppResult<Club> {
+    let password_hash = state.hasher.hash(rand::random::<[u8; 32]>())?;
+    let conn = &mut state.pool.get().await?;
+
+    let (new_club, president) = conn
+        .transaction::<_, AppError, _>(|conn| Box::pin(insert_club(conn, password_hash, req)))
+        .await?;
+    send_onboarding_email(state, &president).await?;
+
+    Ok(new_club)
+}
+
+/// Inserts a club, its socials and its president with `password_hash`, without emailing
+/// anyone. Meant to run in a transaction.
+pub(super) async fn insert_club(
+    conn: &mut AsyncPgConnection,
+    password_hash: String,
+    req: ClubRegisterRequest,
+) -> AppResult<(Club, User)> {
     #[derive(Insertable)]
     #[diesel(table_name = clubs)]
     struct NewClub {
         username: String,
         email: String,
-        password_hash: String,
         club_name: String,
         description: String,
         about: String,
@@ -75,13 +119,18 @@ async fn register(
         club_id: i32,
     }
 
-    let conn = &mut pool.get().await?;
+    #[derive(Insertable)]
+    #[diesel(table_name = users)]
+    struct NewUser {
+        username: String,
+        email: String,
+        password_hash: String,
+    }
 
     let new_club = diesel::insert_into(clubs::table)
         .values(NewClub {
-            username: req.username,
-            email: req.email,
-            password_hash: auth::hash_password(rand::random::<[u8; 32]>())?,
+            username: req.username.clone(),
+            email: req.email.clone(),
             club_name: req.name,
             description: req.description,
             about: "".to_string(),
@@ -107,8 +156,41 @@ async fn register(
         .execute(conn)
         .await?;
 
+    let president = diesel::insert_into(users::table)
+        .values(NewUser {
+            username: req.username,
+            email: req.email,
+            password_hash,
+        })
+        .on_conflict(users::username)
+        .do_nothing()
+        .get_result::<User>(conn)
+        .await
+        .optional()?;
+
+    let Some(president) = president else {
+        return Err(AppError::from(
+            StatusCode::CONFLICT,
+            "a user with that username already exists",
+        ));
+    };
+
+    diesel::insert_into(club_officers::table)
+        .values(ClubOfficer {
+            club_id: new_club.id,
+            user_id: president.id,
+            role: Role::President,
+        })
+        .execute(conn)
+        .await?;
+
+    Ok((new_club, president))
+}
+
+/// Emails the president of a new club a link to set their password.
+pub(super) async fn send_onboarding_email(state: &AppState, president: &User) -> AppResult<()> {
     let uid = nanoid!();
-    let link = format!("{}/password/{}", *FRONTEND_HOST, uid);
+    let link = password::reset_link(state, &uid);
     let body = format!(
         r#"Hi {},
 
@@ -116,14 +198,14 @@ Welcome to the CCA Club Hub! We are so excited to have you here. To finish setti
 
 {link}
 
-This link will expire in 7 days (or when the server restarts), so if you need a new link, just use the "Forgot your password?" link on the login page to create a new link.
+This link will expire in 7 days, so if you need a new link, just use the "Forgot your password?" link on the login page to create a new link.
 
 Thanks,
 The CCA Club Hub Team."#,
-        new_club.username, new_club.username,
+        president.username, president.username,
     );
 
-    let destination_address = new_club
+    let destination_address = president
         .email
         .parse::<Address>()
         .map_err(|_| AppError::from(StatusCode::BAD_REQUEST, "invalid email"))?;
@@ -131,28 +213,27 @@ The CCA Club Hub Team."#,
     let email = Message::builder()
         .from(Mailbox::new(
             Some("CCA Club Hub".to_string()),
-            EMAIL_ADDRESS.clone(),
+            state.mailer.address(),
         ))
         .to(Mailbox::new(
-            Some(new_club.username.clone()),
+            Some(president.username.clone()),
             destination_address,
         ))
         .subject("Welcome to the CCA Club Hub!")
         .body(body)
         .unwrap();
 
-    match email::send(email).await {
+    match state.mailer.send(email).await {
         Ok(_) => {
-            let mut resets = resets.lock().await;
-            // 7 days
-            resets.0.insert(
-                uid,
-                (
-                    Instant::now(),
-                    Duration::from_secs(60 * 60 * 24 * 7),
-                    new_club.id,
-                ),
-            );
+            state
+                .resets
+                .insert(
+                    &uid,
+                    president.id,
+                    ONBOARDING_ALLOWED_TIME,
+                    state.clock.now(),
+                )
+                .await?;
         }
         Err(_) => {
             return Err(AppError::from(
@@ -162,9 +243,426 @@ The CCA Club Hub Team."#,
         }
     }
 
-    Ok(Json(ClubRegisterResponse::from_club(&new_club)?))
+    Ok(())
+}
+
+/// Downloads a full backup of the database and assets, see [`backup`].
+async fn download_backup(
+    State(state): State<AppState>,
+    AdminOnly: AdminOnly,
+    client: Client,
+) -> AppResult<impl IntoResponse> {
+    let archive = backup::create(&state).await?;
+    audit::record(&state, &client, Entry::new(Actor::Admin, "backup.download")).await;
+    let file_name = format!(
+        "cca-club-hub-{}.tar",
+        state.clock.now().format("%Y-%m-%d-%H%M%S")
+    );
+
+    Ok((
+        [
+            (CONTENT_TYPE, "application/x-tar".to_string()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{file_name}\""),
+            ),
+        ],
+        archive,
+    ))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HashReport {
+    /// settings new hashes are made with
+    pub current: String,
+    pub total: usize,
+    /// users whose hash will be replaced the next time they log in
+    pub outdated: usize,
+    pub settings: Vec<HashSettingsCount>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HashSettingsCount {
+    /// e.g. `argon2id v=19 m=4096 t=3 p=1`, or `unknown` for hashes that can't be read
+    pub settings: String,
+    pub users: usize,
+    pub current: bool,
+}
+
+/// Counts the users whose password hash uses each set of argon2 settings.
+pub async fn hash_report(state: &AppState) -> AppResult<HashReport> {
+    let conn = &mut state.pool.get().await?;
+    let hashes = users::table
+        .select(users::password_hash)
+        .load::<String>(conn)
+        .await?;
+
+    let mut counts = BTreeMap::<Option<HashSettings>, usize>::new();
+    for hash in &hashes {
+        *counts.entry(HashSettings::of(hash)).or_default() += 1;
+    }
+
+    let current = state.hasher.settings();
+    let settings: Vec<_> = counts
+        .into_iter()
+        .map(|(settings, users)| HashSettingsCount {
+            current: settings.as_ref() == Some(current),
+            settings: settings.map_or("unknown".to_string(), |s| s.to_string()),
+            users,
+        })
+        .collect();
+
+    Ok(HashReport {
+        current: current.to_string(),
+        total: hashes.len(),
+        outdated: settings
+            .iter()
+            .filter(|s| !s.current)
+            .map(|s| s.users)
+            .sum(),
+        settings,
+    })
+}
+
+async fn password_hashes(
+    State(state): State<AppState>,
+    AdminOnly: AdminOnly,
+) -> AppResult<Json<HashReport>> {
+    Ok(Json(hash_report(&state).await?))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TwoFactorResetResponse {
+    username: String,
+    /// false if the user didn't have two-factor authentication to begin with
+    was_enabled: bool,
+}
+
+/// Turns off two-factor authentication for a user who is locked out.
+async fn reset_two_factor(
+    State(state): State<AppState>,
+    AdminOnly: AdminOnly,
+    client: Client,
+    Path(username): Path<String>,
+) -> AppResult<Json<TwoFactorResetResponse>> {
+    let was_enabled = two_factor::reset(&state, &username).await?;
+    audit::record(
+        &state,
+        &client,
+        Entry::new(Actor::Admin, "2fa.reset")
+            .target(&username)
+            .before(serde_json::json!({ "enabled": was_enabled }))
+            .after(serde_json::json!({ "enabled": false })),
+    )
+    .await;
+    Ok(Json(TwoFactorResetResponse {
+        username,
+        was_enabled,
+    }))
+}
+
+/// Starts a leadership transfer for a club whose president can't, e.g. because they
+/// already graduated.
+async fn nominate_president(
+    State(state): State<AppState>,
+    AdminOnly: AdminOnly,
+    client: Client,
+    Path(club): Path<String>,
+    Json(req): Json<transfer::NominateRequest>,
+) -> AppResult<Json<transfer::TransferResponse>> {
+    let transfer = transfer::nominate(&state, &club, req.email, None).await?;
+    audit::record(
+        &state,
+        &client,
+        Entry::new(Actor::Admin, "transfer.nominate")
+            .club_named(club)
+            .after(&transfer),
+    )
+    .await;
+    Ok(Json(transfer))
+}
+
+async fn cancel_transfer(
+    State(state): State<AppState>,
+    AdminOnly: AdminOnly,
+    client: Client,
+    Path(club): Path<String>,
+) -> AppResult<()> {
+    if !transfer::cancel(&state, &club).await? {
+        return Err(AppError::from(
+            StatusCode::NOT_FOUND,
+            "the club has no pending transfer",
+        ));
+    }
+    audit::record(
+        &state,
+        &client,
+        Entry::new(Actor::Admin, "transfer.cancel").club_named(club),
+    )
+    .await;
+    Ok(())
+}
+
+async fn transfer_history(
+    State(state): State<AppState>,
+    AdminOnly: AdminOnly,
+    Path(club): Path<String>,
+) -> AppResult<Json<Vec<transfer::TransferResponse>>> {
+    Ok(Json(transfer::history(&state, &club).await?))
+}
+
+#[derive(Deserialize)]
+struct ApplicationsQuery {
+    status: Option<ApplicationStatus>,
+}
+
+async fn list_applications(
+    State(state): State<AppState>,
+    AdminOnly: AdminOnly,
+    Query(query): Query<ApplicationsQuery>,
+) -> AppResult<Json<Vec<applications::ApplicationResponse>>> {
+    Ok(Json(applications::list(&state, query.status).await?))
+}
+
+#[derive(Deserialize)]
+struct ApproveRequest {
+    /// made from the club's name if not given
+    username: Option<String>,
+}
+
+async fn approve_application(
+    State(state): State<AppState>,
+    AdminOnly: AdminOnly,
+    client: Client,
+    Path(id): Path<i32>,
+    Json(req): Json<ApproveRequest>,
+) -> AppResult<Json<ClubRegisterResponse>> {
+    let club = applications::approve(&state, id, req.username).await?;
+    audit::record(
+        &state,
+        &client,
+        Entry::new(Actor::Admin, "application.approve")
+            .club(&club)
+            .target(format!("application {id}"))
+            .after(&club),
+    )
+    .await;
+    Ok(Json(ClubRegisterResponse::from_club(&club)?))
+}
+
+#[derive(Deserialize)]
+struct RejectRequest {
+    reason: String,
+}
+
+async fn reject_application(
+    State(state): State<AppState>,
+    AdminOnly: AdminOnly,
+    client: Client,
+    Path(id): Path<i32>,
+    Json(req): Json<RejectRequest>,
+) -> AppResult<Json<applications::ApplicationResponse>> {
+    let application = applications::reject(&state, id, req.reason).await?;
+    audit::record(
+        &state,
+        &client,
+        Entry::new(Actor::Admin, "application.reject")
+            .target(format!("application {id}"))
+            .after(&application),
+    )
+    .await;
+    Ok(Json(application))
+}
+
+#[derive(Deserialize)]
+struct DraftsQuery {
+    status: Option<DraftStatus>,
+}
+
+async fn list_drafts(
+    State(state): State<AppState>,
+    AdminOnly: AdminOnly,
+    Query(query): Query<DraftsQuery>,
+) -> AppResult<Json<Vec<drafts::DraftResponse>>> {
+    Ok(Json(drafts::list(&state, query.status).await?))
+}
+
+#[derive(Deserialize)]
+struct ReviewRequest {
+    comment: Option<String>,
+}
+
+async fn approve_draft(
+    State(state): State<AppState>,
+    AdminOnly: AdminOnly,
+    client: Client,
+    Path(id): Path<i32>,
+    Json(req): Json<ReviewRequest>,
+) -> AppResult<Json<drafts::DraftResponse>> {
+    let draft = drafts::approve(&state, id, req.comment).await?;
+    audit::record(
+        &state,
+        &client,
+        Entry::new(Actor::Admin, "draft.approve")
+            .club_named(&draft.club)
+            .target(format!("draft {id}"))
+            .after(&draft),
+    )
+    .await;
+    Ok(Json(draft))
+}
+
+async fn reject_draft(
+    State(state): State<AppState>,
+    AdminOnly: AdminOnly,
+    client: Client,
+    Path(id): Path<i32>,
+    Json(req): Json<ReviewRequest>,
+) -> AppResult<Json<drafts::DraftResponse>> {
+    let comment = req.comment.unwrap_or_default();
+    let draft = drafts::reject(&state, id, comment).await?;
+    audit::record(
+        &state,
+        &client,
+        Entry::new(Actor::Admin, "draft.reject")
+            .club_named(&draft.club)
+            .target(format!("draft {id}"))
+            .after(&draft),
+    )
+    .await;
+    Ok(Json(draft))
+}
+
+#[derive(Deserialize)]
+struct TrustRequest {
+    trusted: bool,
+}
+
+async fn trust_club(
+    State(state): State<AppState>,
+    AdminOnly: AdminOnly,
+    client: Client,
+    Path(club): Path<String>,
+    Json(req): Json<TrustRequest>,
+) -> AppResult<()> {
+    let club = drafts::set_trusted(&state, &club, req.trusted).await?;
+    audit::record(
+        &state,
+        &client,
+        Entry::new(Actor::Admin, "club.trust")
+            .club(&club)
+            .before(serde_json::json!({ "trusted": club.trusted }))
+            .after(serde_json::json!({ "trusted": req.trusted })),
+    )
+    .await;
+    Ok(())
+}
+
+/// What the audit log records of a club's place in its life.
+fn lifecycle_state(status: ClubStatus, deleted: bool) -> serde_json::Value {
+    serde_json::json!({ "status": status, "deleted": deleted })
+}
+
+async fn archive_club(
+    State(state): State<AppState>,
+    AdminOnly: AdminOnly,
+    client: Client,
+    Path(club): Path<String>,
+) -> AppResult<()> {
+    let club = lifecycle::set_status(&state, &club, ClubStatus::Archived).await?;
+    let deleted = club.deleted_at.is_some();
+    audit::record(
+        &state,
+        &client,
+        Entry::new(Actor::Admin, "club.archive")
+            .club(&club)
+            .before(lifecycle_state(club.status, deleted))
+            .after(lifecycle_state(ClubStatus::Archived, deleted)),
+    )
+    .await;
+    Ok(())
+}
+
+async fn restore_club(
+    State(state): State<AppState>,
+    AdminOnly: AdminOnly,
+    client: Client,
+    Path(club): Path<String>,
+) -> AppResult<()> {
+    let club = lifecycle::restore(&state, &club).await?;
+    audit::record(
+        &state,
+        &client,
+        Entry::new(Actor::Admin, "club.restore")
+            .club(&club)
+            .before(lifecycle_state(club.status, club.deleted_at.is_some()))
+            .after(lifecycle_state(ClubStatus::Active, false)),
+    )
+    .await;
+    Ok(())
+}
+
+async fn delete_club(
+    State(state): State<AppState>,
+    AdminOnly: AdminOnly,
+    client: Client,
+    Path(club): Path<String>,
+) -> AppResult<()> {
+    let club = lifecycle::delete(&state, &club).await?;
+    audit::record(
+        &state,
+        &client,
+        Entry::new(Actor::Admin, "club.delete")
+            .club(&club)
+            .before(lifecycle_state(club.status, false))
+            .after(lifecycle_state(club.status, true)),
+    )
+    .await;
+    Ok(())
+}
+
+/// Which clubs have confirmed their profile for the current school year.
+async fn reregistration_report(
+    State(state): State<AppState>,
+    AdminOnly: AdminOnly,
+) -> AppResult<Json<reregistration::ReregistrationReport>> {
+    let year = reregistration::SchoolYear::current(&state.config.reregistration, state.clock.now());
+    Ok(Json(reregistration::report(&state, &year).await?))
+}
+
+async fn audit_log(
+    State(state): State<AppState>,
+    AdminOnly: AdminOnly,
+    Query(query): Query<audit::AuditQuery>,
+) -> AppResult<Json<Vec<audit::AuditResponse>>> {
+    Ok(Json(audit::search(&state, query).await?))
 }
 
-pub fn app() -> Router {
-    Router::new().route("/register", post(register))
+pub fn app() -> Router<AppState> {
+    Router::new()
+        .route("/register", post(register))
+        .route("/backup", get(download_backup))
+        .route("/password-hashes", get(password_hashes))
+        .route("/2fa/:username", delete(reset_two_factor))
+        .route(
+            "/transfer/:club",
+            post(nominate_president).delete(cancel_transfer),
+        )
+        .route("/transfers/:club", get(transfer_history))
+        .route("/applications", get(list_applications))
+        .route("/applications/:id/approve", post(approve_application))
+        .route("/applications/:id/reject", post(reject_application))
+        .route("/drafts", get(list_drafts))
+        .route("/drafts/:id/approve", post(approve_draft))
+        .route("/drafts/:id/reject", post(reject_draft))
+        .route("/trust/:club", post(trust_club))
+        .route("/clubs/:club", delete(delete_club))
+        .route("/clubs/:club/archive", post(archive_club))
+        .route("/clubs/:club/restore", post(restore_club))
+        .route("/reregistration", get(reregistration_report))
+        .route("/audit", get(audit_log))
+        .nest("/directory", directory::app())
 }
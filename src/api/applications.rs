@@ -0,0 +1,292 @@
+use super::admin::{self, ClubRegisterRequest};
+use crate::{
+    error::{AppError, AppResult, FieldError},
+    models::{ApplicationStatus, Club, ClubApplication},
+    rate_limit::{self, ClientIp},
+    schema::*,
+    state::AppState,
+};
+use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+use chrono::{DateTime, Utc};
+use diesel::{insert_into, prelude::*, update};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use lettre::{message::Mailbox, Address, Message};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApplyRequest {
+    name: String,
+    description: String,
+    meet_time: String,
+    advisor: String,
+    email: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApplicationResponse {
+    pub id: i32,
+    pub club_name: String,
+    pub description: String,
+    pub meet_time: String,
+    pub advisor: String,
+    pub email: String,
+    pub status: ApplicationStatus,
+    pub submitted_at: DateTime<Utc>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    pub rejection_reason: Option<String>,
+    /// the username of the club registered for an approved application
+    pub club: Option<String>,
+}
+
+impl ApplicationResponse {
+    fn new(application: ClubApplication, club: Option<String>) -> Self {
+        Self {
+            id: application.id,
+            club_name: application.club_name,
+            description: application.description,
+            meet_time: application.meet_time,
+            advisor: application.advisor,
+            email: application.email,
+            status: application.status,
+            submitted_at: application.submitted_at,
+            reviewed_at: application.reviewed_at,
+            rejection_reason: application.rejection_reason,
+            club,
+        }
+    }
+}
+
+/// Lets anyone ask for their club to be listed. Nothing is public until an admin approves.
+async fn apply(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Json(req): Json<ApplyRequest>,
+) -> AppResult<Json<ApplicationResponse>> {
+    let limit = &state.config.rate_limit.club_application;
+    rate_limit::hit(&state, &format!("apply-ip:{ip}"), limit).await?;
+
+    let mut errors = Vec::new();
+    for (field, value) in [
+        ("name", &req.name),
+        ("description", &req.description),
+        ("advisor", &req.advisor),
+    ] {
+        if value.trim().is_empty() {
+            errors.push(FieldError {
+                field,
+                message: format!("{field} can't be empty"),
+            });
+        }
+    }
+    if req.email.parse::<Address>().is_err() {
+        errors.push(FieldError {
+            field: "email",
+            message: "invalid email".to_string(),
+        });
+    }
+    if !errors.is_empty() {
+        return Err(AppError::InvalidFields(errors));
+    }
+
+    let conn = &mut state.pool.get().await?;
+    let application = insert_into(club_applications::table)
+        .values((
+            club_applications::club_name.eq(req.name.trim()),
+            club_applications::description.eq(req.description.trim()),
+            club_applications::meet_time.eq(req.meet_time.trim()),
+            club_applications::advisor.eq(req.advisor.trim()),
+            club_applications::email.eq(req.email.trim()),
+            club_applications::status.eq(ApplicationStatus::Pending),
+            club_applications::submitted_at.eq(state.clock.now()),
+        ))
+        .get_result::<ClubApplication>(conn)
+        .await?;
+
+    Ok(Json(ApplicationResponse::new(application, None)))
+}
+
+/// Applications, oldest first, optionally only the ones with `status`.
+pub async fn list(
+    state: &AppState,
+    status: Option<ApplicationStatus>,
+) -> AppResult<Vec<ApplicationResponse>> {
+    let conn = &mut state.pool.get().await?;
+
+    let mut query = club_applications::table
+        .left_join(clubs::table)
+        .select((club_applications::all_columns, clubs::username.nullable()))
+        .order(club_applications::id)
+        .into_boxed();
+    if let Some(status) = status {
+        query = query.filter(club_applications::status.eq(status));
+    }
+
+    Ok(query
+        .load::<(ClubApplication, Option<String>)>(conn)
+        .await?
+        .into_iter()
+        .map(|(application, club)| ApplicationResponse::new(application, club))
+        .collect())
+}
+
+async fn find_pending(state: &AppState, id: i32) -> AppResult<ClubApplication> {
+    let conn = &mut state.pool.get().await?;
+    let application = club_applications::table
+        .find(id)
+        .first::<ClubApplication>(conn)
+        .await
+        .optional()?
+        .ok_or_else(|| AppError::from(StatusCode::NOT_FOUND, "the application does not exist"))?;
+
+    if application.status != ApplicationStatus::Pending {
+        return Err(AppError::from(
+            StatusCode::CONFLICT,
+            format!("the application has already been {}", application.status),
+        ));
+    }
+    Ok(application)
+}
+
+/// A username for a club called `name`, like `chess-club` for "Chess Club!".
+pub fn username_for(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Registers the club an application asks for, which emails its president the usual
+/// welcome. The username is made from the club's name unless one is given.
+pub async fn approve(state: &AppState, id: i32, username: Option<String>) -> AppResult<Club> {
+    let application = find_pending(state, id).await?;
+    let username = username.unwrap_or_else(|| username_for(&application.club_name));
+    if username.is_empty() {
+        return Err(AppError::from(
+            StatusCode::BAD_REQUEST,
+            "the club's name has no letters to make a username from, pick one",
+        ));
+    }
+
+    let registered = admin::register_club(
+        state,
+        ClubRegisterRequest {
+            username: username.clone(),
+            email: application.email.clone(),
+            name: application.club_name,
+            description: application.description,
+            meet_time: application.meet_time,
+        },
+    )
+    .await;
+
+    let conn = &mut state.pool.get().await?;
+    let club = match registered {
+        Ok(club) => club,
+        Err(e) => {
+            // the club is registered before the onboarding email goes out, so a failed
+            // email still approves the application; the admin can send a reset link
+            let club = clubs::table
+                .filter(clubs::username.eq(&username))
+                .filter(clubs::email.eq(&application.email))
+                .first::<Club>(conn)
+                .await
+                .optional()?;
+            if let Some(club) = club {
+                mark_approved(state, conn, id, club.id).await?;
+            }
+            return Err(e);
+        }
+    };
+    mark_approved(state, conn, id, club.id).await?;
+
+    Ok(club)
+}
+
+async fn mark_approved(
+    state: &AppState,
+    conn: &mut AsyncPgConnection,
+    id: i32,
+    club_id: i32,
+) -> AppResult<()> {
+    update(club_applications::table.find(id))
+        .set((
+            club_applications::status.eq(ApplicationStatus::Approved),
+            club_applications::reviewed_at.eq(state.clock.now()),
+            club_applications::club_id.eq(club_id),
+        ))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Turns an application down, emailing the applicant `reason`.
+pub async fn reject(state: &AppState, id: i32, reason: String) -> AppResult<ApplicationResponse> {
+    let application = find_pending(state, id).await?;
+    if reason.trim().is_empty() {
+        return Err(AppError::InvalidFields(vec![FieldError {
+            field: "reason",
+            message: "reason can't be empty".to_string(),
+        }]));
+    }
+
+    send_rejection_email(state, &application, &reason)
+        .await
+        .map_err(|e| {
+            eprintln!("failed to send application rejection email: {e}");
+            AppError::from(StatusCode::INTERNAL_SERVER_ERROR, "failed to send email")
+        })?;
+
+    let conn = &mut state.pool.get().await?;
+    let application = update(club_applications::table.find(id))
+        .set((
+            club_applications::status.eq(ApplicationStatus::Rejected),
+            club_applications::reviewed_at.eq(state.clock.now()),
+            club_applications::rejection_reason.eq(reason),
+        ))
+        .get_result::<ClubApplication>(conn)
+        .await?;
+
+    Ok(ApplicationResponse::new(application, None))
+}
+
+async fn send_rejection_email(
+    state: &AppState,
+    application: &ClubApplication,
+    reason: &str,
+) -> anyhow::Result<()> {
+    let body = format!(
+        r"Hi,
+
+Thank you for applying to list {} on the CCA Club Hub. Unfortunately we couldn't approve it, for this reason:
+
+{reason}
+
+You're welcome to apply again once that is sorted out.
+
+Thanks,
+The CCA Club Hub Team.",
+        application.club_name
+    );
+
+    let email = Message::builder()
+        .from(Mailbox::new(
+            Some("CCA Club Hub".to_string()),
+            state.mailer.address(),
+        ))
+        .to(Mailbox::new(None, application.email.parse::<Address>()?))
+        .subject(format!(
+            "Your application for {} on the CCA Club Hub",
+            application.club_name
+        ))
+        .body(body)?;
+
+    state.mailer.send(email).await
+}
+
+/// Routes next to [`super::club::app`].
+pub fn app() -> Router<AppState> {
+    Router::new().route("/apply", post(apply))
+}
@@ -1,60 +1,222 @@
+use super::two_factor;
 use crate::{
-    auth,
+    audit::{self, Actor, Client, Entry},
+    auth::{self, Auth},
     error::{AppError, AppResult},
-    models::Club,
-    DbPool,
+    models::User,
+    rate_limit::{self, ClientIp},
+    schema::*,
+    state::AppState,
 };
-use axum::{http::StatusCode, routing::post, Extension, Json, Router};
-use diesel::prelude::*;
-use diesel_async::RunQueryDsl;
+use axum::{
+    extract::State,
+    http::{header::CACHE_CONTROL, StatusCode},
+    response::IntoResponse,
+    routing::post,
+    Json, Router,
+};
+use diesel::{prelude::*, update};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use lettre::{message::Mailbox, Address, Message};
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
 #[derive(Deserialize)]
-struct ClubLoginRequest {
+struct LoginRequest {
     pub username: String,
     pub password: String,
 }
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
-struct ClubAuthorizedResponse {
+pub(crate) struct AuthorizedResponse {
     pub token: String,
 }
 
-impl ClubAuthorizedResponse {
-    fn from_club(club: &Club) -> anyhow::Result<ClubAuthorizedResponse> {
+impl AuthorizedResponse {
+    /// A token for `user` listing the clubs they are an officer of.
+    pub(crate) async fn for_user(
+        state: &AppState,
+        conn: &mut AsyncPgConnection,
+        user: &User,
+    ) -> anyhow::Result<AuthorizedResponse> {
+        let clubs = auth::officer_clubs(conn, user.id).await?;
         // expires after one day
-        Ok(ClubAuthorizedResponse {
-            token: auth::generate_jwt(club, Duration::from_secs(24 * 60 * 60))?,
+        Ok(AuthorizedResponse {
+            token: auth::generate_jwt(
+                &state.keys,
+                state.clock.as_ref(),
+                user,
+                clubs,
+                Duration::from_secs(24 * 60 * 60),
+            )?,
         })
     }
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TwoFactorChallengeResponse {
+    two_factor_required: bool,
+    /// sent to `/api/auth/2fa/login` with a code
+    challenge: String,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum LoginResponse {
+    Authorized(AuthorizedResponse),
+    TwoFactorRequired(TwoFactorChallengeResponse),
+}
+
 async fn login(
-    Extension(pool): Extension<DbPool>,
-    Json(req): Json<ClubLoginRequest>,
-) -> AppResult<Json<ClubAuthorizedResponse>> {
-    use crate::schema::clubs::dsl::*;
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    client: Client,
+    Json(req): Json<LoginRequest>,
+) -> AppResult<Json<LoginResponse>> {
+    let limit = &state.config.rate_limit.login;
+    rate_limit::hit(&state, &format!("login-ip:{ip}"), limit).await?;
+    rate_limit::hit(&state, &format!("login:{}", req.username), limit).await?;
+    rate_limit::check_lockout(&state, &req.username).await?;
 
-    let conn = &mut pool.get().await?;
+    let conn = &mut state.pool.get().await?;
 
-    if let Some(club) = clubs
-        .filter(username.eq(req.username))
-        .first::<Club>(conn)
+    let user = users::table
+        .filter(users::username.eq(&req.username))
+        .first::<User>(conn)
         .await
-        .optional()?
-    {
-        if auth::verify_password(req.password, &club.password_hash)? {
-            return Ok(Json(ClubAuthorizedResponse::from_club(&club)?));
+        .optional()?;
+    // unknown usernames still pay for a verification, so timing doesn't reveal which exist
+    let hash = user.as_ref().map(|user| user.password_hash.as_str());
+    if state.hasher.verify_or_dummy(&req.password, hash)? {
+        if let Some(user) = user {
+            auth::check_clubs_open(conn, user.id).await?;
+            // upgrade hashes made with older settings while the password is at hand
+            if state.hasher.needs_rehash(&user.password_hash) {
+                let rehashed = update(users::table.find(user.id))
+                    .set(users::password_hash.eq(state.hasher.hash(&req.password)?))
+                    .execute(conn)
+                    .await;
+                if let Err(e) = rehashed {
+                    eprintln!("failed to re-hash the password of {}: {e}", user.username);
+                }
+            }
+            // failed logins are only forgotten once the code is right too
+            if two_factor::is_enabled(conn, user.id).await? {
+                return Ok(Json(LoginResponse::TwoFactorRequired(
+                    TwoFactorChallengeResponse {
+                        two_factor_required: true,
+                        challenge: two_factor::challenge(&state, &user)?,
+                    },
+                )));
+            }
+            rate_limit::clear_failed_logins(&state, &req.username).await?;
+            let response = AuthorizedResponse::for_user(&state, conn, &user).await?;
+            audit::record(&state, &client, Entry::new(&user, "auth.login")).await;
+            return Ok(Json(LoginResponse::Authorized(response)));
         }
     }
+
+    rate_limit::record_failed_login(&state, &req.username).await?;
+    audit::record(
+        &state,
+        &client,
+        Entry::new(Actor::Anonymous(req.username), "auth.login_failed"),
+    )
+    .await;
     Err(AppError::from(
         StatusCode::UNAUTHORIZED,
         "invalid username or password",
     ))
 }
 
-pub fn app() -> Router {
-    Router::new().route("/login", post(login))
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChangePasswordRequest {
+    current_password: String,
+    new_password: String,
+}
+
+async fn change_password(
+    State(state): State<AppState>,
+    Auth(auth): Auth,
+    client: Client,
+    Json(req): Json<ChangePasswordRequest>,
+) -> AppResult<()> {
+    // a stolen token shouldn't make guessing the current password any easier than logging in
+    let limit = &state.config.rate_limit.login;
+    rate_limit::hit(&state, &format!("password-change:{}", auth.user_id), limit).await?;
+
+    let conn = &mut state.pool.get().await?;
+    let user = users::table.find(auth.user_id).first::<User>(conn).await?;
+
+    if !auth::verify_password(&req.current_password, &user.password_hash)? {
+        audit::record(&state, &client, Entry::new(&auth, "password.change_failed")).await;
+        return Err(AppError::from(
+            StatusCode::UNAUTHORIZED,
+            "incorrect password",
+        ));
+    }
+    auth::check_new_password(&state, conn, "newPassword", &req.new_password, &user).await?;
+
+    update(users::table.find(user.id))
+        .set(users::password_hash.eq(state.hasher.hash(&req.new_password)?))
+        .execute(conn)
+        .await?;
+    // a reset link sent before the change could otherwise undo it
+    state.resets.remove_for_user(user.id).await?;
+    audit::record(&state, &client, Entry::new(&auth, "password.change")).await;
+
+    let sender = state.clone();
+    state.outbox.send("password changed", async move {
+        send_password_changed_email(&sender, user).await
+    });
+
+    Ok(())
+}
+
+/// Tells a user their password was changed, in case it wasn't them.
+async fn send_password_changed_email(state: &AppState, user: User) -> anyhow::Result<()> {
+    let body = format!(
+        r"Hi {},
+
+The password of your CCA Club Hub account was just changed.
+
+If this wasn't you, please reset your password right away and let us know.
+
+Thanks,
+The CCA Club Hub Team.",
+        user.username
+    );
+
+    let email = Message::builder()
+        .from(Mailbox::new(
+            Some("CCA Club Hub".to_string()),
+            state.mailer.address(),
+        ))
+        .to(Mailbox::new(
+            Some(user.username),
+            user.email.parse::<Address>()?,
+        ))
+        .subject("Your CCA Club Hub password was changed")
+        .body(body)?;
+
+    state.mailer.send(email).await
+}
+
+/// The public keys our tokens can be verified with. Shared secrets aren't listed.
+pub async fn jwks(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        // 5 minutes, so new keys are picked up well before they sign anything
+        [(CACHE_CONTROL, "public, max-age=300")],
+        Json(state.keys.jwks(state.clock.now())),
+    )
+}
+
+pub fn app() -> Router<AppState> {
+    Router::new()
+        .route("/login", post(login))
+        .route("/password", post(change_password))
+        .nest("/2fa", two_factor::app())
 }
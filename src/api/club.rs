@@ -1,22 +1,30 @@
 use crate::{
+    auth::AdminOnly,
     error::{AppError, AppResult},
-    models::{Category, Club, ClubCategory, ClubSocial},
+    models::{Category, Club, ClubCategory, ClubSocial, ClubStatus},
     schema::*,
-    DbPool,
+    state::AppState,
 };
-use axum::{extract::Path, http::StatusCode, routing::get, Extension, Json, Router};
-use diesel::prelude::*;
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path, Query, State},
+    http::{request::Parts, StatusCode},
+    routing::get,
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use diesel::{dsl, prelude::*};
 use diesel_async::{pg::AsyncPgConnection, RunQueryDsl};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
-struct ClubSocialResponse {
-    email: String,
-    website: Option<String>,
-    google_classroom: Option<String>,
-    discord: Option<String>,
-    instagram: Option<String>,
+pub struct ClubSocialResponse {
+    pub email: String,
+    pub website: Option<String>,
+    pub google_classroom: Option<String>,
+    pub discord: Option<String>,
+    pub instagram: Option<String>,
 }
 
 impl ClubSocialResponse {
@@ -43,20 +51,66 @@ impl ClubSocialResponse {
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
-struct ClubResponse {
-    id: String,
-    email: String,
-    club_name: String,
-    description: String,
-    about: String,
-    meet_time: String,
-    profile_picture_url: String,
-    featured: bool,
-    categories: Vec<String>,
-    socials: ClubSocialResponse,
+pub struct ClubResponse {
+    pub id: String,
+    pub email: String,
+    pub club_name: String,
+    pub description: String,
+    pub about: String,
+    pub meet_time: String,
+    pub profile_picture_url: String,
+    pub featured: bool,
+    pub categories: Vec<String>,
+    pub socials: ClubSocialResponse,
+    pub status: ClubStatus,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    /// when the profile was last changed
+    pub updated_at: DateTime<Utc>,
+}
+
+pub type Listed = dsl::And<dsl::Eq<clubs::status, ClubStatus>, dsl::IsNull<clubs::deleted_at>>;
+
+/// Clubs shown to the public: active ones that haven't been deleted.
+pub fn listed() -> Listed {
+    clubs::status
+        .eq(ClubStatus::Active)
+        .and(clubs::deleted_at.is_null())
+}
+
+#[derive(Deserialize)]
+struct VisibilityQuery {
+    #[serde(default)]
+    all: bool,
+}
+
+/// Whether to show every club, which admins ask for with `?all=true`, or only the
+/// [`listed`] ones.
+struct Visibility {
+    all: bool,
 }
 
-async fn load_clubs(
+#[async_trait]
+impl FromRequestParts<AppState> for Visibility {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let Query(query) = Query::<VisibilityQuery>::from_request_parts(parts, state)
+            .await
+            .map_err(|e| AppError::from(StatusCode::BAD_REQUEST, e.to_string()))?;
+        // only checked when asked for, so logged in officers aren't taken for wrong admin keys
+        if query.all {
+            AdminOnly::from_request_parts(parts, state).await?;
+        }
+        Ok(Visibility { all: query.all })
+    }
+}
+
+/// Loads the categories of `clubs` and turns them into responses.
+pub async fn load_clubs(
     conn: &mut AsyncPgConnection,
     clubs: Vec<(Club, Option<ClubSocial>)>,
 ) -> AppResult<Vec<ClubResponse>> {
@@ -81,42 +135,62 @@ async fn load_clubs(
             featured: club.featured,
             categories: categories.into_iter().map(|c| c.1.category_name).collect(),
             socials: ClubSocialResponse::from(club.email, socials),
+            status: club.status,
+            deleted_at: club.deleted_at,
+            created_at: club.created_at,
+            updated_at: club.updated_at,
         })
         .collect())
 }
 
-async fn list(Extension(pool): Extension<DbPool>) -> AppResult<Json<Vec<ClubResponse>>> {
-    let conn = &mut pool.get().await?;
+async fn list(
+    State(state): State<AppState>,
+    visibility: Visibility,
+) -> AppResult<Json<Vec<ClubResponse>>> {
+    let conn = &mut state.pool.get().await?;
 
-    let clubs = clubs::table
-        .left_join(club_socials::table)
-        .load(conn)
-        .await?;
+    let mut clubs = clubs::table.left_join(club_socials::table).into_boxed();
+    if !visibility.all {
+        clubs = clubs.filter(listed());
+    }
+    let clubs = clubs.load(conn).await?;
 
     Ok(Json(load_clubs(conn, clubs).await?))
 }
 
-async fn list_featured(Extension(pool): Extension<DbPool>) -> AppResult<Json<Vec<ClubResponse>>> {
-    let conn = &mut pool.get().await?;
+async fn list_featured(
+    State(state): State<AppState>,
+    visibility: Visibility,
+) -> AppResult<Json<Vec<ClubResponse>>> {
+    let conn = &mut state.pool.get().await?;
 
-    let clubs = clubs::table
+    let mut clubs = clubs::table
         .left_join(club_socials::table)
         .filter(clubs::featured.eq(true))
-        .load(conn)
-        .await?;
+        .into_boxed();
+    if !visibility.all {
+        clubs = clubs.filter(listed());
+    }
+    let clubs = clubs.load(conn).await?;
 
     Ok(Json(load_clubs(conn, clubs).await?))
 }
 
 async fn info(
-    Extension(pool): Extension<DbPool>,
+    State(state): State<AppState>,
+    visibility: Visibility,
     Path(club_id): Path<String>,
 ) -> AppResult<Json<ClubResponse>> {
-    let conn = &mut pool.get().await?;
+    let conn = &mut state.pool.get().await?;
 
-    let club = clubs::table
+    let mut club = clubs::table
         .left_join(club_socials::table)
         .filter(clubs::username.eq(club_id))
+        .into_boxed();
+    if !visibility.all {
+        club = club.filter(listed());
+    }
+    let club = club
         .first(conn)
         .await
         .optional()?
@@ -127,8 +201,8 @@ async fn info(
     )?))
 }
 
-async fn list_categories(Extension(pool): Extension<DbPool>) -> AppResult<Json<Vec<String>>> {
-    let conn = &mut pool.get().await?;
+async fn list_categories(State(state): State<AppState>) -> AppResult<Json<Vec<String>>> {
+    let conn = &mut state.pool.get().await?;
 
     Ok(Json(
         categories::table
@@ -140,7 +214,7 @@ async fn list_categories(Extension(pool): Extension<DbPool>) -> AppResult<Json<V
     ))
 }
 
-pub fn app() -> Router {
+pub fn app() -> Router<AppState> {
     Router::new()
         .route("/list", get(list))
         .route("/list/featured", get(list_featured))
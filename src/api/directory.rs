@@ -0,0 +1,651 @@
+use super::{
+    admin::{self, ClubRegisterRequest},
+    edit,
+    revisions::{self, RevisionInfo},
+};
+use crate::{
+    audit::{self, Actor, Client, Entry},
+    auth::AdminOnly,
+    error::{AppError, AppResult},
+    models::{Category, Club, ClubCategory, ClubSocial},
+    schema::*,
+    state::AppState,
+};
+use anyhow::anyhow;
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+        StatusCode,
+    },
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use diesel::{delete, insert_into, prelude::*, update, AsChangeset};
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use lettre::Address;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    sync::Arc,
+};
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    format: Format,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ImportQuery {
+    #[serde(default)]
+    format: Format,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// One club in the directory spreadsheet. Optional columns that are missing or empty
+/// are left untouched on import.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DirectoryRow {
+    pub username: String,
+    pub email: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub meet_time: Option<String>,
+    pub about: Option<String>,
+    pub featured: Option<bool>,
+    /// category names separated by `;`
+    pub categories: Option<String>,
+    pub website: Option<String>,
+    pub google_classroom: Option<String>,
+    pub discord: Option<String>,
+    pub instagram: Option<String>,
+}
+
+impl DirectoryRow {
+    fn category_set(&self) -> Option<BTreeSet<&str>> {
+        self.categories.as_ref().map(|categories| {
+            categories
+                .split(';')
+                .map(str::trim)
+                .filter(|c| !c.is_empty())
+                .collect()
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct SocialRow {
+    username: String,
+    website: Option<String>,
+    google_classroom: Option<String>,
+    discord: Option<String>,
+    instagram: Option<String>,
+}
+
+#[derive(Serialize)]
+struct CategoryRow {
+    category: String,
+    /// usernames of the clubs in the category, separated by `;`
+    clubs: String,
+}
+
+#[derive(Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ImportReport {
+    applied: bool,
+    new: Vec<String>,
+    changed: Vec<ChangedClub>,
+    unchanged: Vec<String>,
+    errors: Vec<RowError>,
+    /// rows that couldn't be applied, the others are applied anyway
+    failed: Vec<RowError>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ChangedClub {
+    username: String,
+    fields: Vec<&'static str>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RowError {
+    /// 1-based index of the row, not counting the csv header
+    row: usize,
+    field: Option<&'static str>,
+    message: String,
+}
+
+/// Loads every club as a complete directory row, ordered by username.
+pub async fn load_directory(conn: &mut AsyncPgConnection) -> AppResult<Vec<DirectoryRow>> {
+    let clubs = clubs::table
+        .left_join(club_socials::table)
+        .order(clubs::username)
+        .load::<(Club, Option<ClubSocial>)>(conn)
+        .await?;
+    let categories = club_categories::table
+        .inner_join(categories::table)
+        .filter(club_categories::club_id.eq_any(clubs.iter().map(|c| c.0.id)))
+        .load::<(ClubCategory, Category)>(conn)
+        .await?
+        .grouped_by(&clubs.iter().map(|c| &c.0).collect::<Vec<_>>());
+
+    Ok(clubs
+        .into_iter()
+        .zip(categories)
+        .map(|((club, socials), categories)| {
+            let socials = socials.unwrap_or(ClubSocial {
+                id: 0,
+                club_id: club.id,
+                website: None,
+                google_classroom: None,
+                discord: None,
+                instagram: None,
+            });
+            DirectoryRow {
+                username: club.username,
+                email: club.email,
+                name: club.club_name,
+                description: Some(club.description),
+                meet_time: Some(club.meet_time),
+                about: Some(club.about),
+                featured: Some(club.featured),
+                categories: Some(
+                    categories
+                        .into_iter()
+                        .map(|c| c.1.category_name)
+                        .collect::<BTreeSet<_>>()
+                        .into_iter()
+                        .collect::<Vec<_>>()
+                        .join(";"),
+                ),
+                website: socials.website,
+                google_classroom: socials.google_classroom,
+                discord: socials.discord,
+                instagram: socials.instagram,
+            }
+        })
+        .collect())
+}
+
+async fn export(
+    State(state): State<AppState>,
+    AdminOnly: AdminOnly,
+    Path(kind): Path<String>,
+    Query(query): Query<ExportQuery>,
+) -> AppResult<Response> {
+    let conn = &mut state.pool.get().await?;
+
+    match kind.as_str() {
+        "clubs" => respond(query.format, "clubs", load_directory(conn).await?),
+        "socials" => respond(
+            query.format,
+            "socials",
+            load_directory(conn)
+                .await?
+                .into_iter()
+                .map(|row| SocialRow {
+                    username: row.username,
+                    website: row.website,
+                    google_classroom: row.google_classroom,
+                    discord: row.discord,
+                    instagram: row.instagram,
+                })
+                .collect(),
+        ),
+        "categories" => {
+            let categories = categories::table
+                .order(categories::category_name)
+                .load::<Category>(conn)
+                .await?;
+            let members = club_categories::table
+                .inner_join(clubs::table)
+                .select((club_categories::category_id, clubs::username))
+                .order(clubs::username)
+                .load::<(i32, String)>(conn)
+                .await?;
+
+            let rows = categories
+                .into_iter()
+                .map(|category| CategoryRow {
+                    clubs: members
+                        .iter()
+                        .filter(|(id, _)| *id == category.id)
+                        .map(|(_, username)| username.as_str())
+                        .collect::<Vec<_>>()
+                        .join(";"),
+                    category: category.category_name,
+                })
+                .collect();
+            respond(query.format, "categories", rows)
+        }
+        _ => Err(AppError::from(
+            StatusCode::NOT_FOUND,
+            "can only export clubs, socials or categories",
+        )),
+    }
+}
+
+fn respond<T: Serialize>(format: Format, name: &str, rows: Vec<T>) -> AppResult<Response> {
+    match format {
+        Format::Json => Ok(Json(rows).into_response()),
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            for row in rows {
+                writer.serialize(row)?;
+            }
+            let body = writer.into_inner().map_err(|e| anyhow!("{e}"))?;
+
+            Ok((
+                [
+                    (CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+                    (
+                        CONTENT_DISPOSITION,
+                        format!("attachment; filename=\"{name}.csv\""),
+                    ),
+                ],
+                body,
+            )
+                .into_response())
+        }
+    }
+}
+
+/// Compares an imported row against the current directory. Returns the names of the
+/// fields that would change.
+fn changed_fields(current: &DirectoryRow, new: &DirectoryRow) -> Vec<&'static str> {
+    fn differs<T: PartialEq>(current: &Option<T>, new: &Option<T>) -> bool {
+        new.is_some() && new != current
+    }
+
+    let mut fields = Vec::new();
+    if current.email != new.email {
+        fields.push("email");
+    }
+    if current.name != new.name {
+        fields.push("name");
+    }
+    for (field, changed) in [
+        (
+            "description",
+            differs(&current.description, &new.description),
+        ),
+        ("meet_time", differs(&current.meet_time, &new.meet_time)),
+        ("about", differs(&current.about, &new.about)),
+        ("featured", differs(&current.featured, &new.featured)),
+        (
+            "categories",
+            differs(&current.category_set(), &new.category_set()),
+        ),
+        ("website", differs(&current.website, &new.website)),
+        (
+            "google_classroom",
+            differs(&current.google_classroom, &new.google_classroom),
+        ),
+        ("discord", differs(&current.discord, &new.discord)),
+        ("instagram", differs(&current.instagram, &new.instagram)),
+    ] {
+        if changed {
+            fields.push(field);
+        }
+    }
+    fields
+}
+
+fn validate_row(
+    index: usize,
+    row: &DirectoryRow,
+    seen: &mut HashSet<String>,
+    category_ids: &HashMap<String, i32>,
+    errors: &mut Vec<RowError>,
+) {
+    let mut error = |field: &'static str, message: String| {
+        errors.push(RowError {
+            row: index,
+            field: Some(field),
+            message,
+        })
+    };
+
+    if row.username.is_empty() {
+        error("username", "username is required".to_string());
+    } else if !seen.insert(row.username.clone()) {
+        error(
+            "username",
+            format!("{} appears more than once", row.username),
+        );
+    }
+    if row.email.parse::<Address>().is_err() {
+        error("email", format!("{:?} is not a valid email", row.email));
+    }
+    if row.name.is_empty() {
+        error("name", "name is required".to_string());
+    }
+    for category in row.category_set().unwrap_or_default() {
+        if !category_ids.contains_key(category) {
+            error("categories", format!("unknown category {category:?}"));
+        }
+    }
+
+    let none = &None;
+    for (field, result) in [
+        (
+            "website",
+            edit::validate_socials(&row.website, none, none, none),
+        ),
+        (
+            "google_classroom",
+            edit::validate_socials(none, &row.google_classroom, none, none),
+        ),
+        (
+            "discord",
+            edit::validate_socials(none, none, &row.discord, none),
+        ),
+        (
+            "instagram",
+            edit::validate_socials(none, none, none, &row.instagram),
+        ),
+    ] {
+        if let Err(e) = result {
+            error(field, e.to_string());
+        }
+    }
+}
+
+fn parse_rows(format: Format, body: &[u8]) -> Result<Vec<DirectoryRow>, Vec<RowError>> {
+    match format {
+        Format::Json => serde_json::from_slice(body).map_err(|e| {
+            vec![RowError {
+                row: 0,
+                field: None,
+                message: format!("invalid json: {e}"),
+            }]
+        }),
+        Format::Csv => {
+            let mut rows = Vec::new();
+            let mut errors = Vec::new();
+            for (index, row) in csv::Reader::from_reader(body).deserialize().enumerate() {
+                match row {
+                    Ok(row) => rows.push(row),
+                    Err(e) => errors.push(RowError {
+                        row: index + 1,
+                        field: None,
+                        message: e.to_string(),
+                    }),
+                }
+            }
+            if errors.is_empty() {
+                Ok(rows)
+            } else {
+                Err(errors)
+            }
+        }
+    }
+}
+
+async fn import(
+    State(state): State<AppState>,
+    AdminOnly: AdminOnly,
+    client: Client,
+    Query(query): Query<ImportQuery>,
+    body: Bytes,
+) -> AppResult<(StatusCode, Json<ImportReport>)> {
+    let rows = match parse_rows(query.format, &body) {
+        Ok(rows) => rows,
+        Err(errors) => {
+            return Ok((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(ImportReport {
+                    errors,
+                    ..Default::default()
+                }),
+            ))
+        }
+    };
+
+    let conn = &mut state.pool.get().await?;
+    let category_ids: HashMap<String, i32> = categories::table
+        .load::<Category>(conn)
+        .await?
+        .into_iter()
+        .map(|c| (c.category_name, c.id))
+        .collect();
+    let club_ids: HashMap<String, i32> = clubs::table
+        .select((clubs::username, clubs::id))
+        .load::<(String, i32)>(conn)
+        .await?
+        .into_iter()
+        .collect();
+    let current: HashMap<String, DirectoryRow> = load_directory(conn)
+        .await?
+        .into_iter()
+        .map(|row| (row.username.clone(), row))
+        .collect();
+
+    let mut report = ImportReport::default();
+    let mut seen = HashSet::new();
+    let mut new_rows = Vec::new();
+    let mut changed_rows = Vec::new();
+
+    for (index, row) in rows.into_iter().enumerate() {
+        let index = index + 1;
+        validate_row(index, &row, &mut seen, &category_ids, &mut report.errors);
+
+        match current.get(&row.username) {
+            None => {
+                report.new.push(row.username.clone());
+                new_rows.push((index, row));
+            }
+            Some(existing) => {
+                let fields = changed_fields(existing, &row);
+                if fields.is_empty() {
+                    report.unchanged.push(row.username);
+                } else {
+                    // the email and featured flag aren't part of the profile's history
+                    let edits_profile = fields
+                        .iter()
+                        .any(|field| !matches!(*field, "email" | "featured"));
+                    report.changed.push(ChangedClub {
+                        username: row.username.clone(),
+                        fields,
+                    });
+                    changed_rows.push((index, club_ids[&row.username], row, edits_profile));
+                }
+            }
+        }
+    }
+
+    if !report.errors.is_empty() {
+        return Ok((StatusCode::UNPROCESSABLE_ENTITY, Json(report)));
+    }
+    if query.dry_run {
+        return Ok((StatusCode::OK, Json(report)));
+    }
+
+    // each club is applied on its own, so one failing doesn't undo or stop the others
+    let mut failed = |row: usize, message: String| {
+        report.failed.push(RowError {
+            row,
+            field: None,
+            message,
+        })
+    };
+    let category_ids = Arc::new(category_ids);
+    let now = state.clock.now();
+    for (index, club_id, row, edits_profile) in changed_rows {
+        let ids = category_ids.clone();
+        let result = conn
+            .transaction::<_, AppError, _>(|conn| {
+                Box::pin(async move {
+                    if !edits_profile {
+                        return Ok(apply_row(conn, club_id, &row, &ids).await?);
+                    }
+                    // like a published edit, so the history still ends with the live profile
+                    revisions::latest(conn, club_id, now).await?;
+                    apply_row(conn, club_id, &row, &ids).await?;
+                    update(clubs::table.find(club_id))
+                        .set(clubs::updated_at.eq(now))
+                        .execute(conn)
+                        .await?;
+                    let revision = RevisionInfo {
+                        edited_by: None,
+                        reverted_from: None,
+                        at: now,
+                    };
+                    revisions::record(conn, club_id, revision).await?;
+                    Ok(())
+                })
+            })
+            .await;
+        if let Err(e) = result {
+            failed(index, e.to_string());
+        }
+    }
+
+    // new clubs are registered like `/admin/register`, onboarding email included
+    for (index, row) in new_rows {
+        let password_hash = state.hasher.hash(rand::random::<[u8; 32]>())?;
+        let request = ClubRegisterRequest {
+            username: row.username.clone(),
+            email: row.email.clone(),
+            name: row.name.clone(),
+            description: row.description.clone().unwrap_or_default(),
+            meet_time: row.meet_time.clone().unwrap_or_default(),
+        };
+        let (username, ids) = (row.username.clone(), category_ids.clone());
+        let result = conn
+            .transaction::<_, AppError, _>(|conn| {
+                Box::pin(async move {
+                    let (club, president) =
+                        admin::insert_club(conn, password_hash, request).await?;
+                    apply_row(conn, club.id, &row, &ids).await?;
+                    Ok(president)
+                })
+            })
+            .await;
+
+        match result {
+            Ok(president) => {
+                if let Err(e) = admin::send_onboarding_email(&state, &president).await {
+                    failed(
+                        index,
+                        format!(
+                            "{username} was registered, but its onboarding email wasn't sent: {e}"
+                        ),
+                    );
+                }
+            }
+            Err(e) => failed(index, e.to_string()),
+        }
+    }
+
+    report.applied = true;
+    audit::record(
+        &state,
+        &client,
+        Entry::new(Actor::Admin, "directory.import").after(&report),
+    )
+    .await;
+    Ok((StatusCode::OK, Json(report)))
+}
+
+/// Writes the columns present in `row` to the club with id `club_id`.
+async fn apply_row(
+    conn: &mut AsyncPgConnection,
+    club_id: i32,
+    row: &DirectoryRow,
+    category_ids: &HashMap<String, i32>,
+) -> QueryResult<()> {
+    #[derive(AsChangeset)]
+    #[diesel(table_name = clubs)]
+    struct ClubChanges<'a> {
+        email: &'a str,
+        club_name: &'a str,
+        description: Option<&'a str>,
+        meet_time: Option<&'a str>,
+        about: Option<&'a str>,
+        featured: Option<bool>,
+    }
+
+    #[derive(AsChangeset)]
+    #[diesel(table_name = club_socials)]
+    struct SocialChanges<'a> {
+        website: Option<&'a str>,
+        google_classroom: Option<&'a str>,
+        discord: Option<&'a str>,
+        instagram: Option<&'a str>,
+    }
+
+    update(clubs::table.find(club_id))
+        .set(ClubChanges {
+            email: &row.email,
+            club_name: &row.name,
+            description: row.description.as_deref(),
+            meet_time: row.meet_time.as_deref(),
+            about: row.about.as_deref(),
+            featured: row.featured,
+        })
+        .execute(conn)
+        .await?;
+
+    let socials = SocialChanges {
+        website: row.website.as_deref(),
+        google_classroom: row.google_classroom.as_deref(),
+        discord: row.discord.as_deref(),
+        instagram: row.instagram.as_deref(),
+    };
+    // an empty changeset is an error in diesel
+    if socials.website.is_some()
+        || socials.google_classroom.is_some()
+        || socials.discord.is_some()
+        || socials.instagram.is_some()
+    {
+        update(club_socials::table.filter(club_socials::club_id.eq(club_id)))
+            .set(socials)
+            .execute(conn)
+            .await?;
+    }
+
+    if let Some(categories) = row.category_set() {
+        delete(club_categories::table.filter(club_categories::club_id.eq(club_id)))
+            .execute(conn)
+            .await?;
+        insert_into(club_categories::table)
+            .values(
+                categories
+                    .into_iter()
+                    .map(|name| {
+                        (
+                            club_categories::club_id.eq(club_id),
+                            club_categories::category_id.eq(category_ids[name]),
+                        )
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .execute(conn)
+            .await?;
+    }
+
+    Ok(())
+}
+
+pub fn app() -> Router<AppState> {
+    Router::new()
+        .route("/export/:kind", get(export))
+        .route("/import", post(import))
+}
@@ -0,0 +1,304 @@
+//! Moderated profile edits. With `moderation.edits` on, edits of clubs that aren't
+//! trusted are kept as drafts and the public profile stays as it was until an admin
+//! approves them.
+
+use super::{
+    edit::{self, ClubEdit, ClubSocialRequest, ProfileEdit, SocialsResponse},
+    revisions::RevisionInfo,
+};
+use crate::{
+    auth::{self, Auth, Claims},
+    error::{AppError, AppResult, FieldError},
+    models::{Category, Club, ClubDraft, DraftStatus, Role},
+    schema::*,
+    state::AppState,
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use diesel::{insert_into, prelude::*, update};
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DraftResponse {
+    pub id: i32,
+    /// username of the club
+    pub club: String,
+    /// username of the officer who made the edit
+    pub submitted_by: Option<String>,
+    pub submitted_at: DateTime<Utc>,
+    pub club_name: String,
+    pub description: String,
+    pub about: String,
+    pub meet_time: String,
+    pub categories: Vec<String>,
+    pub socials: SocialsResponse,
+    pub status: DraftStatus,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    pub review_comment: Option<String>,
+}
+
+/// Adds the usernames and category names the responses show to `drafts`.
+async fn responses(
+    conn: &mut AsyncPgConnection,
+    drafts: Vec<ClubDraft>,
+) -> AppResult<Vec<DraftResponse>> {
+    let club_ids: Vec<i32> = drafts.iter().map(|draft| draft.club_id).collect();
+    let user_ids: Vec<i32> = drafts
+        .iter()
+        .filter_map(|draft| draft.submitted_by)
+        .collect();
+
+    let clubs: HashMap<i32, String> = clubs::table
+        .filter(clubs::id.eq_any(club_ids))
+        .select((clubs::id, clubs::username))
+        .load::<(i32, String)>(conn)
+        .await?
+        .into_iter()
+        .collect();
+    let users: HashMap<i32, String> = users::table
+        .filter(users::id.eq_any(user_ids))
+        .select((users::id, users::username))
+        .load::<(i32, String)>(conn)
+        .await?
+        .into_iter()
+        .collect();
+    let categories: HashMap<i32, String> = categories::table
+        .load::<Category>(conn)
+        .await?
+        .into_iter()
+        .map(|c| (c.id, c.category_name))
+        .collect();
+
+    Ok(drafts
+        .into_iter()
+        .map(|draft| DraftResponse {
+            id: draft.id,
+            club: clubs.get(&draft.club_id).cloned().unwrap_or_default(),
+            submitted_by: draft.submitted_by.and_then(|id| users.get(&id).cloned()),
+            submitted_at: draft.submitted_at,
+            club_name: draft.club_name,
+            description: draft.description,
+            about: draft.about,
+            meet_time: draft.meet_time,
+            // categories removed since the edit are left out
+            categories: draft
+                .category_ids
+                .iter()
+                .filter_map(|id| categories.get(id).cloned())
+                .collect(),
+            socials: SocialsResponse {
+                website: draft.website,
+                google_classroom: draft.google_classroom,
+                discord: draft.discord,
+                instagram: draft.instagram,
+            },
+            status: draft.status,
+            reviewed_at: draft.reviewed_at,
+            review_comment: draft.review_comment,
+        })
+        .collect())
+}
+
+/// Keeps `edit` for an admin to review, replacing the club's pending draft if it has one.
+pub(super) async fn submit(
+    state: &AppState,
+    conn: &mut AsyncPgConnection,
+    club: &Club,
+    auth: &Claims,
+    edit: ProfileEdit,
+) -> AppResult<DraftResponse> {
+    let club_id = club.id;
+    let user_id = auth.user_id;
+    let now = state.clock.now();
+
+    let draft = conn
+        .transaction::<_, AppError, _>(|conn| {
+            Box::pin(async move {
+                update(club_drafts::table)
+                    .filter(club_drafts::club_id.eq(club_id))
+                    .filter(club_drafts::status.eq(DraftStatus::Pending))
+                    .set(club_drafts::status.eq(DraftStatus::Superseded))
+                    .execute(conn)
+                    .await?;
+
+                Ok(insert_into(club_drafts::table)
+                    .values((
+                        club_drafts::club_id.eq(club_id),
+                        club_drafts::submitted_by.eq(user_id),
+                        club_drafts::submitted_at.eq(now),
+                        club_drafts::club_name.eq(edit.club.club_name),
+                        club_drafts::description.eq(edit.club.description),
+                        club_drafts::about.eq(edit.club.about),
+                        club_drafts::meet_time.eq(edit.club.meet_time),
+                        club_drafts::category_ids.eq(edit.category_ids),
+                        club_drafts::website.eq(edit.socials.website),
+                        club_drafts::google_classroom.eq(edit.socials.google_classroom),
+                        club_drafts::discord.eq(edit.socials.discord),
+                        club_drafts::instagram.eq(edit.socials.instagram),
+                        club_drafts::status.eq(DraftStatus::Pending),
+                    ))
+                    .get_result::<ClubDraft>(conn)
+                    .await?)
+            })
+        })
+        .await?;
+
+    Ok(responses(conn, vec![draft]).await?.remove(0))
+}
+
+/// The drafts of a club, newest first, so officers can see whether their edits went live.
+pub(super) async fn club_drafts(
+    State(state): State<AppState>,
+    Auth(auth): Auth,
+    Path(club): Path<String>,
+) -> AppResult<Json<Vec<DraftResponse>>> {
+    let conn = &mut state.pool.get().await?;
+    let (club, _) = auth::authorize(conn, &auth, &club, Role::Editor).await?;
+
+    let drafts = club_drafts::table
+        .filter(club_drafts::club_id.eq(club.id))
+        .order(club_drafts::id.desc())
+        .load::<ClubDraft>(conn)
+        .await?;
+
+    Ok(Json(responses(conn, drafts).await?))
+}
+
+/// Drafts of every club, oldest first, optionally only the ones with `status`.
+pub async fn list(state: &AppState, status: Option<DraftStatus>) -> AppResult<Vec<DraftResponse>> {
+    let conn = &mut state.pool.get().await?;
+
+    let mut query = club_drafts::table.order(club_drafts::id).into_boxed();
+    if let Some(status) = status {
+        query = query.filter(club_drafts::status.eq(status));
+    }
+    let drafts = query.load::<ClubDraft>(conn).await?;
+
+    responses(conn, drafts).await
+}
+
+/// Marks a pending draft as reviewed, or explains why it can't be.
+async fn review(
+    conn: &mut AsyncPgConnection,
+    id: i32,
+    status: DraftStatus,
+    comment: Option<String>,
+    now: DateTime<Utc>,
+) -> AppResult<ClubDraft> {
+    let draft = update(club_drafts::table.find(id))
+        .filter(club_drafts::status.eq(DraftStatus::Pending))
+        .set((
+            club_drafts::status.eq(status),
+            club_drafts::reviewed_at.eq(now),
+            club_drafts::review_comment.eq(comment),
+        ))
+        .get_result::<ClubDraft>(conn)
+        .await
+        .optional()?;
+    if let Some(draft) = draft {
+        return Ok(draft);
+    }
+
+    let status = club_drafts::table
+        .find(id)
+        .select(club_drafts::status)
+        .first::<DraftStatus>(conn)
+        .await
+        .optional()?
+        .ok_or_else(|| AppError::from(StatusCode::NOT_FOUND, "the draft does not exist"))?;
+    Err(AppError::from(
+        StatusCode::CONFLICT,
+        format!("the draft has already been {status}"),
+    ))
+}
+
+/// Publishes a pending draft, with an optional comment for the club.
+pub async fn approve(
+    state: &AppState,
+    id: i32,
+    comment: Option<String>,
+) -> AppResult<DraftResponse> {
+    let now = state.clock.now();
+    let comment = comment.filter(|comment| !comment.trim().is_empty());
+    let conn = &mut state.pool.get().await?;
+
+    let draft = conn
+        .transaction::<_, AppError, _>(|conn| {
+            Box::pin(async move {
+                let draft = review(conn, id, DraftStatus::Approved, comment, now).await?;
+
+                let edit = ProfileEdit::restore(
+                    conn,
+                    ClubEdit {
+                        club_name: draft.club_name.clone(),
+                        description: draft.description.clone(),
+                        about: draft.about.clone(),
+                        meet_time: draft.meet_time.clone(),
+                    },
+                    &draft.category_ids,
+                    ClubSocialRequest {
+                        website: draft.website.clone(),
+                        google_classroom: draft.google_classroom.clone(),
+                        discord: draft.discord.clone(),
+                        instagram: draft.instagram.clone(),
+                    },
+                )
+                .await?;
+                let revision = RevisionInfo {
+                    edited_by: draft.submitted_by,
+                    reverted_from: None,
+                    at: now,
+                };
+                edit::publish(conn, draft.club_id, edit, revision).await?;
+
+                Ok(draft)
+            })
+        })
+        .await?;
+
+    Ok(responses(conn, vec![draft]).await?.remove(0))
+}
+
+/// Turns a pending draft down, telling the club why in `comment`. The public profile
+/// stays as it was.
+pub async fn reject(state: &AppState, id: i32, comment: String) -> AppResult<DraftResponse> {
+    if comment.trim().is_empty() {
+        return Err(AppError::InvalidFields(vec![FieldError {
+            field: "comment",
+            message: "comment can't be empty".to_string(),
+        }]));
+    }
+
+    let conn = &mut state.pool.get().await?;
+    let draft = review(
+        conn,
+        id,
+        DraftStatus::Rejected,
+        Some(comment),
+        state.clock.now(),
+    )
+    .await?;
+
+    Ok(responses(conn, vec![draft]).await?.remove(0))
+}
+
+/// Lets a club publish its edits without review, or takes that back. Returns the club
+/// as it was before.
+pub async fn set_trusted(state: &AppState, club: &str, trusted: bool) -> AppResult<Club> {
+    let conn = &mut state.pool.get().await?;
+    let club = auth::find_club(conn, club).await?;
+
+    update(clubs::table.find(club.id))
+        .set(clubs::trusted.eq(trusted))
+        .execute(conn)
+        .await?;
+
+    Ok(club)
+}
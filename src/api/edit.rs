@@ -1,30 +1,37 @@
-use super::DEFAULT_PROFILE_PICTURE_URL;
+use super::{
+    drafts,
+    revisions::{self, RevisionInfo},
+    DEFAULT_PROFILE_PICTURE_URL,
+};
 use crate::{
-    auth::Auth,
+    audit::{self, Client, Entry},
+    auth::{self, Auth, Claims},
     error::{AppError, AppResult},
-    models::{Category, ClubCategory},
+    models::{Category, Club, ClubCategory, ClubRevision, Role},
     schema::*,
-    DbPool,
+    state::AppState,
+    tasks::{self, Shutdown},
 };
 use axum::{
     body::Bytes,
+    extract::{Path, State},
     headers::ContentType,
     http::StatusCode,
-    routing::{post, put},
-    Extension, Json, Router, TypedHeader,
+    routing::{get, post, put},
+    Json, Router, TypedHeader,
 };
 use diesel::{delete, insert_into, prelude::*, update, AsChangeset, ExpressionMethods};
-use diesel_async::RunQueryDsl;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
 use itertools::Itertools;
 use mime::Mime;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use sha2::{Digest, Sha256};
 use std::{
     collections::{HashMap, HashSet},
     env::current_dir,
-    fs::{self, File},
-    io::Write,
     path::PathBuf,
+    time::Duration,
 };
 use url::{Host, Url};
 
@@ -33,11 +40,11 @@ use url::{Host, Url};
 #[diesel(table_name = club_socials)]
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct ClubSocialRequest {
-    website: Option<String>,
-    google_classroom: Option<String>,
-    discord: Option<String>,
-    instagram: Option<String>,
+pub(super) struct ClubSocialRequest {
+    pub(super) website: Option<String>,
+    pub(super) google_classroom: Option<String>,
+    pub(super) discord: Option<String>,
+    pub(super) instagram: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -54,11 +61,99 @@ struct ClubRequest {
 #[derive(AsChangeset)]
 #[diesel(treat_none_as_null = true)]
 #[diesel(table_name = clubs)]
-struct ClubEdit {
-    club_name: String,
-    description: String,
-    about: String,
-    meet_time: String,
+pub(super) struct ClubEdit {
+    pub(super) club_name: String,
+    pub(super) description: String,
+    pub(super) about: String,
+    pub(super) meet_time: String,
+}
+
+/// A checked edit of a club's profile, published right away or kept as a draft.
+pub(super) struct ProfileEdit {
+    pub(super) club: ClubEdit,
+    pub(super) category_ids: Vec<i32>,
+    pub(super) socials: ClubSocialRequest,
+}
+
+impl ProfileEdit {
+    async fn new(conn: &mut AsyncPgConnection, req: ClubRequest) -> AppResult<Self> {
+        let socials = req.socials;
+        validate_socials(
+            &socials.website,
+            &socials.google_classroom,
+            &socials.discord,
+            &socials.instagram,
+        )?;
+
+        let all_categories = HashMap::<_, _>::from_iter(
+            categories::table
+                .load::<Category>(conn)
+                .await?
+                .into_iter()
+                .map(|c| (c.category_name, c.id)),
+        );
+
+        let mut category_ids = Vec::new();
+        for category in req.categories.into_iter().collect::<HashSet<String>>() {
+            let Some(&id) = all_categories.get(&category) else {
+                return Err(AppError::from(StatusCode::BAD_REQUEST, "invalid category"));
+            };
+            category_ids.push(id);
+        }
+        category_ids.sort_unstable();
+
+        Ok(Self {
+            club: ClubEdit {
+                club_name: req.club_name,
+                description: req.description,
+                about: req.about,
+                meet_time: req.meet_time,
+            },
+            category_ids,
+            socials,
+        })
+    }
+}
+
+impl ProfileEdit {
+    /// An edit back to saved values, leaving out categories removed since they were saved.
+    pub(super) async fn restore(
+        conn: &mut AsyncPgConnection,
+        club: ClubEdit,
+        category_ids: &[i32],
+        socials: ClubSocialRequest,
+    ) -> AppResult<Self> {
+        let category_ids = categories::table
+            .filter(categories::id.eq_any(category_ids))
+            .select(categories::id)
+            .order(categories::id)
+            .load::<i32>(conn)
+            .await?;
+
+        Ok(Self {
+            club,
+            category_ids,
+            socials,
+        })
+    }
+}
+
+/// The socials of a saved version of a profile.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SocialsResponse {
+    pub website: Option<String>,
+    pub google_classroom: Option<String>,
+    pub discord: Option<String>,
+    pub instagram: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct EditResponse {
+    /// false when the edit is waiting for an admin as `draft`
+    published: bool,
+    draft: Option<drafts::DraftResponse>,
 }
 
 #[derive(Debug, Clone, Deserialize, Insertable)]
@@ -79,15 +174,19 @@ lazy_static::lazy_static! {
     static ref PFP_DIR: PathBuf = CWD.join("static/profile_pictures/");
 }
 
+/// Replaces the club's profile picture. Pictures aren't moderated, so this publishes
+/// right away even when `moderation.edits` is on.
 async fn upload_pfp(
-    Extension(pool): Extension<DbPool>,
+    State(state): State<AppState>,
+    Auth(auth): Auth,
+    client: Client,
+    Path(club): Path<String>,
     TypedHeader(content_type): TypedHeader<ContentType>,
     bytes: Bytes,
-    Auth(auth): Auth,
 ) -> AppResult<Json<UploadPfpResponse>> {
-    let club_id = auth.club_db_id;
-
-    let conn = &mut pool.get().await?;
+    let conn = &mut state.pool.get().await?;
+    let (club, _) = auth::authorize(conn, &auth, &club, Role::Editor).await?;
+    let club_id = club.id;
     let kind = infer::get(&bytes)
         .ok_or_else(|| AppError::from(StatusCode::BAD_REQUEST, "file type not recognized"))?;
 
@@ -117,14 +216,10 @@ async fn upload_pfp(
     let result = hasher.finalize();
 
     let file_name = format!("{:02x}.{}", result[..].iter().format(""), kind.extension());
-    let mut path: PathBuf = ["assets", "pfp"].iter().collect();
-    fs::create_dir_all(&path)?;
-
-    path.push(&file_name);
-
-    let mut file = File::create(&path)?;
-
-    file.write_all(&bytes)?;
+    let path_string = state
+        .assets
+        .put(&format!("pfp/{file_name}"), &bytes)
+        .await?;
 
     let old_pfp = clubs::table
         .select(clubs::profile_picture_url)
@@ -138,95 +233,119 @@ async fn upload_pfp(
         .first::<i64>(conn)
         .await?;
 
-    let path_string = path
-        .to_str()
-        .ok_or_else(|| {
-            AppError::from(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "can't turn path into string",
-            )
-        })?
-        .to_string();
-
     update(clubs::table)
         .filter(clubs::id.eq(club_id))
-        .set(clubs::profile_picture_url.eq(&path_string))
+        .set((
+            clubs::profile_picture_url.eq(&path_string),
+            clubs::updated_at.eq(state.clock.now()),
+        ))
         .execute(conn)
         .await?;
 
     if old_pfp != DEFAULT_PROFILE_PICTURE_URL && other_pfps == 1 {
-        fs::remove_file(old_pfp)?;
+        state.assets.remove(&old_pfp).await?;
     }
 
+    let entry = Entry::new(&auth, "club.pfp")
+        .club(&club)
+        .before(json!({ "profilePictureUrl": old_pfp }))
+        .after(json!({ "profilePictureUrl": path_string }));
+    audit::record(&state, &client, entry).await;
+
     Ok(Json(UploadPfpResponse { url: path_string }))
 }
 
 async fn edit_club(
-    Extension(pool): Extension<DbPool>,
-    Json(req): Json<ClubRequest>,
+    State(state): State<AppState>,
     Auth(auth): Auth,
-) -> AppResult<()> {
-    let club_id = auth.club_db_id;
+    client: Client,
+    Path(club): Path<String>,
+    Json(req): Json<ClubRequest>,
+) -> AppResult<Json<EditResponse>> {
+    let conn = &mut state.pool.get().await?;
+    let (club, _) = auth::authorize(conn, &auth, &club, Role::Editor).await?;
+    let edit = ProfileEdit::new(conn, req).await?;
+
+    Ok(Json(
+        save(&state, conn, &client, &club, &auth, edit, None).await?,
+    ))
+}
 
-    let conn = &mut pool.get().await?;
+/// Publishes `edit`, or keeps it as a draft when the club's edits are moderated.
+/// `reverted_from` is the revision the edit restores, if any.
+pub(super) async fn save(
+    state: &AppState,
+    conn: &mut AsyncPgConnection,
+    client: &Client,
+    club: &Club,
+    auth: &Claims,
+    edit: ProfileEdit,
+    reverted_from: Option<i32>,
+) -> AppResult<EditResponse> {
+    if state.config.moderation.edits && !club.trusted {
+        let draft = drafts::submit(state, conn, club, auth, edit).await?;
+        let entry = Entry::new(auth, "club.draft").club(club).after(&draft);
+        audit::record(state, client, entry).await;
+        return Ok(EditResponse {
+            published: false,
+            draft: Some(draft),
+        });
+    }
 
-    let socials = req.socials;
+    let club_id = club.id;
+    let revision = RevisionInfo {
+        edited_by: Some(auth.user_id),
+        reverted_from,
+        at: state.clock.now(),
+    };
+    let (before, after) = conn
+        .transaction::<_, AppError, _>(|conn| Box::pin(publish(conn, club_id, edit, revision)))
+        .await?;
 
-    if let Some(website) = socials.website.as_ref() {
-        if Url::parse(website).is_err() {
-            return Err(AppError::from(
-                StatusCode::BAD_REQUEST,
-                "Website social isn't a valid URL",
-            ));
-        };
-    }
+    let action = if reverted_from.is_some() {
+        "club.revert"
+    } else {
+        "club.edit"
+    };
+    let entry = Entry::new(auth, action)
+        .club(club)
+        .before(before)
+        .after(after);
+    audit::record(state, client, entry).await;
+
+    Ok(EditResponse {
+        published: true,
+        draft: None,
+    })
+}
 
-    ensure_domain(&socials.instagram, "instagram.com")?;
-    ensure_domain(&socials.discord, "discord.gg")?;
-    ensure_domain(&socials.google_classroom, "classroom.google.com")?;
+/// Makes `edit` the live profile of a club, recording it as a new revision. Returns the
+/// revisions from before and after.
+pub(super) async fn publish(
+    conn: &mut AsyncPgConnection,
+    club_id: i32,
+    edit: ProfileEdit,
+    revision: RevisionInfo,
+) -> AppResult<(ClubRevision, ClubRevision)> {
+    let before = revisions::latest(conn, club_id, revision.at).await?;
 
     update(clubs::table)
         .filter(clubs::id.eq(club_id))
-        .set(ClubEdit {
-            club_name: req.club_name,
-            meet_time: req.meet_time,
-            description: req.description,
-            about: req.about,
-        })
+        .set((edit.club, clubs::updated_at.eq(revision.at)))
         .execute(conn)
         .await?;
 
-    let all_categories = HashMap::<_, _>::from_iter(
-        categories::table
-            .load::<Category>(conn)
-            .await?
-            .into_iter()
-            .map(|c| (c.category_name, c.id)),
-    );
-
-    let new_categories: Vec<String> = req
-        .categories
-        .into_iter()
-        .collect::<HashSet<String>>()
-        .into_iter()
-        .collect();
-
-    for category in &new_categories {
-        if !all_categories.contains_key(category) {
-            return Err(AppError::from(StatusCode::BAD_REQUEST, "invalid category"));
-        }
-    }
-
     delete(club_categories::table)
         .filter(club_categories::club_id.eq(club_id))
         .get_results::<ClubCategory>(conn)
         .await?;
 
-    let new_club_categories: Vec<NewClubCategory> = new_categories
+    let new_club_categories: Vec<NewClubCategory> = edit
+        .category_ids
         .into_iter()
-        .map(|name| NewClubCategory {
+        .map(|category_id| NewClubCategory {
             club_id,
-            category_id: *all_categories.get(&name).unwrap(),
+            category_id,
         })
         .collect();
 
@@ -237,17 +356,77 @@ async fn edit_club(
 
     update(club_socials::table)
         .filter(club_socials::club_id.eq(club_id))
-        .set(socials)
+        .set(edit.socials)
         .execute(conn)
         .await?;
 
+    let after = revisions::record(conn, club_id, revision).await?;
+
+    Ok((before, after))
+}
+
+// 6 hours
+const PFP_GC_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+// uploads write the file before the database row is updated, so leave young files alone
+const PFP_GC_MIN_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Background job that deletes uploaded profile pictures no club points to anymore.
+pub async fn collect_orphaned_pfps(state: AppState, shutdown: Shutdown) {
+    tasks::run_every(shutdown, PFP_GC_INTERVAL, || {
+        let state = state.clone();
+        async move {
+            if let Err(e) = remove_orphaned_pfps(&state).await {
+                eprintln!("profile picture gc failed: {e}");
+            }
+        }
+    })
+    .await
+}
+
+async fn remove_orphaned_pfps(state: &AppState) -> anyhow::Result<()> {
+    let conn = &mut state.pool.get().await?;
+    let in_use: HashSet<String> = clubs::table
+        .select(clubs::profile_picture_url)
+        .load::<String>(conn)
+        .await?
+        .into_iter()
+        .collect();
+
+    for (url, modified) in state.assets.list("pfp").await? {
+        let age = modified.elapsed().unwrap_or_default();
+        if !in_use.contains(&url) && age > PFP_GC_MIN_AGE {
+            state.assets.remove(&url).await?;
+        }
+    }
+
     Ok(())
 }
 
-pub fn app() -> Router {
+pub fn app() -> Router<AppState> {
     Router::new()
-        .route("/info", post(edit_club))
-        .route("/pfp", put(upload_pfp))
+        .route("/:club_id/info", post(edit_club))
+        .route("/:club_id/pfp", put(upload_pfp))
+        .route("/:club_id/drafts", get(drafts::club_drafts))
+}
+
+pub(crate) fn validate_socials(
+    website: &Option<String>,
+    google_classroom: &Option<String>,
+    discord: &Option<String>,
+    instagram: &Option<String>,
+) -> AppResult<()> {
+    if let Some(website) = website.as_ref() {
+        if Url::parse(website).is_err() {
+            return Err(AppError::from(
+                StatusCode::BAD_REQUEST,
+                "Website social isn't a valid URL",
+            ));
+        };
+    }
+
+    ensure_domain(instagram, "instagram.com")?;
+    ensure_domain(discord, "discord.gg")?;
+    ensure_domain(google_classroom, "classroom.google.com")
 }
 
 fn ensure_domain(url: &Option<String>, domain: &str) -> AppResult<()> {
@@ -0,0 +1,67 @@
+//! Clubs that stop meeting. Inactive and archived clubs are hidden from the public
+//! listings, and the officers of archived clubs can't log in. Deleting a club only hides
+//! it, so it can still be restored.
+
+use crate::{
+    auth,
+    error::{AppError, AppResult},
+    models::{Club, ClubStatus},
+    schema::*,
+    state::AppState,
+};
+use axum::http::StatusCode;
+use chrono::{DateTime, Utc};
+use diesel::{prelude::*, update};
+use diesel_async::RunQueryDsl;
+
+/// Sets the status of a club. Returns the club as it was before.
+pub async fn set_status(state: &AppState, club: &str, status: ClubStatus) -> AppResult<Club> {
+    let conn = &mut state.pool.get().await?;
+    let club = auth::find_club(conn, club).await?;
+
+    update(clubs::table.find(club.id))
+        .set((clubs::status.eq(status), clubs::missed_deadline.eq(false)))
+        .execute(conn)
+        .await?;
+
+    Ok(club)
+}
+
+/// Hides a club everywhere, as if it had been deleted. Returns the club as it was before.
+pub async fn delete(state: &AppState, club: &str) -> AppResult<Club> {
+    let conn = &mut state.pool.get().await?;
+    let club = auth::find_club(conn, club).await?;
+    if club.deleted_at.is_some() {
+        return Err(AppError::from(
+            StatusCode::CONFLICT,
+            "the club has already been deleted",
+        ));
+    }
+
+    update(clubs::table.find(club.id))
+        .set(clubs::deleted_at.eq(state.clock.now()))
+        .execute(conn)
+        .await?;
+
+    Ok(club)
+}
+
+/// Makes an archived, inactive or deleted club active again. This also counts as
+/// confirming its profile for the school year, so it isn't made inactive again right away.
+/// Returns the club as it was before.
+pub async fn restore(state: &AppState, club: &str) -> AppResult<Club> {
+    let conn = &mut state.pool.get().await?;
+    let club = auth::find_club(conn, club).await?;
+
+    update(clubs::table.find(club.id))
+        .set((
+            clubs::status.eq(ClubStatus::Active),
+            clubs::deleted_at.eq(None::<DateTime<Utc>>),
+            clubs::confirmed_at.eq(state.clock.now()),
+            clubs::missed_deadline.eq(false),
+        ))
+        .execute(conn)
+        .await?;
+
+    Ok(club)
+}
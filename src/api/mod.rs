@@ -1,26 +1,32 @@
-use std::{collections::HashMap, sync::Arc};
-
-use axum::{Extension, Router};
-use tokio::sync::Mutex;
-
-use self::password::Resets;
+use crate::state::AppState;
+use axum::Router;
 
 pub mod admin;
+pub mod applications;
 pub mod auth;
 pub mod club;
+pub mod directory;
+pub mod drafts;
 pub mod edit;
+pub mod lifecycle;
+pub mod officers;
 pub mod password;
+pub mod reregistration;
+pub mod revisions;
+pub mod transfer;
+pub mod two_factor;
 
-pub fn app() -> Router {
-    let shared_resets = Arc::new(Mutex::new(Resets(HashMap::new())));
-
+pub fn app() -> Router<AppState> {
     Router::new()
         .nest("/admin", admin::app())
         .nest("/auth", auth::app())
-        .nest("/edit", edit::app())
-        .nest("/club", club::app())
+        .nest("/edit", edit::app().merge(revisions::app()))
+        .nest("/club", club::app().merge(applications::app()))
+        .nest("/officers", officers::app().merge(transfer::app()))
+        .nest("/invitations", officers::invitation_app())
+        .nest("/transfers", transfer::confirm_app())
         .nest("/password", password::app())
-        .layer(Extension(shared_resets))
+        .nest("/confirm", reregistration::app())
 }
 
 pub const DEFAULT_PROFILE_PICTURE_URL: &str = "assets/default_pfp.png";
@@ -0,0 +1,572 @@
+use super::{auth::AuthorizedResponse, password::hash_uid};
+use crate::{
+    audit::{self, Client, Entry},
+    auth::{self, Auth},
+    error::{AppError, AppResult},
+    models::{Club, ClubInvitation, ClubOfficer, Role, User},
+    password_policy,
+    schema::*,
+    state::AppState,
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{delete, get, post},
+    Json, Router,
+};
+use chrono::{DateTime, Datelike, Utc};
+use diesel::{insert_into, prelude::*, update};
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use lettre::{message::Mailbox, Address, Message};
+use nanoid::nanoid;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::Duration;
+
+// 7 days, like onboarding links
+const INVITATION_ALLOWED_TIME: Duration = Duration::from_secs(60 * 60 * 24 * 7);
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OfficerResponse {
+    username: String,
+    email: String,
+    role: Role,
+}
+
+/// The officers of a club, which any of them may see.
+async fn list_officers(
+    State(state): State<AppState>,
+    Auth(auth): Auth,
+    Path(club): Path<String>,
+) -> AppResult<Json<Vec<OfficerResponse>>> {
+    let conn = &mut state.pool.get().await?;
+    let (club, _) = auth::authorize(conn, &auth, &club, Role::Editor).await?;
+
+    let officers = club_officers::table
+        .inner_join(users::table)
+        .filter(club_officers::club_id.eq(club.id))
+        .select((users::username, users::email, club_officers::role))
+        .order(users::username)
+        .load::<(String, String, Role)>(conn)
+        .await?
+        .into_iter()
+        .map(|(username, email, role)| OfficerResponse {
+            username,
+            email,
+            role,
+        })
+        .collect();
+
+    Ok(Json(officers))
+}
+
+async fn remove_officer(
+    State(state): State<AppState>,
+    Auth(auth): Auth,
+    client: Client,
+    Path((club, username)): Path<(String, String)>,
+) -> AppResult<()> {
+    let conn = &mut state.pool.get().await?;
+    let (club, _) = auth::authorize(conn, &auth, &club, Role::President).await?;
+
+    let officer = club_officers::table
+        .inner_join(users::table)
+        .filter(club_officers::club_id.eq(club.id))
+        .filter(users::username.eq(&username))
+        .select((club_officers::user_id, club_officers::role))
+        .first::<(i32, Role)>(conn)
+        .await
+        .optional()?;
+
+    match officer {
+        None => Err(AppError::from(
+            StatusCode::NOT_FOUND,
+            "that user is not an officer of this club",
+        )),
+        Some((_, Role::President)) => Err(AppError::from(
+            StatusCode::BAD_REQUEST,
+            "the president can't be removed",
+        )),
+        Some((user_id, role)) => {
+            diesel::delete(club_officers::table.find((club.id, user_id)))
+                .execute(conn)
+                .await?;
+            let entry = Entry::new(&auth, "officer.remove")
+                .club(&club)
+                .target(username)
+                .before(json!({ "role": role }));
+            audit::record(&state, &client, entry).await;
+            Ok(())
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InvitationResponse {
+    id: i32,
+    email: String,
+    role: Role,
+    invited_by: Option<String>,
+    expires_at: DateTime<Utc>,
+    /// expired invitations can still be resent
+    expired: bool,
+}
+
+impl InvitationResponse {
+    fn new(state: &AppState, invitation: ClubInvitation, invited_by: Option<String>) -> Self {
+        Self {
+            id: invitation.id,
+            email: invitation.email,
+            role: invitation.role,
+            invited_by,
+            expired: state.clock.now() > invitation.expires_at,
+            expires_at: invitation.expires_at,
+        }
+    }
+}
+
+async fn list_invitations(
+    State(state): State<AppState>,
+    Auth(auth): Auth,
+    Path(club): Path<String>,
+) -> AppResult<Json<Vec<InvitationResponse>>> {
+    let conn = &mut state.pool.get().await?;
+    let (club, _) = auth::authorize(conn, &auth, &club, Role::President).await?;
+
+    let invitations = club_invitations::table
+        .left_join(users::table)
+        .filter(club_invitations::club_id.eq(club.id))
+        .select((club_invitations::all_columns, users::username.nullable()))
+        .order(club_invitations::id)
+        .load::<(ClubInvitation, Option<String>)>(conn)
+        .await?
+        .into_iter()
+        .map(|(invitation, invited_by)| InvitationResponse::new(&state, invitation, invited_by))
+        .collect();
+
+    Ok(Json(invitations))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InviteRequest {
+    email: String,
+    role: Role,
+}
+
+async fn invite(
+    State(state): State<AppState>,
+    Auth(auth): Auth,
+    client: Client,
+    Path(club): Path<String>,
+    Json(req): Json<InviteRequest>,
+) -> AppResult<Json<InvitationResponse>> {
+    let conn = &mut state.pool.get().await?;
+    let (club, _) = auth::authorize(conn, &auth, &club, Role::President).await?;
+
+    if req.role == Role::President {
+        return Err(AppError::from(
+            StatusCode::BAD_REQUEST,
+            "a club only has one president, invite an officer instead",
+        ));
+    }
+    let pending = club_invitations::table
+        .filter(club_invitations::club_id.eq(club.id))
+        .filter(club_invitations::email.eq(&req.email))
+        .count()
+        .get_result::<i64>(conn)
+        .await?;
+    if pending > 0 {
+        return Err(AppError::from(
+            StatusCode::CONFLICT,
+            "that email already has an invitation, resend it instead",
+        ));
+    }
+
+    let address = parse_email(&req.email)?;
+    let token = nanoid!();
+    let expires_at = state.clock.now() + chrono::Duration::from_std(INVITATION_ALLOWED_TIME)?;
+
+    let invitation = insert_into(club_invitations::table)
+        .values((
+            club_invitations::club_id.eq(club.id),
+            club_invitations::email.eq(&req.email),
+            club_invitations::role.eq(req.role),
+            club_invitations::token_hash.eq(hash_uid(&token)),
+            club_invitations::invited_by.eq(auth.user_id),
+            club_invitations::expires_at.eq(expires_at),
+        ))
+        .get_result::<ClubInvitation>(conn)
+        .await?;
+
+    send_invitation(&state, &club, &auth.username, address, req.role, token);
+
+    let invitation = InvitationResponse::new(&state, invitation, Some(auth.username.clone()));
+    let entry = Entry::new(&auth, "officer.invite")
+        .club(&club)
+        .target(&invitation.email)
+        .after(&invitation);
+    audit::record(&state, &client, entry).await;
+    Ok(Json(invitation))
+}
+
+/// Emails a new link for an invitation, which is valid for another 7 days. The old link
+/// stops working.
+async fn resend_invitation(
+    State(state): State<AppState>,
+    Auth(auth): Auth,
+    client: Client,
+    Path((club, id)): Path<(String, i32)>,
+) -> AppResult<Json<InvitationResponse>> {
+    let conn = &mut state.pool.get().await?;
+    let (club, _) = auth::authorize(conn, &auth, &club, Role::President).await?;
+    let invitation = find_club_invitation(conn, &club, id).await?;
+
+    let address = parse_email(&invitation.email)?;
+    let token = nanoid!();
+    let expires_at = state.clock.now() + chrono::Duration::from_std(INVITATION_ALLOWED_TIME)?;
+
+    let invitation = update(club_invitations::table.find(invitation.id))
+        .set((
+            club_invitations::token_hash.eq(hash_uid(&token)),
+            club_invitations::invited_by.eq(auth.user_id),
+            club_invitations::expires_at.eq(expires_at),
+        ))
+        .get_result::<ClubInvitation>(conn)
+        .await?;
+    send_invitation(
+        &state,
+        &club,
+        &auth.username,
+        address,
+        invitation.role,
+        token,
+    );
+
+    let invitation = InvitationResponse::new(&state, invitation, Some(auth.username.clone()));
+    let entry = Entry::new(&auth, "officer.invitation_resend")
+        .club(&club)
+        .target(&invitation.email)
+        .after(&invitation);
+    audit::record(&state, &client, entry).await;
+    Ok(Json(invitation))
+}
+
+async fn revoke_invitation(
+    State(state): State<AppState>,
+    Auth(auth): Auth,
+    client: Client,
+    Path((club, id)): Path<(String, i32)>,
+) -> AppResult<()> {
+    let conn = &mut state.pool.get().await?;
+    let (club, _) = auth::authorize(conn, &auth, &club, Role::President).await?;
+    let invitation = find_club_invitation(conn, &club, id).await?;
+
+    diesel::delete(club_invitations::table.find(invitation.id))
+        .execute(conn)
+        .await?;
+    let entry = Entry::new(&auth, "officer.invitation_revoke")
+        .club(&club)
+        .target(&invitation.email)
+        .before(json!({ "role": invitation.role }));
+    audit::record(&state, &client, entry).await;
+    Ok(())
+}
+
+async fn find_club_invitation(
+    conn: &mut AsyncPgConnection,
+    club: &Club,
+    id: i32,
+) -> AppResult<ClubInvitation> {
+    club_invitations::table
+        .find(id)
+        .filter(club_invitations::club_id.eq(club.id))
+        .first::<ClubInvitation>(conn)
+        .await
+        .optional()?
+        .ok_or_else(|| AppError::from(StatusCode::NOT_FOUND, "the invitation does not exist"))
+}
+
+/// The frontend url for the invitation `token`.
+fn invitation_link(state: &AppState, token: &str) -> String {
+    format!("{}/invitation/{}", state.config.server.frontend_host, token)
+}
+
+fn parse_email(email: &str) -> AppResult<Address> {
+    email
+        .parse::<Address>()
+        .map_err(|_| AppError::from(StatusCode::BAD_REQUEST, "invalid email"))
+}
+
+/// Emails the link for an invitation in the background. The token has to be stored by
+/// now, so the link works as soon as it arrives.
+fn send_invitation(
+    state: &AppState,
+    club: &Club,
+    invited_by: &str,
+    email: Address,
+    role: Role,
+    token: String,
+) {
+    let sender = state.clone();
+    let club = club.clone();
+    let invited_by = invited_by.to_string();
+    state.outbox.send("officer invitation", async move {
+        send_invitation_email(&sender, &club, &invited_by, email, role, &token).await
+    });
+}
+
+async fn send_invitation_email(
+    state: &AppState,
+    club: &Club,
+    invited_by: &str,
+    destination_address: Address,
+    role: Role,
+    token: &str,
+) -> anyhow::Result<()> {
+    let link = invitation_link(state, token);
+    let article = if role == Role::Officer { "an" } else { "a" };
+    let body = format!(
+        r"Hi,
+
+{invited_by} has invited you to be {article} {role} of {} on the CCA Club Hub. To accept, open the below link within the next {} days (or paste it into your browser if clicking is not working):
+
+{link}
+
+You can log in with an account you already have, or create a new one. If you weren't expecting this invitation you can disregard this message.
+
+Thanks,
+The CCA Club Hub Team.",
+        club.club_name,
+        INVITATION_ALLOWED_TIME.as_secs() / (60 * 60 * 24),
+    );
+
+    let email = Message::builder()
+        .from(Mailbox::new(
+            Some("CCA Club Hub".to_string()),
+            state.mailer.address(),
+        ))
+        .to(Mailbox::new(None, destination_address))
+        .subject(format!("Join {} on the CCA Club Hub", club.club_name))
+        .body(body)?;
+
+    state.mailer.send(email).await
+}
+
+/// Looks up the invitation with the link `token`, as long as it hasn't expired.
+async fn find_invitation(
+    state: &AppState,
+    conn: &mut AsyncPgConnection,
+    token: &str,
+) -> AppResult<(ClubInvitation, Club)> {
+    let (invitation, club) = club_invitations::table
+        .inner_join(clubs::table)
+        .filter(club_invitations::token_hash.eq(hash_uid(token)))
+        .first::<(ClubInvitation, Club)>(conn)
+        .await
+        .optional()?
+        .ok_or_else(|| AppError::from(StatusCode::BAD_REQUEST, "invalid invitation link"))?;
+
+    if state.clock.now() > invitation.expires_at {
+        return Err(AppError::from(
+            StatusCode::BAD_REQUEST,
+            "invitation expired",
+        ));
+    }
+    Ok((invitation, club))
+}
+
+/// Uses up the invitation and adds the user to the club it is for.
+async fn join(
+    conn: &mut AsyncPgConnection,
+    invitation: &ClubInvitation,
+    user_id: i32,
+) -> AppResult<()> {
+    // whoever deletes it first gets in, so a link can't be used twice at once
+    let deleted = diesel::delete(club_invitations::table.find(invitation.id))
+        .execute(conn)
+        .await?;
+    if deleted == 0 {
+        return Err(AppError::from(
+            StatusCode::BAD_REQUEST,
+            "invalid invitation link",
+        ));
+    }
+
+    let added = insert_into(club_officers::table)
+        .values(ClubOfficer {
+            club_id: invitation.club_id,
+            user_id,
+            role: invitation.role,
+        })
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .await?;
+    if added == 0 {
+        return Err(AppError::from(
+            StatusCode::CONFLICT,
+            "you are already an officer of this club",
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InvitationDetailsResponse {
+    club: String,
+    club_name: String,
+    email: String,
+    role: Role,
+    expires_at: DateTime<Utc>,
+}
+
+/// What the invitation page shows before it is accepted.
+async fn invitation_details(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> AppResult<Json<InvitationDetailsResponse>> {
+    let conn = &mut state.pool.get().await?;
+    let (invitation, club) = find_invitation(&state, conn, &token).await?;
+
+    Ok(Json(InvitationDetailsResponse {
+        club: club.username,
+        club_name: club.club_name,
+        email: invitation.email,
+        role: invitation.role,
+        expires_at: invitation.expires_at,
+    }))
+}
+
+/// Accepts an invitation with the account of the logged in user. Their token lists the
+/// new club once they log in again.
+async fn accept(
+    State(state): State<AppState>,
+    Auth(auth): Auth,
+    client: Client,
+    Path(token): Path<String>,
+) -> AppResult<()> {
+    let conn = &mut state.pool.get().await?;
+    let (invitation, club) = find_invitation(&state, conn, &token).await?;
+
+    let role = invitation.role;
+    let user_id = auth.user_id;
+    conn.transaction::<_, AppError, _>(|conn| {
+        Box::pin(async move { join(conn, &invitation, user_id).await })
+    })
+    .await?;
+
+    let entry = Entry::new(&auth, "officer.join")
+        .club(&club)
+        .after(json!({ "role": role }));
+    audit::record(&state, &client, entry).await;
+    Ok(())
+}
+
+/// An account to create for someone following a link they were emailed.
+#[derive(Deserialize)]
+pub(super) struct RegisterRequest {
+    username: String,
+    password: String,
+}
+
+impl RegisterRequest {
+    /// Checks the password against the policy, which also rejects the name of `club`, and
+    /// hashes it.
+    pub(super) fn hash_password(&self, state: &AppState, club: &Club) -> AppResult<String> {
+        password_policy::enforce(
+            &state.config.password_policy,
+            "password",
+            &self.password,
+            &[&self.username, &club.club_name],
+            state.clock.now().year(),
+        )?;
+        Ok(state.hasher.hash(&self.password)?)
+    }
+
+    /// Creates the user with `email` and the hash from [`RegisterRequest::hash_password`].
+    pub(super) async fn insert(
+        self,
+        conn: &mut AsyncPgConnection,
+        email: String,
+        password_hash: String,
+    ) -> AppResult<User> {
+        #[derive(Insertable)]
+        #[diesel(table_name = users)]
+        struct NewUser {
+            username: String,
+            email: String,
+            password_hash: String,
+        }
+
+        insert_into(users::table)
+            .values(NewUser {
+                username: self.username,
+                email,
+                password_hash,
+            })
+            .on_conflict(users::username)
+            .do_nothing()
+            .get_result::<User>(conn)
+            .await
+            .optional()?
+            .ok_or_else(|| {
+                AppError::from(
+                    StatusCode::CONFLICT,
+                    "a user with that username already exists",
+                )
+            })
+    }
+}
+
+/// Accepts an invitation by creating a new account with the invited email, and logs it in.
+async fn register(
+    State(state): State<AppState>,
+    client: Client,
+    Path(token): Path<String>,
+    Json(req): Json<RegisterRequest>,
+) -> AppResult<Json<AuthorizedResponse>> {
+    let conn = &mut state.pool.get().await?;
+    let (invitation, club) = find_invitation(&state, conn, &token).await?;
+    let password_hash = req.hash_password(&state, &club)?;
+
+    let role = invitation.role;
+    let user = conn
+        .transaction::<_, AppError, _>(|conn| {
+            Box::pin(async move {
+                let user = req
+                    .insert(conn, invitation.email.clone(), password_hash)
+                    .await?;
+                join(conn, &invitation, user.id).await?;
+                Ok(user)
+            })
+        })
+        .await?;
+
+    let entry = Entry::new(&user, "officer.join")
+        .club(&club)
+        .after(json!({ "role": role }));
+    audit::record(&state, &client, entry).await;
+    Ok(Json(
+        AuthorizedResponse::for_user(&state, conn, &user).await?,
+    ))
+}
+
+pub fn app() -> Router<AppState> {
+    Router::new()
+        .route("/:club", get(list_officers))
+        .route("/:club/officers/:username", delete(remove_officer))
+        .route("/:club/invitations", get(list_invitations).post(invite))
+        .route("/:club/invitations/:id", delete(revoke_invitation))
+        .route("/:club/invitations/:id/resend", post(resend_invitation))
+}
+
+/// Routes for whoever got an invitation, who may not have an account yet.
+pub fn invitation_app() -> Router<AppState> {
+    Router::new()
+        .route("/:token", get(invitation_details))
+        .route("/:token/accept", post(accept))
+        .route("/:token/register", post(register))
+}
@@ -1,43 +1,268 @@
 use crate::{
+    audit::{self, Actor, Client, Entry},
     auth,
-    email::{self, EMAIL_ADDRESS, FRONTEND_HOST},
+    clock::Clock,
     error::{AppError, AppResult},
-    models::Club,
+    models::User,
+    rate_limit::{self, ClientIp},
     schema::*,
+    state::AppState,
+    tasks::{self, Shutdown},
     DbPool,
 };
 use axum::{
-    extract::Path,
+    async_trait,
+    extract::{Path, State},
     http::StatusCode,
     routing::{get, post},
-    Extension, Json, Router,
+    Json, Router,
 };
-use diesel::{update, ExpressionMethods, OptionalExtension, QueryDsl};
+use chrono::{DateTime, Utc};
+use diesel::{delete, insert_into, update, ExpressionMethods, OptionalExtension, QueryDsl};
 use diesel_async::RunQueryDsl;
+use itertools::Itertools;
 use lettre::{message::Mailbox, Address, Message};
 use nanoid::nanoid;
 use serde::Deserialize;
-use std::{
-    collections::HashMap,
-    sync::Arc,
-    time::{Duration, Instant},
-};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 use tokio::sync::Mutex;
 
-#[allow(dead_code)]
-type ResetState = Arc<Mutex<Resets>>;
+/// Where outstanding password reset links are kept. [`PgResets`] is used by the server,
+/// [`Resets`] keeps them in memory.
+#[async_trait]
+pub trait ResetStore: Send + Sync {
+    /// Registers a link for `user_id` that stays valid for `valid_for` after `now`.
+    async fn insert(
+        &self,
+        uid: &str,
+        user_id: i32,
+        valid_for: Duration,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<()>;
+
+    /// Returns the user a link belongs to. Expired links are forgotten.
+    async fn user_for(
+        &self,
+        uid: &str,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<Result<i32, ResetError>>;
+
+    async fn remove(&self, uid: &str) -> anyhow::Result<()>;
+
+    /// Drops every link of `user_id`, e.g. once their password has been changed.
+    async fn remove_for_user(&self, user_id: i32) -> anyhow::Result<()>;
+
+    /// Drops every expired link.
+    async fn clean(&self, now: DateTime<Utc>) -> anyhow::Result<()>;
+}
 
+/// Outstanding password reset links, keyed by the uid in the link.
 #[derive(Default)]
-pub struct Resets(pub HashMap<String, (Instant, Duration, i32)>);
+pub struct Resets(pub HashMap<String, (DateTime<Utc>, Duration, i32)>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetError {
+    Invalid,
+    Expired,
+}
 
 impl Resets {
-    #[allow(dead_code)]
-    async fn clean(&mut self) {
+    /// Registers a link for `user_id` that stays valid for `valid_for` after `now`.
+    pub fn insert(&mut self, uid: String, user_id: i32, valid_for: Duration, now: DateTime<Utc>) {
+        self.0.insert(uid, (now, valid_for, user_id));
+    }
+
+    /// Returns the user a link belongs to. Expired links are forgotten.
+    pub fn user_for(&mut self, uid: &str, now: DateTime<Utc>) -> Result<i32, ResetError> {
+        let (issued, valid_for, user_id) = *self.0.get(uid).ok_or(ResetError::Invalid)?;
+        if is_expired(issued, valid_for, now) {
+            self.0.remove(uid);
+            return Err(ResetError::Expired);
+        }
+        Ok(user_id)
+    }
+
+    pub fn remove(&mut self, uid: &str) {
+        self.0.remove(uid);
+    }
+
+    pub fn remove_for_user(&mut self, user_id: i32) {
+        self.0.retain(|_, (_, _, id)| *id != user_id);
+    }
+
+    pub fn clean(&mut self, now: DateTime<Utc>) {
         self.0
-            .retain(|_, (instant, dur, _)| instant.elapsed() < *dur);
+            .retain(|_, (issued, valid_for, _)| !is_expired(*issued, *valid_for, now));
     }
 }
 
+#[async_trait]
+impl ResetStore for Mutex<Resets> {
+    async fn insert(
+        &self,
+        uid: &str,
+        user_id: i32,
+        valid_for: Duration,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        self.lock()
+            .await
+            .insert(uid.to_string(), user_id, valid_for, now);
+        Ok(())
+    }
+
+    async fn user_for(
+        &self,
+        uid: &str,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<Result<i32, ResetError>> {
+        Ok(self.lock().await.user_for(uid, now))
+    }
+
+    async fn remove(&self, uid: &str) -> anyhow::Result<()> {
+        self.lock().await.remove(uid);
+        Ok(())
+    }
+
+    async fn remove_for_user(&self, user_id: i32) -> anyhow::Result<()> {
+        self.lock().await.remove_for_user(user_id);
+        Ok(())
+    }
+
+    async fn clean(&self, now: DateTime<Utc>) -> anyhow::Result<()> {
+        self.lock().await.clean(now);
+        Ok(())
+    }
+}
+
+fn is_expired(issued: DateTime<Utc>, valid_for: Duration, now: DateTime<Utc>) -> bool {
+    // a negative elapsed time (clock went backwards) is not expired
+    (now - issued)
+        .to_std()
+        .is_ok_and(|elapsed| elapsed > valid_for)
+}
+
+/// Keeps reset links in the `password_resets` table so they survive restarts and can
+/// be issued by other processes, like `cca-admin`. Only a hash of each uid is stored.
+pub struct PgResets(pub DbPool);
+
+pub(crate) fn hash_uid(uid: &str) -> String {
+    format!("{:02x}", Sha256::digest(uid.as_bytes()).iter().format(""))
+}
+
+#[async_trait]
+impl ResetStore for PgResets {
+    async fn insert(
+        &self,
+        uid: &str,
+        user_id: i32,
+        valid_for: Duration,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        let conn = &mut self.0.get().await?;
+        insert_into(password_resets::table)
+            .values((
+                password_resets::token_hash.eq(hash_uid(uid)),
+                password_resets::user_id.eq(user_id),
+                password_resets::expires_at.eq(now + chrono::Duration::from_std(valid_for)?),
+            ))
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn user_for(
+        &self,
+        uid: &str,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<Result<i32, ResetError>> {
+        let conn = &mut self.0.get().await?;
+        let token_hash = hash_uid(uid);
+        let Some((user_id, expires_at)) = password_resets::table
+            .find(&token_hash)
+            .select((password_resets::user_id, password_resets::expires_at))
+            .first::<(i32, DateTime<Utc>)>(conn)
+            .await
+            .optional()?
+        else {
+            return Ok(Err(ResetError::Invalid));
+        };
+
+        if now > expires_at {
+            delete(password_resets::table.find(&token_hash))
+                .execute(conn)
+                .await?;
+            return Ok(Err(ResetError::Expired));
+        }
+        Ok(Ok(user_id))
+    }
+
+    async fn remove(&self, uid: &str) -> anyhow::Result<()> {
+        let conn = &mut self.0.get().await?;
+        delete(password_resets::table.find(hash_uid(uid)))
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_for_user(&self, user_id: i32) -> anyhow::Result<()> {
+        let conn = &mut self.0.get().await?;
+        delete(password_resets::table.filter(password_resets::user_id.eq(user_id)))
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn clean(&self, now: DateTime<Utc>) -> anyhow::Result<()> {
+        let conn = &mut self.0.get().await?;
+        delete(password_resets::table.filter(password_resets::expires_at.lt(now)))
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+}
+
+// 1 day
+const CLEAN_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Background job that drops expired reset links so they don't pile up.
+pub async fn clean_expired_resets(
+    resets: Arc<dyn ResetStore>,
+    clock: Arc<dyn Clock>,
+    shutdown: Shutdown,
+) {
+    tasks::run_every(shutdown, CLEAN_INTERVAL, || {
+        let resets = resets.clone();
+        let now = clock.now();
+        async move {
+            if let Err(e) = resets.clean(now).await {
+                eprintln!("failed to clean expired reset links: {e}");
+            }
+        }
+    })
+    .await
+}
+
+/// The frontend url for the reset link `uid`.
+pub fn reset_link(state: &AppState, uid: &str) -> String {
+    format!("{}/password/{}", state.config.server.frontend_host, uid)
+}
+
+/// Creates a reset link for `user_id` without emailing it to anyone.
+pub async fn issue_reset_link(
+    state: &AppState,
+    user_id: i32,
+    valid_for: Duration,
+) -> anyhow::Result<String> {
+    let uid = nanoid!();
+    state
+        .resets
+        .insert(&uid, user_id, valid_for, state.clock.now())
+        .await?;
+    Ok(reset_link(state, &uid))
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct PwdRequest {
@@ -54,26 +279,61 @@ struct NewPwdRequest {
 const RESET_ALLOWED_TIME: Duration = Duration::from_secs(60 * 60);
 
 async fn password_request(
-    Extension(pool): Extension<DbPool>,
-    Extension(resets): Extension<Arc<Mutex<Resets>>>,
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    client: Client,
     Json(req): Json<PwdRequest>,
 ) -> AppResult<()> {
-    let conn = &mut pool.get().await?;
-
-    let Some(club) = clubs::table
-        .filter(clubs::email.eq(req.email))
-        .first::<Club>(conn)
-        .await
-        .optional()?
-    else {
-        return Err(AppError::from(
-            StatusCode::NOT_FOUND,
-            "could not find matching club",
-        ));
-    };
+    let limit = &state.config.rate_limit.password_reset;
+    rate_limit::hit(&state, &format!("reset-ip:{ip}"), limit).await?;
+    rate_limit::hit(&state, &format!("reset:{}", req.email), limit).await?;
+
+    audit::record(
+        &state,
+        &client,
+        Entry::new(
+            Actor::Anonymous(req.email.clone()),
+            "password.reset_request",
+        ),
+    )
+    .await;
 
+    let conn = &mut state.pool.get().await?;
+
+    // someone with accounts for several clubs may have used the same email for each
+    let users = users::table
+        .filter(users::email.eq(req.email))
+        .load::<User>(conn)
+        .await?;
+
+    // answer the same whether or not anyone has that email, and send it in the background
+    // so the response time doesn't tell either
+    for user in users {
+        let sender = state.clone();
+        state.outbox.send("password reset", async move {
+            send_reset_email(&sender, user).await
+        });
+    }
+
+    Ok(())
+}
+
+async fn send_reset_email(state: &AppState, user: User) -> anyhow::Result<()> {
+    // the link has to work by the time it arrives, so it is stored before sending
     let uid = nanoid!();
-    let link = format!("{}/password/{}", *FRONTEND_HOST, uid);
+    state
+        .resets
+        .insert(&uid, user.id, RESET_ALLOWED_TIME, state.clock.now())
+        .await?;
+    if let Err(e) = email_reset_link(state, user, &uid).await {
+        state.resets.remove(&uid).await?;
+        return Err(e);
+    }
+    Ok(())
+}
+
+async fn email_reset_link(state: &AppState, user: User, uid: &str) -> anyhow::Result<()> {
+    let link = reset_link(state, uid);
     let body = format!(
         r"Hi {},
 
@@ -85,101 +345,78 @@ If you did not request this password reset you can disregard this message and yo
 
 Thanks,
 The CCA Club Hub Team.",
-        club.username,
+        user.username,
         RESET_ALLOWED_TIME.as_secs() / 60
     );
 
-    let destination_address = club
-        .email
-        .parse::<Address>()
-        .map_err(|_| AppError::from(StatusCode::BAD_REQUEST, "invalid email"))?;
-
     let email = Message::builder()
         .from(Mailbox::new(
             Some("apathetic programmers".to_string()),
-            EMAIL_ADDRESS.clone(),
+            state.mailer.address(),
+        ))
+        .to(Mailbox::new(
+            Some(user.username),
+            user.email.parse::<Address>()?,
         ))
-        .to(Mailbox::new(Some(club.username), destination_address))
         .subject("CCA Club Hub Password Reset")
-        .body(body)
-        .unwrap();
-
-    match email::send(email).await {
-        Ok(_) => {
-            let mut resets = resets.lock().await;
-            resets
-                .0
-                .insert(uid, (Instant::now(), RESET_ALLOWED_TIME, club.id));
-            Ok(())
-        }
-        Err(_) => Err(AppError::from(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "failed to send email",
-        )),
-    }
+        .body(body)?;
+
+    state.mailer.send(email).await
 }
 
 async fn password_reset(
-    Extension(pool): Extension<DbPool>,
-    Extension(resets): Extension<Arc<Mutex<Resets>>>,
+    State(state): State<AppState>,
+    client: Client,
     Path(uid): Path<String>,
     Json(req): Json<NewPwdRequest>,
 ) -> AppResult<()> {
-    let mut resets = resets.lock().await;
-
-    let Some((instant, allowed_time, club_id)) = resets.0.get(&uid) else {
-        return Err(AppError::from(
-            StatusCode::UNAUTHORIZED,
-            "invalid password reset url",
-        ));
+    let user_id = match state.resets.user_for(&uid, state.clock.now()).await? {
+        Ok(user_id) => user_id,
+        Err(ResetError::Invalid) => {
+            return Err(AppError::from(
+                StatusCode::UNAUTHORIZED,
+                "invalid password reset url",
+            ))
+        }
+        Err(ResetError::Expired) => {
+            return Err(AppError::from(
+                StatusCode::UNAUTHORIZED,
+                "password reset expired",
+            ))
+        }
     };
 
-    if instant.elapsed() > *allowed_time {
-        resets.0.remove(&uid);
-        return Err(AppError::from(
-            StatusCode::UNAUTHORIZED,
-            "password reset expired",
-        ));
-    }
+    let conn = &mut state.pool.get().await?;
 
-    let conn = &mut pool.get().await?;
+    let user = users::table.find(user_id).first::<User>(conn).await?;
+    auth::check_new_password(&state, conn, "password", &req.password, &user).await?;
 
-    update(clubs::table.find(club_id))
-        .set(clubs::password_hash.eq(auth::hash_password(req.password)?))
+    update(users::table.find(user_id))
+        .set(users::password_hash.eq(state.hasher.hash(req.password)?))
         .execute(conn)
         .await?;
 
-    resets.0.remove(&uid);
+    state.resets.remove(&uid).await?;
+    audit::record(&state, &client, Entry::new(&user, "password.reset")).await;
 
     Ok(())
 }
 
-async fn check_uid(
-    Extension(resets): Extension<Arc<Mutex<Resets>>>,
-    Path(uid): Path<String>,
-) -> AppResult<()> {
-    resets.lock().await.0.get(&uid).map_or(
-        Err(AppError::from(
+async fn check_uid(State(state): State<AppState>, Path(uid): Path<String>) -> AppResult<()> {
+    match state.resets.user_for(&uid, state.clock.now()).await? {
+        Ok(_) => Ok(()),
+        Err(ResetError::Invalid) => Err(AppError::from(
             StatusCode::BAD_REQUEST,
             "invalid password reset url",
         )),
-        |_| Ok(()),
-    )
+        Err(ResetError::Expired) => Err(AppError::from(
+            StatusCode::BAD_REQUEST,
+            "password reset expired",
+        )),
+    }
 }
 
-pub fn app() -> Router {
-    // TODO we need to fix this. we moved the reset state to the api layer so we need to do something about this polling behaviour
-    // let shared_resets = ResetState::default();
-    // let uids = shared_resets.clone();
-    // tokio::task::spawn(async move {
-    //     let mut interval = interval(Duration::from_secs(24 * 60 * 60));
-
-    //     loop {
-    //         interval.tick().await;
-    //         uids.lock().await.clean().await;
-    //     }
-    // });
-
+pub fn app() -> Router<AppState> {
     Router::new()
         .route("/reset", post(password_request))
         .route("/:uid", post(password_reset))
@@ -0,0 +1,447 @@
+//! Yearly confirmation that club profiles are still accurate. Clubs registered before the
+//! school year started are emailed a link to confirm theirs, and the ones that were
+//! reminded but haven't confirmed by the deadline become inactive until they do.
+
+use super::password::hash_uid;
+use crate::{
+    audit::{self, Actor, Client, Entry},
+    config::ReregistrationConfig,
+    error::{AppError, AppResult},
+    models::{Club, ClubReminder, ClubStatus},
+    schema::*,
+    state::AppState,
+    tasks::{self, Shutdown},
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::get,
+    Json, Router,
+};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use diesel::{delete, insert_into, prelude::*, update};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use lettre::{message::Mailbox, Address, Message};
+use nanoid::nanoid;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::HashMap;
+
+// 6 hours
+const REMINDER_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(6 * 60 * 60);
+
+/// The school year profiles are confirmed for.
+#[derive(Debug, Clone, Copy)]
+pub struct SchoolYear {
+    pub start: DateTime<Utc>,
+    /// clubs that were reminded but haven't confirmed by then become inactive
+    pub deadline: DateTime<Utc>,
+}
+
+impl SchoolYear {
+    /// The school year `now` is in.
+    pub fn current(config: &ReregistrationConfig, now: DateTime<Utc>) -> Self {
+        let (month, day) = config
+            .school_year_start()
+            .expect("the school year start is checked when the config is loaded");
+        let start_in = |year| {
+            let date = NaiveDate::from_ymd_opt(year, month, day)
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .expect("every year has the school year start");
+            DateTime::<Utc>::from_utc(date, Utc)
+        };
+
+        let mut start = start_in(now.year());
+        if start > now {
+            start = start_in(now.year() - 1);
+        }
+        SchoolYear {
+            start,
+            deadline: start + Duration::days(config.deadline_days.into()),
+        }
+    }
+
+    /// Whether `club` has confirmed its profile this school year. Clubs registered since
+    /// it started don't need to.
+    pub fn is_confirmed(&self, club: &Club) -> bool {
+        club.created_at >= self.start || club.confirmed_at.is_some_and(|at| at >= self.start)
+    }
+}
+
+/// Clubs that can still confirm, leaving out archived and deleted ones.
+async fn open_clubs(conn: &mut AsyncPgConnection) -> QueryResult<Vec<Club>> {
+    clubs::table
+        .filter(clubs::status.ne(ClubStatus::Archived))
+        .filter(clubs::deleted_at.is_null())
+        .order(clubs::username)
+        .load::<Club>(conn)
+        .await
+}
+
+/// How many reminders each club was sent for `year`, and when the last one was.
+async fn reminders_sent(
+    conn: &mut AsyncPgConnection,
+    year: &SchoolYear,
+) -> QueryResult<HashMap<i32, (usize, DateTime<Utc>)>> {
+    let reminders = club_reminders::table
+        .filter(club_reminders::school_year.eq(year.start))
+        .select((club_reminders::club_id, club_reminders::sent_at))
+        .load::<(i32, DateTime<Utc>)>(conn)
+        .await?;
+
+    let mut sent = HashMap::<i32, (usize, DateTime<Utc>)>::new();
+    for (club_id, sent_at) in reminders {
+        let entry = sent.entry(club_id).or_insert((0, sent_at));
+        entry.0 += 1;
+        entry.1 = entry.1.max(sent_at);
+    }
+    Ok(sent)
+}
+
+/// What a pass of the reminder job did.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReminderRun {
+    /// usernames of the clubs that were emailed
+    pub reminded: Vec<String>,
+    /// usernames of the clubs that became inactive
+    pub deactivated: Vec<String>,
+}
+
+/// Reminds the clubs that haven't confirmed before the deadline of `year`, or makes the
+/// reminded ones inactive after it.
+pub async fn run(state: &AppState, year: &SchoolYear) -> anyhow::Result<ReminderRun> {
+    if state.clock.now() < year.deadline {
+        Ok(ReminderRun {
+            reminded: send_reminders(state, year).await?,
+            deactivated: Vec::new(),
+        })
+    } else {
+        Ok(ReminderRun {
+            reminded: Vec::new(),
+            deactivated: deactivate_unconfirmed(state, year).await?,
+        })
+    }
+}
+
+/// Emails a confirmation link to every club that hasn't confirmed and wasn't reminded
+/// within the last `reminder_interval_days`.
+async fn send_reminders(state: &AppState, year: &SchoolYear) -> anyhow::Result<Vec<String>> {
+    let now = state.clock.now();
+    let interval = Duration::days(state.config.reregistration.reminder_interval_days.into());
+    let conn = &mut state.pool.get().await?;
+    let sent = reminders_sent(conn, year).await?;
+
+    let mut reminded = Vec::new();
+    for club in open_clubs(conn).await? {
+        if year.is_confirmed(&club) {
+            continue;
+        }
+        if sent
+            .get(&club.id)
+            .is_some_and(|(_, last)| now - *last < interval)
+        {
+            continue;
+        }
+
+        // stored first so the link works as soon as it arrives, and dropped again if the
+        // email never goes out so the club is reminded on the next run
+        let token = nanoid!();
+        let token_hash = hash_uid(&token);
+        let stored = insert_into(club_reminders::table)
+            .values((
+                club_reminders::club_id.eq(club.id),
+                club_reminders::school_year.eq(year.start),
+                club_reminders::token_hash.eq(&token_hash),
+                club_reminders::sent_at.eq(now),
+            ))
+            .execute(conn)
+            .await;
+        if let Err(e) = stored {
+            eprintln!("failed to store a reminder for {}: {e}", club.username);
+            continue;
+        }
+        if let Err(e) = send_reminder_email(state, &club, year, &token).await {
+            eprintln!("failed to send a reminder to {}: {e}", club.username);
+            if let Err(e) = delete(club_reminders::table)
+                .filter(club_reminders::token_hash.eq(&token_hash))
+                .execute(conn)
+                .await
+            {
+                eprintln!("failed to remove the reminder for {}: {e}", club.username);
+            }
+            continue;
+        }
+        reminded.push(club.username);
+    }
+
+    Ok(reminded)
+}
+
+/// Makes the active clubs that were reminded during `year` but haven't confirmed inactive.
+/// Clubs that were never reminded, e.g. because they have no email, are left alone.
+async fn deactivate_unconfirmed(
+    state: &AppState,
+    year: &SchoolYear,
+) -> anyhow::Result<Vec<String>> {
+    let conn = &mut state.pool.get().await?;
+    let sent = reminders_sent(conn, year).await?;
+
+    let mut deactivated = Vec::new();
+    for club in open_clubs(conn).await? {
+        if club.status != ClubStatus::Active
+            || year.is_confirmed(&club)
+            || !sent.contains_key(&club.id)
+        {
+            continue;
+        }
+
+        update(clubs::table.find(club.id))
+            .set((
+                clubs::status.eq(ClubStatus::Inactive),
+                clubs::missed_deadline.eq(true),
+            ))
+            .execute(conn)
+            .await?;
+        audit::record(
+            state,
+            &Client::default(),
+            Entry::new(Actor::System, "club.deactivate")
+                .club(&club)
+                .before(json!({ "status": club.status }))
+                .after(json!({ "status": ClubStatus::Inactive })),
+        )
+        .await;
+        deactivated.push(club.username);
+    }
+
+    Ok(deactivated)
+}
+
+/// Background job that runs [`run`] for the current school year, when enabled.
+pub async fn remind_unconfirmed_clubs(state: AppState, shutdown: Shutdown) {
+    if !state.config.reregistration.enabled {
+        return;
+    }
+
+    tasks::run_every(shutdown, REMINDER_CHECK_INTERVAL, || {
+        let state = state.clone();
+        async move {
+            let year = SchoolYear::current(&state.config.reregistration, state.clock.now());
+            if let Err(e) = run(&state, &year).await {
+                eprintln!("club reminders failed: {e}");
+            }
+        }
+    })
+    .await
+}
+
+/// The frontend url for the confirmation `token`.
+fn confirmation_link(state: &AppState, token: &str) -> String {
+    format!("{}/confirm/{}", state.config.server.frontend_host, token)
+}
+
+async fn send_reminder_email(
+    state: &AppState,
+    club: &Club,
+    year: &SchoolYear,
+    token: &str,
+) -> anyhow::Result<()> {
+    let link = confirmation_link(state, token);
+    let body = format!(
+        r"Hi,
+
+A new school year has started! Please check that the profile of {} on the CCA Club Hub is still accurate, especially its meeting time. If it is, just open the below link to confirm (or paste it into your browser if clicking is not working):
+
+{link}
+
+If anything changed, log in to update the profile first. Clubs that haven't confirmed by {} will be hidden from the club list until they do.
+
+Thanks,
+The CCA Club Hub Team.",
+        club.club_name,
+        year.deadline.format("%B %-d"),
+    );
+
+    let email = Message::builder()
+        .from(Mailbox::new(
+            Some("CCA Club Hub".to_string()),
+            state.mailer.address(),
+        ))
+        .to(Mailbox::new(None, club.email.parse::<Address>()?))
+        .subject(format!("Is {}'s profile still accurate?", club.club_name))
+        .body(body)?;
+
+    state.mailer.send(email).await
+}
+
+/// Looks up the club a confirmation link was sent to, as long as the link is from the
+/// current school year.
+async fn find_reminder(
+    state: &AppState,
+    conn: &mut AsyncPgConnection,
+    token: &str,
+) -> AppResult<(ClubReminder, Club)> {
+    let (reminder, club) = club_reminders::table
+        .inner_join(clubs::table)
+        .filter(club_reminders::token_hash.eq(hash_uid(token)))
+        .filter(clubs::deleted_at.is_null())
+        .first::<(ClubReminder, Club)>(conn)
+        .await
+        .optional()?
+        .ok_or_else(|| AppError::from(StatusCode::BAD_REQUEST, "invalid confirmation link"))?;
+
+    let year = SchoolYear::current(&state.config.reregistration, state.clock.now());
+    if reminder.school_year != year.start {
+        return Err(AppError::from(
+            StatusCode::BAD_REQUEST,
+            "confirmation link expired",
+        ));
+    }
+    if club.status == ClubStatus::Archived {
+        return Err(AppError::from(
+            StatusCode::FORBIDDEN,
+            "the club has been archived",
+        ));
+    }
+    Ok((reminder, club))
+}
+
+/// The profile a confirmation link is for, so it can be checked before confirming.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ConfirmationResponse {
+    club: String,
+    club_name: String,
+    description: String,
+    meet_time: String,
+    email: String,
+    status: ClubStatus,
+    confirmed_at: Option<DateTime<Utc>>,
+}
+
+impl ConfirmationResponse {
+    fn new(club: Club) -> Self {
+        Self {
+            club: club.username,
+            club_name: club.club_name,
+            description: club.description,
+            meet_time: club.meet_time,
+            email: club.email,
+            status: club.status,
+            confirmed_at: club.confirmed_at,
+        }
+    }
+}
+
+async fn confirmation_details(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> AppResult<Json<ConfirmationResponse>> {
+    let conn = &mut state.pool.get().await?;
+    let (_, club) = find_reminder(&state, conn, &token).await?;
+    Ok(Json(ConfirmationResponse::new(club)))
+}
+
+/// Confirms the profile is still accurate, making the club active again if it was made
+/// inactive for missing the deadline. Clubs an admin made inactive stay that way.
+async fn confirm(
+    State(state): State<AppState>,
+    client: Client,
+    Path(token): Path<String>,
+) -> AppResult<Json<ConfirmationResponse>> {
+    let conn = &mut state.pool.get().await?;
+    let (_, club) = find_reminder(&state, conn, &token).await?;
+
+    let status = if club.missed_deadline {
+        ClubStatus::Active
+    } else {
+        club.status
+    };
+    let confirmed = update(clubs::table.find(club.id))
+        .set((
+            clubs::confirmed_at.eq(state.clock.now()),
+            clubs::status.eq(status),
+            clubs::missed_deadline.eq(false),
+        ))
+        .get_result::<Club>(conn)
+        .await?;
+    audit::record(
+        &state,
+        &client,
+        Entry::new(Actor::Anonymous(club.email.clone()), "club.confirm")
+            .club(&club)
+            .before(json!({ "status": club.status, "confirmedAt": club.confirmed_at }))
+            .after(json!({ "status": confirmed.status, "confirmedAt": confirmed.confirmed_at })),
+    )
+    .await;
+
+    Ok(Json(ConfirmationResponse::new(confirmed)))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReregistrationReport {
+    pub school_year_start: DateTime<Utc>,
+    pub deadline: DateTime<Utc>,
+    pub confirmed: usize,
+    pub unconfirmed: usize,
+    /// unconfirmed clubs first
+    pub clubs: Vec<ClubConfirmation>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClubConfirmation {
+    pub club: String,
+    pub club_name: String,
+    pub email: String,
+    pub status: ClubStatus,
+    pub confirmed: bool,
+    pub confirmed_at: Option<DateTime<Utc>>,
+    pub reminders_sent: usize,
+    pub last_reminded_at: Option<DateTime<Utc>>,
+}
+
+/// Which clubs have confirmed their profile for `year`, leaving out archived and deleted
+/// ones.
+pub async fn report(state: &AppState, year: &SchoolYear) -> AppResult<ReregistrationReport> {
+    let conn = &mut state.pool.get().await?;
+    let sent = reminders_sent(conn, year).await?;
+
+    let mut clubs: Vec<ClubConfirmation> = open_clubs(conn)
+        .await?
+        .into_iter()
+        .map(|club| {
+            let (reminders_sent, last_reminded_at) = match sent.get(&club.id) {
+                Some((count, last)) => (*count, Some(*last)),
+                None => (0, None),
+            };
+            ClubConfirmation {
+                confirmed: year.is_confirmed(&club),
+                club: club.username,
+                club_name: club.club_name,
+                email: club.email,
+                status: club.status,
+                confirmed_at: club.confirmed_at,
+                reminders_sent,
+                last_reminded_at,
+            }
+        })
+        .collect();
+    // stable, so each group stays sorted by username
+    clubs.sort_by_key(|club| club.confirmed);
+
+    let confirmed = clubs.iter().filter(|club| club.confirmed).count();
+    Ok(ReregistrationReport {
+        school_year_start: year.start,
+        deadline: year.deadline,
+        confirmed,
+        unconfirmed: clubs.len() - confirmed,
+        clubs,
+    })
+}
+
+pub fn app() -> Router<AppState> {
+    Router::new().route("/:token", get(confirmation_details).post(confirm))
+}
@@ -0,0 +1,344 @@
+//! The history of club profiles. Every published edit stores a full snapshot of the
+//! profile, which officers can compare and go back to.
+
+use super::edit::{self, ClubEdit, ClubSocialRequest, EditResponse, ProfileEdit, SocialsResponse};
+use crate::{
+    audit::Client,
+    auth::{self, Auth},
+    error::{AppError, AppResult},
+    models::{Category, ClubRevision, Role},
+    schema::*,
+    state::AppState,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use diesel::{insert_into, prelude::*};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// Who made an edit and when, stored with its revision.
+pub(super) struct RevisionInfo {
+    /// the officer who made the edit, if there was one and their account still exists
+    pub(super) edited_by: Option<i32>,
+    /// the revision the edit restores
+    pub(super) reverted_from: Option<i32>,
+    pub(super) at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevisionResponse {
+    pub id: i32,
+    /// username of the officer who made the edit, null for admin directory imports and for
+    /// the profile as it was before its first recorded edit
+    pub edited_by: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub reverted_from: Option<i32>,
+    pub club_name: String,
+    pub description: String,
+    pub about: String,
+    pub meet_time: String,
+    pub categories: Vec<String>,
+    pub socials: SocialsResponse,
+}
+
+/// A field that differs between two revisions.
+#[derive(Serialize)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub from: Value,
+    pub to: Value,
+}
+
+/// Stores the live profile of a club as a revision.
+async fn snapshot(
+    conn: &mut AsyncPgConnection,
+    club_id: i32,
+    edited_by: Option<i32>,
+    reverted_from: Option<i32>,
+    at: DateTime<Utc>,
+) -> AppResult<ClubRevision> {
+    let (club_name, description, about, meet_time) = clubs::table
+        .find(club_id)
+        .select((
+            clubs::club_name,
+            clubs::description,
+            clubs::about,
+            clubs::meet_time,
+        ))
+        .first::<(String, String, String, String)>(conn)
+        .await?;
+    let (website, google_classroom, discord, instagram) = club_socials::table
+        .filter(club_socials::club_id.eq(club_id))
+        .select((
+            club_socials::website,
+            club_socials::google_classroom,
+            club_socials::discord,
+            club_socials::instagram,
+        ))
+        .first::<(
+            Option<String>,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+        )>(conn)
+        .await
+        .optional()?
+        .unwrap_or_default();
+    let category_ids = club_categories::table
+        .filter(club_categories::club_id.eq(club_id))
+        .select(club_categories::category_id)
+        .order(club_categories::category_id)
+        .load::<i32>(conn)
+        .await?;
+
+    Ok(insert_into(club_revisions::table)
+        .values((
+            club_revisions::club_id.eq(club_id),
+            club_revisions::edited_by.eq(edited_by),
+            club_revisions::created_at.eq(at),
+            club_revisions::club_name.eq(club_name),
+            club_revisions::description.eq(description),
+            club_revisions::about.eq(about),
+            club_revisions::meet_time.eq(meet_time),
+            club_revisions::category_ids.eq(category_ids),
+            club_revisions::website.eq(website),
+            club_revisions::google_classroom.eq(google_classroom),
+            club_revisions::discord.eq(discord),
+            club_revisions::instagram.eq(instagram),
+            club_revisions::reverted_from.eq(reverted_from),
+        ))
+        .get_result::<ClubRevision>(conn)
+        .await?)
+}
+
+/// The latest revision of a club, first storing the profile as it is when no edit has
+/// been recorded yet, so that edit can be undone.
+pub(super) async fn latest(
+    conn: &mut AsyncPgConnection,
+    club_id: i32,
+    at: DateTime<Utc>,
+) -> AppResult<ClubRevision> {
+    let latest = club_revisions::table
+        .filter(club_revisions::club_id.eq(club_id))
+        .order(club_revisions::id.desc())
+        .first::<ClubRevision>(conn)
+        .await
+        .optional()?;
+
+    match latest {
+        Some(latest) => Ok(latest),
+        None => snapshot(conn, club_id, None, None, at).await,
+    }
+}
+
+/// Stores the profile of a club right after an edit.
+pub(super) async fn record(
+    conn: &mut AsyncPgConnection,
+    club_id: i32,
+    revision: RevisionInfo,
+) -> AppResult<ClubRevision> {
+    snapshot(
+        conn,
+        club_id,
+        revision.edited_by,
+        revision.reverted_from,
+        revision.at,
+    )
+    .await
+}
+
+/// Adds the usernames and category names the responses show to `revisions`.
+async fn responses(
+    conn: &mut AsyncPgConnection,
+    revisions: Vec<ClubRevision>,
+) -> AppResult<Vec<RevisionResponse>> {
+    let user_ids: Vec<i32> = revisions
+        .iter()
+        .filter_map(|revision| revision.edited_by)
+        .collect();
+
+    let users: HashMap<i32, String> = users::table
+        .filter(users::id.eq_any(user_ids))
+        .select((users::id, users::username))
+        .load::<(i32, String)>(conn)
+        .await?
+        .into_iter()
+        .collect();
+    let categories: HashMap<i32, String> = categories::table
+        .load::<Category>(conn)
+        .await?
+        .into_iter()
+        .map(|c| (c.id, c.category_name))
+        .collect();
+
+    Ok(revisions
+        .into_iter()
+        .map(|revision| {
+            // categories removed since the revision are left out
+            let mut names: Vec<String> = revision
+                .category_ids
+                .iter()
+                .filter_map(|id| categories.get(id).cloned())
+                .collect();
+            names.sort();
+
+            RevisionResponse {
+                id: revision.id,
+                edited_by: revision.edited_by.and_then(|id| users.get(&id).cloned()),
+                created_at: revision.created_at,
+                reverted_from: revision.reverted_from,
+                club_name: revision.club_name,
+                description: revision.description,
+                about: revision.about,
+                meet_time: revision.meet_time,
+                categories: names,
+                socials: SocialsResponse {
+                    website: revision.website,
+                    google_classroom: revision.google_classroom,
+                    discord: revision.discord,
+                    instagram: revision.instagram,
+                },
+            }
+        })
+        .collect())
+}
+
+async fn find(conn: &mut AsyncPgConnection, club_id: i32, id: i32) -> AppResult<ClubRevision> {
+    club_revisions::table
+        .find(id)
+        .filter(club_revisions::club_id.eq(club_id))
+        .first::<ClubRevision>(conn)
+        .await
+        .optional()?
+        .ok_or_else(|| AppError::from(StatusCode::NOT_FOUND, "the revision does not exist"))
+}
+
+/// Every field that differs between two revisions, in the order the profile shows them.
+pub fn diff(from: &RevisionResponse, to: &RevisionResponse) -> Vec<FieldChange> {
+    [
+        ("clubName", json!(from.club_name), json!(to.club_name)),
+        (
+            "description",
+            json!(from.description),
+            json!(to.description),
+        ),
+        ("about", json!(from.about), json!(to.about)),
+        ("meetTime", json!(from.meet_time), json!(to.meet_time)),
+        ("categories", json!(from.categories), json!(to.categories)),
+        (
+            "website",
+            json!(from.socials.website),
+            json!(to.socials.website),
+        ),
+        (
+            "googleClassroom",
+            json!(from.socials.google_classroom),
+            json!(to.socials.google_classroom),
+        ),
+        (
+            "discord",
+            json!(from.socials.discord),
+            json!(to.socials.discord),
+        ),
+        (
+            "instagram",
+            json!(from.socials.instagram),
+            json!(to.socials.instagram),
+        ),
+    ]
+    .into_iter()
+    .filter(|(_, from, to)| from != to)
+    .map(|(field, from, to)| FieldChange { field, from, to })
+    .collect()
+}
+
+/// The revisions of a club, newest first.
+async fn list(
+    State(state): State<AppState>,
+    Auth(auth): Auth,
+    Path(club): Path<String>,
+) -> AppResult<Json<Vec<RevisionResponse>>> {
+    let conn = &mut state.pool.get().await?;
+    let (club, _) = auth::authorize(conn, &auth, &club, Role::Editor).await?;
+
+    let revisions = club_revisions::table
+        .filter(club_revisions::club_id.eq(club.id))
+        .order(club_revisions::id.desc())
+        .load::<ClubRevision>(conn)
+        .await?;
+
+    Ok(Json(responses(conn, revisions).await?))
+}
+
+#[derive(Deserialize)]
+struct DiffQuery {
+    from: i32,
+    to: i32,
+}
+
+async fn compare(
+    State(state): State<AppState>,
+    Auth(auth): Auth,
+    Path(club): Path<String>,
+    Query(query): Query<DiffQuery>,
+) -> AppResult<Json<Vec<FieldChange>>> {
+    let conn = &mut state.pool.get().await?;
+    let (club, _) = auth::authorize(conn, &auth, &club, Role::Editor).await?;
+
+    let from = find(conn, club.id, query.from).await?;
+    let to = find(conn, club.id, query.to).await?;
+    let revisions = responses(conn, vec![from, to]).await?;
+
+    Ok(Json(diff(&revisions[0], &revisions[1])))
+}
+
+/// Makes an earlier revision the live profile again, as a new revision. Goes through
+/// moderation like any other edit.
+async fn revert(
+    State(state): State<AppState>,
+    Auth(auth): Auth,
+    client: Client,
+    Path((club, id)): Path<(String, i32)>,
+) -> AppResult<Json<EditResponse>> {
+    let conn = &mut state.pool.get().await?;
+    let (club, _) = auth::authorize(conn, &auth, &club, Role::Editor).await?;
+    let revision = find(conn, club.id, id).await?;
+
+    let edit = ProfileEdit::restore(
+        conn,
+        ClubEdit {
+            club_name: revision.club_name,
+            description: revision.description,
+            about: revision.about,
+            meet_time: revision.meet_time,
+        },
+        &revision.category_ids,
+        ClubSocialRequest {
+            website: revision.website,
+            google_classroom: revision.google_classroom,
+            discord: revision.discord,
+            instagram: revision.instagram,
+        },
+    )
+    .await?;
+
+    Ok(Json(
+        edit::save(&state, conn, &client, &club, &auth, edit, Some(revision.id)).await?,
+    ))
+}
+
+/// Routes next to [`super::edit::app`].
+pub fn app() -> Router<AppState> {
+    Router::new()
+        .route("/:club_id/revisions", get(list))
+        .route("/:club_id/revisions/diff", get(compare))
+        .route("/:club_id/revisions/:id/revert", post(revert))
+}
@@ -0,0 +1,505 @@
+use super::{auth::AuthorizedResponse, officers::RegisterRequest, password::hash_uid};
+use crate::{
+    audit::{self, Client, Entry},
+    auth::{self, Auth, Claims},
+    error::{AppError, AppResult},
+    models::{Club, ClubOfficer, LeadershipTransfer, Role},
+    schema::*,
+    state::AppState,
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use diesel::{insert_into, prelude::*, update};
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use lettre::{message::Mailbox, Address, Message};
+use nanoid::nanoid;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{collections::HashMap, time::Duration};
+
+// 7 days, like invitations
+const TRANSFER_ALLOWED_TIME: Duration = Duration::from_secs(60 * 60 * 24 * 7);
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferResponse {
+    pub id: i32,
+    pub new_email: String,
+    /// `None` when an admin started the transfer
+    pub requested_by: Option<String>,
+    pub requested_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// `None` while the new president hasn't confirmed yet
+    pub completed_at: Option<DateTime<Utc>>,
+    pub old_email: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+/// Fills in the usernames of everyone involved in `transfers`.
+async fn responses(
+    conn: &mut AsyncPgConnection,
+    transfers: Vec<LeadershipTransfer>,
+) -> QueryResult<Vec<TransferResponse>> {
+    let ids: Vec<i32> = transfers
+        .iter()
+        .flat_map(|t| [t.requested_by, t.from_user, t.to_user])
+        .flatten()
+        .collect();
+    let usernames: HashMap<i32, String> = users::table
+        .filter(users::id.eq_any(ids))
+        .select((users::id, users::username))
+        .load::<(i32, String)>(conn)
+        .await?
+        .into_iter()
+        .collect();
+    let username = |id: Option<i32>| id.and_then(|id| usernames.get(&id).cloned());
+
+    Ok(transfers
+        .into_iter()
+        .map(|t| TransferResponse {
+            id: t.id,
+            new_email: t.new_email,
+            requested_by: username(t.requested_by),
+            requested_at: t.requested_at,
+            expires_at: t.expires_at,
+            completed_at: t.completed_at,
+            old_email: t.old_email,
+            from: username(t.from_user),
+            to: username(t.to_user),
+        })
+        .collect())
+}
+
+/// Every transfer of `club`, newest first, including one that is still pending.
+pub async fn history(state: &AppState, club: &str) -> AppResult<Vec<TransferResponse>> {
+    let conn = &mut state.pool.get().await?;
+    let club = auth::find_club(conn, club).await?;
+
+    let transfers = leadership_transfers::table
+        .filter(leadership_transfers::club_id.eq(club.id))
+        .order(leadership_transfers::id.desc())
+        .load::<LeadershipTransfer>(conn)
+        .await?;
+    Ok(responses(conn, transfers).await?)
+}
+
+/// Starts handing `club` over to whoever has `new_email`, replacing a transfer that is
+/// still pending, and emails them a link to confirm. `requested_by` is `None` for admins.
+pub async fn nominate(
+    state: &AppState,
+    club: &str,
+    new_email: String,
+    requested_by: Option<&Claims>,
+) -> AppResult<TransferResponse> {
+    let conn = &mut state.pool.get().await?;
+    let club = auth::find_club(conn, club).await?;
+
+    let address = new_email
+        .parse::<Address>()
+        .map_err(|_| AppError::from(StatusCode::BAD_REQUEST, "invalid email"))?;
+    let token = nanoid!();
+    let now = state.clock.now();
+    let requested_by_id = requested_by.map(|claims| claims.user_id);
+
+    let token_hash = hash_uid(&token);
+    let club_id = club.id;
+    let transfer = conn
+        .transaction::<_, AppError, _>(|conn| {
+            Box::pin(async move {
+                diesel::delete(
+                    leadership_transfers::table
+                        .filter(leadership_transfers::club_id.eq(club_id))
+                        .filter(leadership_transfers::completed_at.is_null()),
+                )
+                .execute(conn)
+                .await?;
+
+                Ok(insert_into(leadership_transfers::table)
+                    .values((
+                        leadership_transfers::club_id.eq(club_id),
+                        leadership_transfers::new_email.eq(new_email),
+                        leadership_transfers::token_hash.eq(token_hash),
+                        leadership_transfers::requested_by.eq(requested_by_id),
+                        leadership_transfers::requested_at.eq(now),
+                        leadership_transfers::expires_at
+                            .eq(now + chrono::Duration::from_std(TRANSFER_ALLOWED_TIME)?),
+                    ))
+                    .get_result::<LeadershipTransfer>(conn)
+                    .await?)
+            })
+        })
+        .await?;
+
+    // the link only goes out once it works
+    let sender = state.clone();
+    let requested_by = requested_by.map(|claims| claims.username.clone());
+    state.outbox.send("leadership transfer", async move {
+        send_transfer_email(&sender, &club, requested_by.as_deref(), address, &token).await
+    });
+
+    Ok(responses(conn, vec![transfer])
+        .await?
+        .pop()
+        .ok_or_else(|| anyhow::anyhow!("`responses` should return one transfer"))?)
+}
+
+/// Cancels the pending transfer of `club`, if there is one.
+pub async fn cancel(state: &AppState, club: &str) -> AppResult<bool> {
+    let conn = &mut state.pool.get().await?;
+    let club = auth::find_club(conn, club).await?;
+
+    let cancelled = diesel::delete(
+        leadership_transfers::table
+            .filter(leadership_transfers::club_id.eq(club.id))
+            .filter(leadership_transfers::completed_at.is_null()),
+    )
+    .execute(conn)
+    .await?;
+    Ok(cancelled > 0)
+}
+
+/// The frontend url for the transfer `token`.
+fn transfer_link(state: &AppState, token: &str) -> String {
+    format!("{}/transfer/{}", state.config.server.frontend_host, token)
+}
+
+async fn send_transfer_email(
+    state: &AppState,
+    club: &Club,
+    requested_by: Option<&str>,
+    destination_address: Address,
+    token: &str,
+) -> anyhow::Result<()> {
+    let link = transfer_link(state, token);
+    let requested_by = requested_by.unwrap_or("A CCA Club Hub admin");
+    let body = format!(
+        r"Hi,
+
+{requested_by} would like you to take over {} on the CCA Club Hub as its president, with this address as the club's contact email. To accept, open the below link within the next {} days (or paste it into your browser if clicking is not working):
+
+{link}
+
+You can log in with an account you already have, or create a new one. Once you accept, the current president will no longer be able to manage the club. If you weren't expecting this you can disregard this message.
+
+Thanks,
+The CCA Club Hub Team.",
+        club.club_name,
+        TRANSFER_ALLOWED_TIME.as_secs() / (60 * 60 * 24),
+    );
+
+    let email = Message::builder()
+        .from(Mailbox::new(
+            Some("CCA Club Hub".to_string()),
+            state.mailer.address(),
+        ))
+        .to(Mailbox::new(None, destination_address))
+        .subject(format!("Take over {} on the CCA Club Hub", club.club_name))
+        .body(body)?;
+
+    state.mailer.send(email).await
+}
+
+/// Tells the old contact email of a club that it has been handed over, in case that
+/// wasn't expected.
+async fn send_transferred_email(
+    state: &AppState,
+    club: Club,
+    new_president: String,
+    new_email: String,
+) -> anyhow::Result<()> {
+    let body = format!(
+        r"Hi,
+
+{} on the CCA Club Hub has been handed over to {new_president}, and its contact email is now {new_email}. Thank you for leading it!
+
+If this wasn't expected, please let us know.
+
+Thanks,
+The CCA Club Hub Team.",
+        club.club_name
+    );
+
+    let email = Message::builder()
+        .from(Mailbox::new(
+            Some("CCA Club Hub".to_string()),
+            state.mailer.address(),
+        ))
+        .to(Mailbox::new(None, club.email.parse::<Address>()?))
+        .subject(format!("{} has a new president", club.club_name))
+        .body(body)?;
+
+    state.mailer.send(email).await
+}
+
+/// Looks up the pending transfer with the link `token`, as long as it hasn't expired.
+async fn find_transfer(
+    state: &AppState,
+    conn: &mut AsyncPgConnection,
+    token: &str,
+) -> AppResult<(LeadershipTransfer, Club)> {
+    let (transfer, club) = leadership_transfers::table
+        .inner_join(clubs::table)
+        .filter(leadership_transfers::token_hash.eq(hash_uid(token)))
+        .filter(leadership_transfers::completed_at.is_null())
+        .first::<(LeadershipTransfer, Club)>(conn)
+        .await
+        .optional()?
+        .ok_or_else(|| AppError::from(StatusCode::BAD_REQUEST, "invalid transfer link"))?;
+
+    if state.clock.now() > transfer.expires_at {
+        return Err(AppError::from(StatusCode::BAD_REQUEST, "transfer expired"));
+    }
+    Ok((transfer, club))
+}
+
+/// Makes `user_id` the president of the club and switches its contact email over. The old
+/// president is no longer an officer, and is logged out everywhere.
+async fn complete(
+    conn: &mut AsyncPgConnection,
+    transfer: &LeadershipTransfer,
+    club: &Club,
+    user_id: i32,
+    now: DateTime<Utc>,
+) -> AppResult<()> {
+    // whoever clears the token first completes it, so a link can't be used twice at once
+    let claimed = update(
+        leadership_transfers::table
+            .find(transfer.id)
+            .filter(leadership_transfers::completed_at.is_null()),
+    )
+    .set((
+        leadership_transfers::token_hash.eq(None::<String>),
+        leadership_transfers::completed_at.eq(now),
+    ))
+    .execute(conn)
+    .await?;
+    if claimed == 0 {
+        return Err(AppError::from(
+            StatusCode::BAD_REQUEST,
+            "invalid transfer link",
+        ));
+    }
+
+    let old_president = club_officers::table
+        .filter(club_officers::club_id.eq(club.id))
+        .filter(club_officers::role.eq(Role::President))
+        .select(club_officers::user_id)
+        .first::<i32>(conn)
+        .await
+        .optional()?;
+    if old_president == Some(user_id) {
+        return Err(AppError::from(
+            StatusCode::CONFLICT,
+            "you are already the president of this club",
+        ));
+    }
+    if let Some(old_president) = old_president {
+        diesel::delete(club_officers::table.find((club.id, old_president)))
+            .execute(conn)
+            .await?;
+        auth::revoke_sessions(conn, old_president, now).await?;
+    }
+
+    insert_into(club_officers::table)
+        .values(ClubOfficer {
+            club_id: club.id,
+            user_id,
+            role: Role::President,
+        })
+        .on_conflict((club_officers::club_id, club_officers::user_id))
+        .do_update()
+        .set(club_officers::role.eq(Role::President))
+        .execute(conn)
+        .await?;
+
+    update(clubs::table.find(club.id))
+        .set(clubs::email.eq(&transfer.new_email))
+        .execute(conn)
+        .await?;
+
+    update(leadership_transfers::table.find(transfer.id))
+        .set((
+            leadership_transfers::old_email.eq(&club.email),
+            leadership_transfers::from_user.eq(old_president),
+            leadership_transfers::to_user.eq(user_id),
+        ))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+fn notify_old_contact(state: &AppState, club: Club, new_president: String, new_email: String) {
+    let sender = state.clone();
+    state.outbox.send("leadership transferred", async move {
+        send_transferred_email(&sender, club, new_president, new_email).await
+    });
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TransferDetailsResponse {
+    club: String,
+    club_name: String,
+    new_email: String,
+    expires_at: DateTime<Utc>,
+}
+
+/// What the transfer page shows before it is confirmed.
+async fn transfer_details(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> AppResult<Json<TransferDetailsResponse>> {
+    let conn = &mut state.pool.get().await?;
+    let (transfer, club) = find_transfer(&state, conn, &token).await?;
+
+    Ok(Json(TransferDetailsResponse {
+        club: club.username,
+        club_name: club.club_name,
+        new_email: transfer.new_email,
+        expires_at: transfer.expires_at,
+    }))
+}
+
+/// Confirms a transfer with the account of the logged in user.
+async fn accept(
+    State(state): State<AppState>,
+    Auth(auth): Auth,
+    client: Client,
+    Path(token): Path<String>,
+) -> AppResult<()> {
+    let conn = &mut state.pool.get().await?;
+    let (transfer, club) = find_transfer(&state, conn, &token).await?;
+    let now = state.clock.now();
+
+    let new_email = transfer.new_email.clone();
+    conn.transaction::<_, AppError, _>(|conn| {
+        let club = club.clone();
+        Box::pin(async move { complete(conn, &transfer, &club, auth.user_id, now).await })
+    })
+    .await?;
+
+    let entry = Entry::new(&auth, "transfer.complete")
+        .club(&club)
+        .before(json!({ "email": club.email }))
+        .after(json!({ "email": new_email }));
+    audit::record(&state, &client, entry).await;
+    notify_old_contact(&state, club, auth.username, new_email);
+    Ok(())
+}
+
+/// Confirms a transfer by creating a new account with the new contact email, and logs it in.
+async fn register(
+    State(state): State<AppState>,
+    client: Client,
+    Path(token): Path<String>,
+    Json(req): Json<RegisterRequest>,
+) -> AppResult<Json<AuthorizedResponse>> {
+    let conn = &mut state.pool.get().await?;
+    let (transfer, club) = find_transfer(&state, conn, &token).await?;
+    let password_hash = req.hash_password(&state, &club)?;
+    let now = state.clock.now();
+
+    let new_email = transfer.new_email.clone();
+    let user = conn
+        .transaction::<_, AppError, _>(|conn| {
+            let club = club.clone();
+            Box::pin(async move {
+                let user = req
+                    .insert(conn, transfer.new_email.clone(), password_hash)
+                    .await?;
+                complete(conn, &transfer, &club, user.id, now).await?;
+                Ok(user)
+            })
+        })
+        .await?;
+
+    let entry = Entry::new(&user, "transfer.complete")
+        .club(&club)
+        .before(json!({ "email": club.email }))
+        .after(json!({ "email": new_email }));
+    audit::record(&state, &client, entry).await;
+    notify_old_contact(&state, club, user.username.clone(), new_email);
+    Ok(Json(
+        AuthorizedResponse::for_user(&state, conn, &user).await?,
+    ))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NominateRequest {
+    pub email: String,
+}
+
+async fn president_history(
+    State(state): State<AppState>,
+    Auth(auth): Auth,
+    Path(club): Path<String>,
+) -> AppResult<Json<Vec<TransferResponse>>> {
+    let conn = &mut state.pool.get().await?;
+    auth::authorize(conn, &auth, &club, Role::President).await?;
+    Ok(Json(history(&state, &club).await?))
+}
+
+async fn president_nominate(
+    State(state): State<AppState>,
+    Auth(auth): Auth,
+    client: Client,
+    Path(club): Path<String>,
+    Json(req): Json<NominateRequest>,
+) -> AppResult<Json<TransferResponse>> {
+    let conn = &mut state.pool.get().await?;
+    let (club, _) = auth::authorize(conn, &auth, &club, Role::President).await?;
+    let transfer = nominate(&state, &club.username, req.email, Some(&auth)).await?;
+    let entry = Entry::new(&auth, "transfer.nominate")
+        .club(&club)
+        .after(&transfer);
+    audit::record(&state, &client, entry).await;
+    Ok(Json(transfer))
+}
+
+async fn president_cancel(
+    State(state): State<AppState>,
+    Auth(auth): Auth,
+    client: Client,
+    Path(club): Path<String>,
+) -> AppResult<()> {
+    let conn = &mut state.pool.get().await?;
+    let (club, _) = auth::authorize(conn, &auth, &club, Role::President).await?;
+    if !cancel(&state, &club.username).await? {
+        return Err(AppError::from(
+            StatusCode::NOT_FOUND,
+            "the club has no pending transfer",
+        ));
+    }
+    audit::record(
+        &state,
+        &client,
+        Entry::new(&auth, "transfer.cancel").club(&club),
+    )
+    .await;
+    Ok(())
+}
+
+/// Routes for a club's president, next to [`super::officers::app`].
+pub fn app() -> Router<AppState> {
+    Router::new()
+        .route("/:club/transfers", get(president_history))
+        .route(
+            "/:club/transfer",
+            post(president_nominate).delete(president_cancel),
+        )
+}
+
+/// Routes for whoever was nominated, who may not have an account yet.
+pub fn confirm_app() -> Router<AppState> {
+    Router::new()
+        .route("/:token", get(transfer_details))
+        .route("/:token/accept", post(accept))
+        .route("/:token/register", post(register))
+}
@@ -0,0 +1,493 @@
+use super::auth::AuthorizedResponse;
+use crate::{
+    audit::{self, Client, Entry},
+    auth::{self, Auth},
+    error::{AppError, AppResult},
+    models::{User, UserTotp},
+    rate_limit::{self, ClientIp},
+    schema::*,
+    state::AppState,
+    totp,
+};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use diesel::{delete, insert_into, prelude::*, sql_types::*, update, PgArrayExpressionMethods};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use itertools::Itertools;
+use lettre::{message::Mailbox, Address, Message};
+use nanoid::nanoid;
+use qrcode::{render::svg, QrCode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+// 5 minutes to type in a code after the password
+const CHALLENGE_ALLOWED_TIME: Duration = Duration::from_secs(5 * 60);
+const RECOVERY_CODES: usize = 10;
+// no 0/o, 1/l/i, so codes can be copied from paper
+const RECOVERY_CODE_ALPHABET: [char; 31] = [
+    '2', '3', '4', '5', '6', '7', '8', '9', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'j', 'k', 'm',
+    'n', 'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z',
+];
+
+sql_function!(fn array_remove(array: Array<Text>, element: Text) -> Array<Text>);
+
+/// What a user gets instead of a token after their password when they have two-factor
+/// authentication, to trade in at `/api/auth/2fa/login` together with a code.
+/// The fields don't overlap with [`auth::Claims`], so neither passes for the other.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChallengeClaims {
+    two_factor_user_id: i32,
+    exp: u64,
+}
+
+/// A challenge for `user`, who has already given the right password.
+pub(crate) fn challenge(state: &AppState, user: &User) -> anyhow::Result<String> {
+    auth::sign(
+        &state.keys,
+        state.clock.as_ref(),
+        &ChallengeClaims {
+            two_factor_user_id: user.id,
+            exp: state.clock.timestamp() + CHALLENGE_ALLOWED_TIME.as_secs(),
+        },
+    )
+}
+
+/// Whether logging in as `user_id` needs a code as well as the password.
+pub(crate) async fn is_enabled(conn: &mut AsyncPgConnection, user_id: i32) -> QueryResult<bool> {
+    Ok(user_totp::table
+        .find(user_id)
+        .select(user_totp::enabled)
+        .first::<bool>(conn)
+        .await
+        .optional()?
+        .unwrap_or(false))
+}
+
+/// Turns two-factor authentication off for `username`, for users who lost their
+/// authenticator and recovery codes. Returns whether it was on.
+pub async fn reset(state: &AppState, username: &str) -> AppResult<bool> {
+    let conn = &mut state.pool.get().await?;
+    let user = users::table
+        .filter(users::username.eq(username))
+        .first::<User>(conn)
+        .await
+        .optional()?
+        .ok_or_else(|| {
+            AppError::from(
+                StatusCode::NOT_FOUND,
+                format!("no user with username {username}"),
+            )
+        })?;
+
+    // also drops an enrollment that was never confirmed
+    let was_enabled = delete(user_totp::table.find(user.id))
+        .returning(user_totp::enabled)
+        .get_result::<bool>(conn)
+        .await
+        .optional()?
+        .unwrap_or(false);
+    if was_enabled {
+        let sender = state.clone();
+        state.outbox.send("two-factor", async move {
+            let message = "was turned off by a CCA Club Hub admin";
+            send_two_factor_email(&sender, user, message).await
+        });
+    }
+    Ok(was_enabled)
+}
+
+fn hash_recovery_code(code: &str) -> String {
+    // ignore the dash and case people might add or drop when typing it in
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    format!(
+        "{:02x}",
+        Sha256::digest(normalized.as_bytes()).iter().format("")
+    )
+}
+
+/// New recovery codes, and the hashes they are stored as.
+fn generate_recovery_codes() -> (Vec<String>, Vec<String>) {
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            let code = nanoid!(10, &RECOVERY_CODE_ALPHABET);
+            let code = format!("{}-{}", &code[..5], &code[5..]);
+            let hash = hash_recovery_code(&code);
+            (code, hash)
+        })
+        .unzip()
+}
+
+/// Checks `code` against the authenticator or, once enabled, the recovery codes. Either
+/// only works once: authenticator codes older than the last one used are refused, and
+/// recovery codes are removed. Counts against the user's code limit so a stolen token
+/// or challenge can't be used to guess.
+async fn check_code(
+    state: &AppState,
+    conn: &mut AsyncPgConnection,
+    totp: &UserTotp,
+    code: &str,
+) -> AppResult<bool> {
+    let limit = &state.config.rate_limit.login;
+    rate_limit::hit(state, &format!("2fa:{}", totp.user_id), limit).await?;
+
+    let last_used_step = u64::try_from(totp.last_used_step).unwrap_or(0);
+    if let Some(step) = totp::verify(&totp.secret, code, state.clock.timestamp(), last_used_step) {
+        // another request may have used the same code in the meantime
+        let step = step as i64;
+        let updated = update(
+            user_totp::table
+                .find(totp.user_id)
+                .filter(user_totp::last_used_step.lt(step)),
+        )
+        .set(user_totp::last_used_step.eq(step))
+        .execute(conn)
+        .await?;
+        return Ok(updated > 0);
+    }
+
+    if !totp.enabled {
+        return Ok(false);
+    }
+    let hash = hash_recovery_code(code);
+    let updated = update(
+        user_totp::table
+            .find(totp.user_id)
+            .filter(user_totp::recovery_codes.contains(vec![hash.clone()])),
+    )
+    .set(user_totp::recovery_codes.eq(array_remove(user_totp::recovery_codes, hash)))
+    .execute(conn)
+    .await?;
+    Ok(updated > 0)
+}
+
+fn incorrect_code() -> AppError {
+    AppError::from(StatusCode::UNAUTHORIZED, "incorrect code")
+}
+
+async fn load(conn: &mut AsyncPgConnection, user_id: i32) -> QueryResult<Option<UserTotp>> {
+    user_totp::table
+        .find(user_id)
+        .first::<UserTotp>(conn)
+        .await
+        .optional()
+}
+
+/// Loads the settings of a user who has two-factor authentication on.
+async fn load_enabled(conn: &mut AsyncPgConnection, user_id: i32) -> AppResult<UserTotp> {
+    load(conn, user_id)
+        .await?
+        .filter(|totp| totp.enabled)
+        .ok_or_else(|| {
+            AppError::from(
+                StatusCode::CONFLICT,
+                "two-factor authentication is not enabled",
+            )
+        })
+}
+
+/// Checks the password of the user a token belongs to, for changes that turn
+/// protection on or off.
+async fn check_password(
+    state: &AppState,
+    conn: &mut AsyncPgConnection,
+    user_id: i32,
+    password: &str,
+) -> AppResult<User> {
+    let limit = &state.config.rate_limit.login;
+    rate_limit::hit(state, &format!("password-change:{user_id}"), limit).await?;
+
+    let user = users::table.find(user_id).first::<User>(conn).await?;
+    if !auth::verify_password(password, &user.password_hash)? {
+        return Err(AppError::from(
+            StatusCode::UNAUTHORIZED,
+            "incorrect password",
+        ));
+    }
+    Ok(user)
+}
+
+#[derive(Deserialize)]
+struct EnrollRequest {
+    password: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EnrollResponse {
+    /// for typing into apps that can't scan
+    secret: String,
+    uri: String,
+    qr_svg: String,
+}
+
+/// Starts enrollment with a new secret. Nothing changes for logins until it is confirmed.
+async fn enroll(
+    State(state): State<AppState>,
+    Auth(auth): Auth,
+    Json(req): Json<EnrollRequest>,
+) -> AppResult<Json<EnrollResponse>> {
+    let conn = &mut state.pool.get().await?;
+    let user = check_password(&state, conn, auth.user_id, &req.password).await?;
+    if is_enabled(conn, user.id).await? {
+        return Err(AppError::from(
+            StatusCode::CONFLICT,
+            "two-factor authentication is already enabled",
+        ));
+    }
+
+    let secret = totp::generate_secret();
+    let pending = UserTotp {
+        user_id: user.id,
+        secret: secret.clone(),
+        enabled: false,
+        last_used_step: 0,
+        recovery_codes: Vec::new(),
+    };
+    insert_into(user_totp::table)
+        .values(&pending)
+        .on_conflict(user_totp::user_id)
+        .do_update()
+        .set((
+            user_totp::secret.eq(&pending.secret),
+            user_totp::last_used_step.eq(0),
+        ))
+        .execute(conn)
+        .await?;
+
+    let uri = totp::provisioning_uri(&secret, &user.username);
+    let qr_svg = QrCode::new(uri.as_bytes())?.render::<svg::Color>().build();
+    Ok(Json(EnrollResponse {
+        secret,
+        uri,
+        qr_svg,
+    }))
+}
+
+#[derive(Deserialize)]
+struct CodeRequest {
+    code: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RecoveryCodesResponse {
+    /// only ever shown here, they are stored hashed
+    recovery_codes: Vec<String>,
+}
+
+/// Turns two-factor authentication on once the user shows their app makes the right codes.
+async fn confirm(
+    State(state): State<AppState>,
+    Auth(auth): Auth,
+    client: Client,
+    Json(req): Json<CodeRequest>,
+) -> AppResult<Json<RecoveryCodesResponse>> {
+    let conn = &mut state.pool.get().await?;
+    let totp = match load(conn, auth.user_id).await? {
+        Some(totp) if totp.enabled => {
+            return Err(AppError::from(
+                StatusCode::CONFLICT,
+                "two-factor authentication is already enabled",
+            ))
+        }
+        Some(totp) => totp,
+        None => {
+            return Err(AppError::from(
+                StatusCode::CONFLICT,
+                "two-factor authentication hasn't been enrolled",
+            ))
+        }
+    };
+    if !check_code(&state, conn, &totp, &req.code).await? {
+        return Err(incorrect_code());
+    }
+
+    let (codes, hashes) = generate_recovery_codes();
+    update(user_totp::table.find(totp.user_id))
+        .set((
+            user_totp::enabled.eq(true),
+            user_totp::recovery_codes.eq(hashes),
+        ))
+        .execute(conn)
+        .await?;
+
+    let user = users::table.find(totp.user_id).first::<User>(conn).await?;
+    audit::record(&state, &client, Entry::new(&user, "2fa.enable")).await;
+    let sender = state.clone();
+    state.outbox.send("two-factor", async move {
+        send_two_factor_email(&sender, user, "was turned on").await
+    });
+
+    Ok(Json(RecoveryCodesResponse {
+        recovery_codes: codes,
+    }))
+}
+
+/// Replaces every recovery code, e.g. once most have been used.
+async fn regenerate_recovery_codes(
+    State(state): State<AppState>,
+    Auth(auth): Auth,
+    client: Client,
+    Json(req): Json<CodeRequest>,
+) -> AppResult<Json<RecoveryCodesResponse>> {
+    let conn = &mut state.pool.get().await?;
+    let totp = load_enabled(conn, auth.user_id).await?;
+    if !check_code(&state, conn, &totp, &req.code).await? {
+        return Err(incorrect_code());
+    }
+
+    let (codes, hashes) = generate_recovery_codes();
+    update(user_totp::table.find(totp.user_id))
+        .set(user_totp::recovery_codes.eq(hashes))
+        .execute(conn)
+        .await?;
+    audit::record(&state, &client, Entry::new(&auth, "2fa.recovery_codes")).await;
+
+    Ok(Json(RecoveryCodesResponse {
+        recovery_codes: codes,
+    }))
+}
+
+#[derive(Deserialize)]
+struct DisableRequest {
+    password: String,
+    code: String,
+}
+
+async fn disable(
+    State(state): State<AppState>,
+    Auth(auth): Auth,
+    client: Client,
+    Json(req): Json<DisableRequest>,
+) -> AppResult<()> {
+    let conn = &mut state.pool.get().await?;
+    let user = check_password(&state, conn, auth.user_id, &req.password).await?;
+    let totp = load_enabled(conn, user.id).await?;
+    if !check_code(&state, conn, &totp, &req.code).await? {
+        return Err(incorrect_code());
+    }
+
+    delete(user_totp::table.find(user.id)).execute(conn).await?;
+    audit::record(&state, &client, Entry::new(&user, "2fa.disable")).await;
+
+    let sender = state.clone();
+    state.outbox.send("two-factor", async move {
+        send_two_factor_email(&sender, user, "was turned off").await
+    });
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StatusResponse {
+    enabled: bool,
+    recovery_codes_left: usize,
+}
+
+async fn status(
+    State(state): State<AppState>,
+    Auth(auth): Auth,
+) -> AppResult<Json<StatusResponse>> {
+    let conn = &mut state.pool.get().await?;
+    let totp = load(conn, auth.user_id).await?.filter(|totp| totp.enabled);
+    Ok(Json(StatusResponse {
+        enabled: totp.is_some(),
+        recovery_codes_left: totp.map_or(0, |totp| totp.recovery_codes.len()),
+    }))
+}
+
+#[derive(Deserialize)]
+struct LoginRequest {
+    challenge: String,
+    code: String,
+}
+
+/// The second step of logging in, trading a challenge and a code for a token.
+async fn login(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    client: Client,
+    Json(req): Json<LoginRequest>,
+) -> AppResult<Json<AuthorizedResponse>> {
+    let limit = &state.config.rate_limit.login;
+    rate_limit::hit(&state, &format!("login-ip:{ip}"), limit).await?;
+
+    let challenge =
+        auth::verify::<ChallengeClaims>(&state.keys, state.clock.as_ref(), &req.challenge)?;
+    if challenge.exp < state.clock.timestamp() {
+        return Err(AppError::from(
+            StatusCode::UNAUTHORIZED,
+            "challenge expired",
+        ));
+    }
+
+    let conn = &mut state.pool.get().await?;
+    let user = users::table
+        .find(challenge.two_factor_user_id)
+        .first::<User>(conn)
+        .await?;
+    rate_limit::check_lockout(&state, &user.username).await?;
+    auth::check_clubs_open(conn, user.id).await?;
+    let totp = load_enabled(conn, user.id).await?;
+
+    if !check_code(&state, conn, &totp, &req.code).await? {
+        rate_limit::record_failed_login(&state, &user.username).await?;
+        audit::record(&state, &client, Entry::new(&user, "auth.login_failed")).await;
+        return Err(incorrect_code());
+    }
+
+    rate_limit::clear_failed_logins(&state, &user.username).await?;
+    let response = AuthorizedResponse::for_user(&state, conn, &user).await?;
+    audit::record(&state, &client, Entry::new(&user, "auth.login")).await;
+    Ok(Json(response))
+}
+
+/// Tells a user their two-factor authentication changed, in case it wasn't them.
+async fn send_two_factor_email(state: &AppState, user: User, message: &str) -> anyhow::Result<()> {
+    let body = format!(
+        r"Hi {},
+
+Two-factor authentication for your CCA Club Hub account {message}.
+
+If this wasn't you, please reset your password right away and let us know.
+
+Thanks,
+The CCA Club Hub Team.",
+        user.username
+    );
+
+    let email = Message::builder()
+        .from(Mailbox::new(
+            Some("CCA Club Hub".to_string()),
+            state.mailer.address(),
+        ))
+        .to(Mailbox::new(
+            Some(user.username),
+            user.email.parse::<Address>()?,
+        ))
+        .subject("Two-factor authentication for your CCA Club Hub account changed")
+        .body(body)?;
+
+    state.mailer.send(email).await
+}
+
+pub fn app() -> Router<AppState> {
+    Router::new()
+        .route("/enroll", post(enroll))
+        .route("/confirm", post(confirm))
+        .route("/recovery-codes", post(regenerate_recovery_codes))
+        .route("/disable", post(disable))
+        .route("/status", get(status))
+        .route("/login", post(login))
+}
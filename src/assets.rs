@@ -0,0 +1,82 @@
+use axum::async_trait;
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// Storage for uploaded files. Urls returned by [`AssetStore::put`] are what gets
+/// stored in the database and served to clients.
+#[async_trait]
+pub trait AssetStore: Send + Sync {
+    /// Stores `bytes` under `name` (e.g. `pfp/<hash>.png`) and returns its url.
+    async fn put(&self, name: &str, bytes: &[u8]) -> io::Result<String>;
+
+    async fn get(&self, url: &str) -> io::Result<Vec<u8>>;
+
+    async fn remove(&self, url: &str) -> io::Result<()>;
+
+    /// Lists the urls and modification times of every asset under `dir`.
+    async fn list(&self, dir: &str) -> io::Result<Vec<(String, SystemTime)>>;
+
+    /// The name `url` was stored under, or `None` if the url isn't from this store.
+    fn name_of<'a>(&self, url: &'a str) -> Option<&'a str>;
+}
+
+/// Stores assets on the local disk, served from `/assets`.
+pub struct LocalAssetStore {
+    root: PathBuf,
+}
+
+impl LocalAssetStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn url(&self, path: PathBuf) -> io::Result<String> {
+        path.to_str()
+            .map(str::to_string)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "path is not utf-8"))
+    }
+}
+
+#[async_trait]
+impl AssetStore for LocalAssetStore {
+    async fn put(&self, name: &str, bytes: &[u8]) -> io::Result<String> {
+        let path = self.root.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        File::create(&path)?.write_all(bytes)?;
+        self.url(path)
+    }
+
+    async fn get(&self, url: &str) -> io::Result<Vec<u8>> {
+        fs::read(url)
+    }
+
+    async fn remove(&self, url: &str) -> io::Result<()> {
+        fs::remove_file(url)
+    }
+
+    async fn list(&self, dir: &str) -> io::Result<Vec<(String, SystemTime)>> {
+        let dir = self.root.join(dir);
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        fs::read_dir(dir)?
+            .map(|entry| {
+                let path = entry?.path();
+                let modified = fs::metadata(&path)?.modified()?;
+                Ok((self.url(path)?, modified))
+            })
+            .collect()
+    }
+
+    fn name_of<'a>(&self, url: &'a str) -> Option<&'a str> {
+        Path::new(url).strip_prefix(&self.root).ok()?.to_str()
+    }
+}
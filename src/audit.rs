@@ -0,0 +1,285 @@
+//! A record of privileged and mutating actions: who did what to which club, from where,
+//! and what changed.
+
+use crate::{
+    auth::{self, Claims},
+    error::{AppError, AppResult},
+    models::{AuditEntry, Club, User},
+    rate_limit::ClientIp,
+    schema::*,
+    state::AppState,
+};
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header::USER_AGENT, request::Parts, StatusCode},
+};
+use chrono::{DateTime, Utc};
+use diesel::{insert_into, prelude::*};
+use diesel_async::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::convert::Infallible;
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+/// Where a request came from, for the audit log.
+#[derive(Debug, Clone, Default)]
+pub struct Client {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for Client {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let Ok(ClientIp(ip)) = ClientIp::from_request_parts(parts, state).await;
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+
+        Ok(Client {
+            ip: Some(ip).filter(|ip| ip != "unknown"),
+            user_agent,
+        })
+    }
+}
+
+/// Who did something.
+pub enum Actor {
+    User {
+        id: i32,
+        username: String,
+    },
+    /// whoever has the admin key
+    Admin,
+    /// someone who isn't logged in, by the username or email they gave
+    Anonymous(String),
+    /// a background job
+    System,
+    /// someone running `cca-admin` on the server
+    Cli,
+}
+
+impl From<&Claims> for Actor {
+    fn from(claims: &Claims) -> Self {
+        Actor::User {
+            id: claims.user_id,
+            username: claims.username.clone(),
+        }
+    }
+}
+
+impl From<&User> for Actor {
+    fn from(user: &User) -> Self {
+        Actor::User {
+            id: user.id,
+            username: user.username.clone(),
+        }
+    }
+}
+
+/// An action to record, built up with the methods below and written by [`record`].
+pub struct Entry {
+    actor: Actor,
+    action: &'static str,
+    club_id: Option<i32>,
+    /// username of the club, for callers that don't have it loaded
+    club_username: Option<String>,
+    target: Option<String>,
+    before: Option<Value>,
+    after: Option<Value>,
+}
+
+impl Entry {
+    /// `action` is named like `club.edit`, the area then what was done.
+    pub fn new(actor: impl Into<Actor>, action: &'static str) -> Self {
+        Entry {
+            actor: actor.into(),
+            action,
+            club_id: None,
+            club_username: None,
+            target: None,
+            before: None,
+            after: None,
+        }
+    }
+
+    /// The club acted on, which is also the target unless another one is given.
+    pub fn club(mut self, club: &Club) -> Self {
+        self.club_id = Some(club.id);
+        self.target.get_or_insert_with(|| club.username.clone());
+        self
+    }
+
+    /// Like [`Entry::club`], for the club with `username`.
+    pub fn club_named(mut self, username: impl Into<String>) -> Self {
+        let username = username.into();
+        self.target.get_or_insert_with(|| username.clone());
+        self.club_username = Some(username);
+        self
+    }
+
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    pub fn before(mut self, before: impl Serialize) -> Self {
+        self.before = serde_json::to_value(before).ok();
+        self
+    }
+
+    pub fn after(mut self, after: impl Serialize) -> Self {
+        self.after = serde_json::to_value(after).ok();
+        self
+    }
+}
+
+/// Writes `entry` to the audit log. The action has already happened by now, so a
+/// failure is only logged rather than failing the request.
+pub async fn record(state: &AppState, client: &Client, entry: Entry) {
+    if let Err(e) = insert(state, client, entry).await {
+        eprintln!("failed to write to the audit log: {e}");
+    }
+}
+
+async fn insert(state: &AppState, client: &Client, entry: Entry) -> anyhow::Result<()> {
+    let (actor_id, actor) = match entry.actor {
+        Actor::User { id, username } => (Some(id), username),
+        Actor::Admin => (None, "admin".to_string()),
+        Actor::Anonymous(name) => (None, name),
+        Actor::System => (None, "system".to_string()),
+        Actor::Cli => (None, "cli".to_string()),
+    };
+
+    let conn = &mut state.pool.get().await?;
+    let club_id = match (entry.club_id, entry.club_username) {
+        (None, Some(username)) => clubs::table
+            .filter(clubs::username.eq(username))
+            .select(clubs::id)
+            .first::<i32>(conn)
+            .await
+            .optional()?,
+        (club_id, _) => club_id,
+    };
+
+    insert_into(audit_log::table)
+        .values((
+            audit_log::created_at.eq(state.clock.now()),
+            audit_log::actor_id.eq(actor_id),
+            audit_log::actor.eq(actor),
+            audit_log::action.eq(entry.action),
+            audit_log::club_id.eq(club_id),
+            audit_log::target.eq(entry.target),
+            audit_log::ip.eq(&client.ip),
+            audit_log::user_agent.eq(&client.user_agent),
+            audit_log::before.eq(entry.before),
+            audit_log::after.eq(entry.after),
+        ))
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// What to search the audit log for. Every filter given has to match.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditQuery {
+    /// username of the club acted on
+    pub club: Option<String>,
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    /// entries at or after this time
+    pub from: Option<DateTime<Utc>>,
+    /// entries before this time
+    pub to: Option<DateTime<Utc>>,
+    /// entries older than the one with this id, for paging
+    pub before_id: Option<i32>,
+    /// at most this many entries, 100 by default and 1000 at most
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditResponse {
+    pub id: i32,
+    pub created_at: DateTime<Utc>,
+    pub actor_id: Option<i32>,
+    pub actor: String,
+    pub action: String,
+    /// username of the club, null if it was deleted
+    pub club: Option<String>,
+    pub target: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+/// Entries matching `query`, newest first.
+pub async fn search(state: &AppState, query: AuditQuery) -> AppResult<Vec<AuditResponse>> {
+    let conn = &mut state.pool.get().await?;
+
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(AppError::from(
+            StatusCode::BAD_REQUEST,
+            format!("limit must be between 1 and {MAX_LIMIT}"),
+        ));
+    }
+
+    let mut entries = audit_log::table
+        .left_join(clubs::table)
+        .select((audit_log::all_columns, clubs::username.nullable()))
+        .order(audit_log::id.desc())
+        .limit(limit)
+        .into_boxed();
+    if let Some(club) = &query.club {
+        let club = auth::find_club(conn, club).await?;
+        entries = entries.filter(audit_log::club_id.eq(club.id));
+    }
+    if let Some(actor) = query.actor {
+        entries = entries.filter(audit_log::actor.eq(actor));
+    }
+    if let Some(action) = query.action {
+        entries = entries.filter(audit_log::action.eq(action));
+    }
+    if let Some(from) = query.from {
+        entries = entries.filter(audit_log::created_at.ge(from));
+    }
+    if let Some(to) = query.to {
+        entries = entries.filter(audit_log::created_at.lt(to));
+    }
+    if let Some(before_id) = query.before_id {
+        entries = entries.filter(audit_log::id.lt(before_id));
+    }
+
+    Ok(entries
+        .load::<(AuditEntry, Option<String>)>(conn)
+        .await?
+        .into_iter()
+        .map(|(entry, club)| AuditResponse {
+            id: entry.id,
+            created_at: entry.created_at,
+            actor_id: entry.actor_id,
+            actor: entry.actor,
+            action: entry.action,
+            club,
+            target: entry.target,
+            ip: entry.ip,
+            user_agent: entry.user_agent,
+            before: entry.before,
+            after: entry.after,
+        })
+        .collect())
+}
@@ -1,116 +1,379 @@
 use crate::{
+    clock::Clock,
+    config::PasswordHashConfig,
     error::{AppError, AppResult, ResponseStatusError},
-    models::Club,
+    keyring::Keys,
+    models::{Club, ClubStatus, Role, User},
+    password_policy,
+    rate_limit::{self, ClientIp},
+    schema::*,
+    state::AppState,
 };
-use argon2::Argon2;
+use anyhow::anyhow;
+use argon2::{Algorithm, Argon2, Params, Version};
 use axum::{
     async_trait,
-    extract::{FromRequest, RequestParts},
+    extract::FromRequestParts,
     headers::{authorization::Bearer, Authorization},
-    http::StatusCode,
+    http::{request::Parts, StatusCode},
     TypedHeader,
 };
-use jsonwebtoken::{errors::Result as JwtResult, DecodingKey, EncodingKey};
+use chrono::{DateTime, Datelike, Utc};
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use jsonwebtoken::{Header, Validation};
 use password_hash::{
     self, rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
 };
-use serde::{Deserialize, Serialize};
-use std::{ops::Deref, time::Duration};
-
-pub fn hash_password(password: impl AsRef<[u8]>) -> password_hash::Result<String> {
-    let salt = SaltString::generate(&mut OsRng);
-    Argon2::default()
-        .hash_password(password.as_ref(), &salt)
-        .map(|h| h.to_string())
-}
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{fmt, time::Duration};
 
 pub fn verify_password(
     password: impl AsRef<[u8]>,
     password_hash: impl AsRef<str>,
 ) -> password_hash::Result<bool> {
     let parsed_hash = PasswordHash::new(password_hash.as_ref())?;
+    // the algorithm and params are read from the hash itself
     Ok(Argon2::default()
         .verify_password(password.as_ref(), &parsed_hash)
         .is_ok())
 }
 
-struct Keys {
-    encoding: EncodingKey,
-    decoding: DecodingKey,
+/// The algorithm, version and costs a password hash was made with.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct HashSettings {
+    pub algorithm: String,
+    pub version: u32,
+    pub memory_cost: u32,
+    pub time_cost: u32,
+    pub parallelism: u32,
+}
+
+impl HashSettings {
+    /// Reads the settings out of a PHC hash string, `None` if it isn't an argon2 hash.
+    pub fn of(password_hash: &str) -> Option<HashSettings> {
+        let hash = PasswordHash::new(password_hash).ok()?;
+        let algorithm = Algorithm::try_from(hash.algorithm).ok()?;
+        let params = Params::try_from(&hash).ok()?;
+        Some(HashSettings {
+            algorithm: algorithm.as_str().to_string(),
+            // argon2 treats a missing version as the latest one
+            version: hash.version.unwrap_or(Version::default() as u32),
+            memory_cost: params.m_cost(),
+            time_cost: params.t_cost(),
+            parallelism: params.p_cost(),
+        })
+    }
+}
+
+impl fmt::Display for HashSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} v={} m={} t={} p={}",
+            self.algorithm, self.version, self.memory_cost, self.time_cost, self.parallelism
+        )
+    }
+}
+
+/// Hashes passwords with the configured argon2 settings.
+pub struct Hasher {
+    argon2: Argon2<'static>,
+    settings: HashSettings,
+    // verified against when a username doesn't exist, so failed logins take as long
+    // either way
+    dummy_hash: String,
 }
 
-lazy_static::lazy_static! {
-    // TODO: use jwt_secret from config instead of env var
-    static ref KEYS: Keys = {
-        let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-        Keys {
-            encoding: EncodingKey::from_base64_secret(&secret).expect("JWT_SECRET is not valid base64"),
-            decoding: DecodingKey::from_base64_secret(&secret).expect("JWT_SECRET is not valid base64"),
+impl Hasher {
+    pub fn from_config(config: &PasswordHashConfig) -> anyhow::Result<Hasher> {
+        let argon2 = config.argon2().map_err(anyhow::Error::msg)?;
+        let dummy_hash = argon2
+            .hash_password(b"not a real password", &SaltString::generate(&mut OsRng))?
+            .to_string();
+        let settings = HashSettings::of(&dummy_hash)
+            .ok_or_else(|| anyhow!("could not read back the settings of a new hash"))?;
+
+        Ok(Hasher {
+            argon2,
+            settings,
+            dummy_hash,
+        })
+    }
+
+    /// The settings new hashes are made with.
+    pub fn settings(&self) -> &HashSettings {
+        &self.settings
+    }
+
+    pub fn hash(&self, password: impl AsRef<[u8]>) -> password_hash::Result<String> {
+        let salt = SaltString::generate(&mut OsRng);
+        self.argon2
+            .hash_password(password.as_ref(), &salt)
+            .map(|h| h.to_string())
+    }
+
+    /// Like [`verify_password`], but when there is no hash it verifies against a dummy
+    /// one and returns `false`, so it takes as long whether or not an account exists.
+    pub fn verify_or_dummy(
+        &self,
+        password: impl AsRef<[u8]>,
+        password_hash: Option<&str>,
+    ) -> password_hash::Result<bool> {
+        match password_hash {
+            Some(hash) => verify_password(password, hash),
+            None => verify_password(password, &self.dummy_hash).map(|_| false),
         }
-    };
+    }
 
-    static ref ADMIN_KEY: String = std::env::var("ADMIN_KEY").expect("ADMIN_KEY must be set");
+    /// Whether `password_hash` was made with other settings, and should be replaced
+    /// the next time the password is known.
+    pub fn needs_rehash(&self, password_hash: &str) -> bool {
+        HashSettings::of(password_hash).as_ref() != Some(&self.settings)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Claims {
-    pub club_id: String,
-    pub club_db_id: i32,
+    pub user_id: i32,
+    pub username: String,
+    /// ids of the clubs the user was an officer of when the token was issued, for the
+    /// frontend. Requests are authorized against the current officers, see [`authorize`].
+    pub clubs: Vec<i32>,
+    /// when the token was issued, 0 for tokens from before this was recorded
+    #[serde(default)]
+    pub iat: u64,
     pub exp: u64,
 }
 
-#[allow(unused_must_use)]
-pub fn ensure_jwt_secret_is_valid() {
-    KEYS.deref();
+/// Signs `claims` with the current signing key, naming it in the `kid` header.
+pub fn sign<T: Serialize>(keys: &Keys, clock: &dyn Clock, claims: &T) -> anyhow::Result<String> {
+    let key = keys
+        .signing_key(clock.now())
+        .ok_or_else(|| anyhow!("every jwt key is retired"))?;
+    let mut header = Header::new(key.algorithm);
+    header.kid = Some(key.kid.clone());
+    Ok(jsonwebtoken::encode(&header, claims, &key.encoding)?)
 }
 
-pub fn generate_jwt(club: &Club, exp: Duration) -> JwtResult<String> {
-    jsonwebtoken::encode(
-        &Default::default(),
+/// Checks the signature of a jwt with the key named by its `kid` and decodes its claims.
+/// Expiry is left to the caller, so it can be checked against a [`Clock`].
+pub fn verify<T: DeserializeOwned>(
+    keys: &Keys,
+    clock: &dyn Clock,
+    token: &str,
+) -> Result<T, ResponseStatusError> {
+    let invalid = || ResponseStatusError::from(StatusCode::BAD_REQUEST, "invalid token");
+    let header = jsonwebtoken::decode_header(token).map_err(|_| invalid())?;
+    let key = keys
+        .verifying_key(header.kid.as_deref(), clock.now())
+        .ok_or_else(invalid)?;
+
+    let mut validation = Validation::new(key.algorithm);
+    validation.validate_exp = false;
+
+    jsonwebtoken::decode::<T>(token, &key.decoding, &validation)
+        .map(|data| data.claims)
+        .map_err(|_| invalid())
+}
+
+pub fn generate_jwt(
+    keys: &Keys,
+    clock: &dyn Clock,
+    user: &User,
+    clubs: Vec<i32>,
+    exp: Duration,
+) -> anyhow::Result<String> {
+    sign(
+        keys,
+        clock,
         &Claims {
-            club_id: club.username.clone(),
-            club_db_id: club.id,
-            exp: jsonwebtoken::get_current_timestamp() + exp.as_secs(),
+            user_id: user.id,
+            username: user.username.clone(),
+            clubs,
+            iat: clock.timestamp(),
+            exp: clock.timestamp() + exp.as_secs(),
         },
-        &KEYS.encoding,
     )
 }
 
+/// Decodes a jwt and checks that it hasn't expired according to `clock`.
+pub fn validate_jwt(
+    keys: &Keys,
+    clock: &dyn Clock,
+    token: &str,
+) -> Result<Claims, ResponseStatusError> {
+    let claims = verify::<Claims>(keys, clock, token)?;
+
+    if claims.exp < clock.timestamp() {
+        Err((StatusCode::UNAUTHORIZED, "token expired").into())
+    } else {
+        Ok(claims)
+    }
+}
+
+/// A valid token of a user whose sessions haven't been revoked since it was issued.
 #[derive(Debug, Clone)]
 pub struct Auth(pub Claims);
 
 #[async_trait]
-impl<B: Send> FromRequest<B> for Auth {
-    type Rejection = ResponseStatusError;
+impl FromRequestParts<AppState> for Auth {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let TypedHeader(Authorization(bearer)) =
+            TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, state)
+                .await
+                .map_err(|_| {
+                    ResponseStatusError::from(StatusCode::UNAUTHORIZED, "missing credentials")
+                })?;
+        let claims = validate_jwt(&state.keys, state.clock.as_ref(), bearer.token())?;
 
-    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
-        let TypedHeader(Authorization(bearer)) = req
-            .extract::<TypedHeader<Authorization<Bearer>>>()
+        let conn = &mut state.pool.get().await?;
+        let revoked_at = users::table
+            .find(claims.user_id)
+            .select(users::sessions_revoked_at)
+            .first::<Option<DateTime<Utc>>>(conn)
             .await
-            .map_err(|_| (StatusCode::UNAUTHORIZED, "missing credentials"))?;
-        let claims =
-            jsonwebtoken::decode::<Claims>(bearer.token(), &KEYS.decoding, &Default::default())
-                .map_err(|_| (StatusCode::BAD_REQUEST, "invalid token"))?
-                .claims;
-
-        if claims.exp < jsonwebtoken::get_current_timestamp() {
-            Err((StatusCode::UNAUTHORIZED, "token expired").into())
-        } else {
-            Ok(Auth(claims))
+            .optional()?;
+        match revoked_at {
+            // the user has been deleted
+            None => Err(AppError::from(StatusCode::UNAUTHORIZED, "token revoked")),
+            // tokens only have whole seconds, so ones from the same second are revoked too
+            Some(Some(revoked_at)) if claims.iat as i64 <= revoked_at.timestamp() => {
+                Err(AppError::from(StatusCode::UNAUTHORIZED, "token revoked"))
+            }
+            Some(_) => Ok(Auth(claims)),
         }
     }
 }
 
-impl Auth {
-    pub fn into_authorized(self, club_id: &str) -> AppResult<Claims> {
-        match self.0 {
-            c if c.club_id == club_id => Ok(c),
-            _ => Err(AppError::from(
-                StatusCode::UNAUTHORIZED,
-                "wrong credentials",
-            )),
-        }
+/// Logs `user_id` out everywhere: tokens issued before `now` stop working.
+pub async fn revoke_sessions(
+    conn: &mut AsyncPgConnection,
+    user_id: i32,
+    now: DateTime<Utc>,
+) -> QueryResult<()> {
+    diesel::update(users::table.find(user_id))
+        .set(users::sessions_revoked_at.eq(now))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// The ids of the clubs `user_id` is an officer of, leaving out archived and deleted ones.
+pub async fn officer_clubs(conn: &mut AsyncPgConnection, user_id: i32) -> QueryResult<Vec<i32>> {
+    club_officers::table
+        .inner_join(clubs::table)
+        .filter(club_officers::user_id.eq(user_id))
+        .filter(clubs::status.ne(ClubStatus::Archived))
+        .filter(clubs::deleted_at.is_null())
+        .select(club_officers::club_id)
+        .order(club_officers::club_id)
+        .load(conn)
+        .await
+}
+
+/// Fails for a user whose clubs have all been archived or deleted. Users who aren't an
+/// officer of any club can still log in, e.g. to accept an invitation.
+pub async fn check_clubs_open(conn: &mut AsyncPgConnection, user_id: i32) -> AppResult<()> {
+    let clubs = club_officers::table
+        .inner_join(clubs::table)
+        .filter(club_officers::user_id.eq(user_id))
+        .select((clubs::status, clubs::deleted_at))
+        .load::<(ClubStatus, Option<DateTime<Utc>>)>(conn)
+        .await?;
+
+    let closed = |(status, deleted_at): &(ClubStatus, Option<DateTime<Utc>>)| {
+        *status == ClubStatus::Archived || deleted_at.is_some()
+    };
+    if !clubs.is_empty() && clubs.iter().all(closed) {
+        return Err(AppError::from(
+            StatusCode::FORBIDDEN,
+            "your club has been archived",
+        ));
+    }
+    Ok(())
+}
+
+/// Checks a new password of `user` against the policy, which also rejects their
+/// username and the names of their clubs.
+pub async fn check_new_password(
+    state: &AppState,
+    conn: &mut AsyncPgConnection,
+    field: &'static str,
+    password: &str,
+    user: &User,
+) -> AppResult<()> {
+    let club_names = club_officers::table
+        .inner_join(clubs::table)
+        .filter(club_officers::user_id.eq(user.id))
+        .select(clubs::club_name)
+        .load::<String>(conn)
+        .await?;
+    let mut user_inputs = vec![user.username.as_str()];
+    user_inputs.extend(club_names.iter().map(String::as_str));
+    password_policy::enforce(
+        &state.config.password_policy,
+        field,
+        password,
+        &user_inputs,
+        state.clock.now().year(),
+    )
+}
+
+/// Loads the club with the username `club`, or fails with a 404.
+pub async fn find_club(conn: &mut AsyncPgConnection, club: &str) -> AppResult<Club> {
+    clubs::table
+        .filter(clubs::username.eq(club))
+        .first::<Club>(conn)
+        .await
+        .optional()?
+        .ok_or_else(|| AppError::from(StatusCode::NOT_FOUND, "the club does not exist"))
+}
+
+/// Loads the club with the username `club` if the user behind `claims` is currently one
+/// of its officers with at least `role`.
+pub async fn authorize(
+    conn: &mut AsyncPgConnection,
+    claims: &Claims,
+    club: &str,
+    role: Role,
+) -> AppResult<(Club, Role)> {
+    let club = find_club(conn, club).await?;
+    if club.deleted_at.is_some() {
+        return Err(AppError::from(
+            StatusCode::NOT_FOUND,
+            "the club does not exist",
+        ));
+    }
+    if club.status == ClubStatus::Archived {
+        return Err(AppError::from(
+            StatusCode::FORBIDDEN,
+            "the club has been archived",
+        ));
+    }
+
+    let officer_role = club_officers::table
+        .find((club.id, claims.user_id))
+        .select(club_officers::role)
+        .first::<Role>(conn)
+        .await
+        .optional()?;
+    match officer_role {
+        Some(officer_role) if officer_role >= role => Ok((club, officer_role)),
+        Some(_) => Err(AppError::from(
+            StatusCode::FORBIDDEN,
+            format!("only a club's {role}s can do that"),
+        )),
+        None => Err(AppError::from(
+            StatusCode::FORBIDDEN,
+            "you are not an officer of this club",
+        )),
     }
 }
 
@@ -118,16 +381,30 @@ impl Auth {
 pub struct AdminOnly;
 
 #[async_trait]
-impl<B: Send> FromRequest<B> for AdminOnly {
-    type Rejection = ResponseStatusError;
+impl FromRequestParts<AppState> for AdminOnly {
+    type Rejection = AppError;
 
-    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
-        let TypedHeader(Authorization(bearer)) = req
-            .extract::<TypedHeader<Authorization<Bearer>>>()
-            .await
-            .map_err(|_| (StatusCode::UNAUTHORIZED, "missing admin key"))?;
-        if bearer.token() != *ADMIN_KEY {
-            Err((StatusCode::UNAUTHORIZED, "incorrect admin key").into())
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let TypedHeader(Authorization(bearer)) =
+            TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, state)
+                .await
+                .map_err(|_| AppError::from(StatusCode::UNAUTHORIZED, "missing admin key"))?;
+
+        // only wrong keys count, so scripts using the right one aren't throttled
+        let Ok(ClientIp(ip)) = ClientIp::from_request_parts(parts, state).await;
+        let key = format!("admin-ip:{ip}");
+        let limit = &state.config.rate_limit.admin_key;
+        rate_limit::check(state, &key, limit).await?;
+
+        if bearer.token() != state.config.auth.admin_key {
+            rate_limit::hit(state, &key, limit).await?;
+            Err(AppError::from(
+                StatusCode::UNAUTHORIZED,
+                "incorrect admin key",
+            ))
         } else {
             Ok(AdminOnly)
         }
@@ -0,0 +1,431 @@
+use crate::{
+    migrations,
+    models::{
+        AuditEntry, Category, Club, ClubApplication, ClubCategory, ClubDraft, ClubOfficer,
+        ClubReminder, ClubRevision, ClubSocial, LeadershipTransfer, User, UserTotp,
+    },
+    schema::*,
+    state::AppState,
+};
+use anyhow::{bail, Context};
+use chrono::{DateTime, Utc};
+use diesel::{delete, insert_into, sql_query, QueryDsl};
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::{self, Read},
+    path::{Component, Path},
+};
+
+/// Bumped whenever the layout of the archive or the manifest changes.
+pub const FORMAT_VERSION: u32 = 10;
+
+const MANIFEST_PATH: &str = "manifest.json";
+const ASSET_DIR: &str = "assets/";
+
+// keeps inserts well under postgres' limit of 65535 bind parameters
+const INSERT_BATCH: usize = 1000;
+
+/// `manifest.json` at the root of a backup archive. Asset files sit next to it under
+/// `assets/`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub format_version: u32,
+    /// latest migration applied to the database the backup was taken from
+    pub schema_version: String,
+    pub created_at: DateTime<Utc>,
+    pub clubs: Vec<Club>,
+    pub club_socials: Vec<ClubSocial>,
+    pub categories: Vec<Category>,
+    pub club_categories: Vec<ClubCategory>,
+    /// password hashes included
+    pub users: Vec<User>,
+    pub club_officers: Vec<ClubOfficer>,
+    /// two-factor secrets and hashed recovery codes, so users keep their authenticator
+    pub user_totp: Vec<UserTotp>,
+    /// pending and completed, as a record of who led each club
+    pub leadership_transfers: Vec<LeadershipTransfer>,
+    /// reviewed ones too, as a record of what was decided
+    pub club_applications: Vec<ClubApplication>,
+    /// profile edits waiting for review, and the reviewed ones
+    pub club_drafts: Vec<ClubDraft>,
+    /// every published version of each profile
+    pub club_revisions: Vec<ClubRevision>,
+    /// who did what, see [`crate::audit`]
+    pub audit_log: Vec<AuditEntry>,
+    /// so the tokens in reminders that were already sent keep working
+    pub club_reminders: Vec<ClubReminder>,
+    /// names of the asset files in the archive, as given to [`AssetStore::put`](crate::assets::AssetStore::put)
+    pub assets: Vec<String>,
+}
+
+/// A backup archive that has been read and checked.
+pub struct Backup {
+    pub manifest: Manifest,
+    pub assets: HashMap<String, Vec<u8>>,
+}
+
+/// The latest migration applied to the database.
+pub async fn schema_version(state: &AppState) -> anyhow::Result<String> {
+    let url = state.config.database.url.clone();
+    let status = tokio::task::spawn_blocking(move || migrations::status(&url)).await??;
+    status
+        .applied
+        .into_iter()
+        .max()
+        .context("the database has no migrations applied")
+}
+
+/// Backs up every club, user (password hashes and two-factor secrets included), category,
+/// leadership transfer, club application, profile draft and revision, re-registration
+/// reminder, the audit log and the assets the clubs use as a tar archive.
+pub async fn create(state: &AppState) -> anyhow::Result<Vec<u8>> {
+    let now = state.clock.now();
+    let schema_version = schema_version(state).await?;
+    let conn = &mut state.pool.get().await?;
+
+    let (
+        clubs,
+        club_socials,
+        categories,
+        club_categories,
+        users,
+        club_officers,
+        user_totp,
+        leadership_transfers,
+        club_applications,
+        club_drafts,
+        club_revisions,
+        audit_log,
+        club_reminders,
+    ) = conn
+        .transaction::<_, anyhow::Error, _>(|conn| {
+            Box::pin(async move {
+                // read every table from the same snapshot
+                sql_query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ")
+                    .execute(conn)
+                    .await?;
+                Ok((
+                    clubs::table.order(clubs::id).load::<Club>(conn).await?,
+                    club_socials::table
+                        .order(club_socials::id)
+                        .load::<ClubSocial>(conn)
+                        .await?,
+                    categories::table
+                        .order(categories::id)
+                        .load::<Category>(conn)
+                        .await?,
+                    club_categories::table
+                        .order(club_categories::id)
+                        .load::<ClubCategory>(conn)
+                        .await?,
+                    users::table.order(users::id).load::<User>(conn).await?,
+                    club_officers::table
+                        .order((club_officers::club_id, club_officers::user_id))
+                        .load::<ClubOfficer>(conn)
+                        .await?,
+                    user_totp::table
+                        .order(user_totp::user_id)
+                        .load::<UserTotp>(conn)
+                        .await?,
+                    leadership_transfers::table
+                        .order(leadership_transfers::id)
+                        .load::<LeadershipTransfer>(conn)
+                        .await?,
+                    club_applications::table
+                        .order(club_applications::id)
+                        .load::<ClubApplication>(conn)
+                        .await?,
+                    club_drafts::table
+                        .order(club_drafts::id)
+                        .load::<ClubDraft>(conn)
+                        .await?,
+                    club_revisions::table
+                        .order(club_revisions::id)
+                        .load::<ClubRevision>(conn)
+                        .await?,
+                    audit_log::table
+                        .order(audit_log::id)
+                        .load::<AuditEntry>(conn)
+                        .await?,
+                    club_reminders::table
+                        .order(club_reminders::id)
+                        .load::<ClubReminder>(conn)
+                        .await?,
+                ))
+            })
+        })
+        .await?;
+
+    let urls: BTreeSet<&str> = clubs
+        .iter()
+        .flat_map(|club| [&club.profile_picture_url, &club.banner_url])
+        .filter_map(|url| state.assets.name_of(url).map(|_| url.as_str()))
+        .collect();
+
+    let mut archive = tar::Builder::new(Vec::new());
+    let mut assets = Vec::new();
+    for url in urls {
+        let bytes = match state.assets.get(url).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                eprintln!("backup: skipping missing asset {url}");
+                continue;
+            }
+            Err(e) => return Err(e).with_context(|| format!("could not read asset {url}")),
+        };
+        let name = state
+            .assets
+            .name_of(url)
+            .expect("only urls from the asset store are backed up");
+        append(&mut archive, &format!("{ASSET_DIR}{name}"), &bytes, now)?;
+        assets.push(name.to_string());
+    }
+
+    let manifest = Manifest {
+        format_version: FORMAT_VERSION,
+        schema_version,
+        created_at: now,
+        clubs,
+        club_socials,
+        categories,
+        club_categories,
+        users,
+        club_officers,
+        user_totp,
+        leadership_transfers,
+        club_applications,
+        club_drafts,
+        club_revisions,
+        audit_log,
+        club_reminders,
+        assets,
+    };
+    append(
+        &mut archive,
+        MANIFEST_PATH,
+        &serde_json::to_vec_pretty(&manifest)?,
+        now,
+    )?;
+
+    Ok(archive.into_inner()?)
+}
+
+fn append(
+    archive: &mut tar::Builder<Vec<u8>>,
+    path: &str,
+    bytes: &[u8],
+    now: DateTime<Utc>,
+) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(bytes.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(now.timestamp().max(0) as u64);
+    archive.append_data(&mut header, path, bytes)
+}
+
+/// Reads a backup archive and checks that it is complete, without touching the database.
+pub fn read(archive: impl Read) -> anyhow::Result<Backup> {
+    let mut manifest = None;
+    let mut assets = HashMap::new();
+
+    for entry in tar::Archive::new(archive).entries()? {
+        let mut entry = entry?;
+        let path = entry
+            .path()?
+            .to_str()
+            .context("backup contains a path that isn't utf-8")?
+            .to_string();
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes)?;
+
+        if path == MANIFEST_PATH {
+            manifest = Some(bytes);
+        } else if let Some(name) = path.strip_prefix(ASSET_DIR) {
+            assets.insert(name.to_string(), bytes);
+        } else {
+            bail!("unexpected file {path} in backup");
+        }
+    }
+
+    let manifest = manifest.with_context(|| format!("backup has no {MANIFEST_PATH}"))?;
+    // check the version before the rest, since other fields may have changed between versions
+    let manifest: serde_json::Value = serde_json::from_slice(&manifest)?;
+    let format_version = manifest.get("format_version").and_then(|v| v.as_u64());
+    if format_version != Some(FORMAT_VERSION.into()) {
+        bail!(
+            "backup has format version {}, expected {FORMAT_VERSION}",
+            format_version.map_or("unknown".to_string(), |v| v.to_string())
+        );
+    }
+    let manifest: Manifest = serde_json::from_value(manifest).context("invalid manifest")?;
+
+    for name in &manifest.assets {
+        if !Path::new(name)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            bail!("backup contains an invalid asset name {name}");
+        }
+        if !assets.contains_key(name) {
+            bail!("backup is missing asset {name}");
+        }
+    }
+    if let Some(name) = assets.keys().find(|name| !manifest.assets.contains(name)) {
+        bail!("unexpected file {ASSET_DIR}{name} in backup");
+    }
+
+    Ok(Backup { manifest, assets })
+}
+
+/// Replaces every club, user, category, transfer, application, draft and revision with the
+/// ones in `backup`, dropping reset links and officer invitations, then writes its assets. The
+/// database must be at the same schema version the backup was taken at.
+pub async fn restore(state: &AppState, backup: Backup) -> anyhow::Result<()> {
+    let Backup { manifest, assets } = backup;
+
+    let current = schema_version(state).await?;
+    if current != manifest.schema_version {
+        bail!(
+            "backup was taken at schema version {} but the database is at {current}, \
+             migrate the database to the same version first",
+            manifest.schema_version
+        );
+    }
+
+    let conn = &mut state.pool.get().await?;
+    conn.transaction::<_, anyhow::Error, _>(|conn| {
+        Box::pin(async move {
+            delete(password_resets::table).execute(conn).await?;
+            delete(club_invitations::table).execute(conn).await?;
+            delete(club_categories::table).execute(conn).await?;
+            delete(leadership_transfers::table).execute(conn).await?;
+            delete(club_applications::table).execute(conn).await?;
+            delete(club_drafts::table).execute(conn).await?;
+            delete(club_revisions::table).execute(conn).await?;
+            delete(audit_log::table).execute(conn).await?;
+            delete(club_reminders::table).execute(conn).await?;
+            delete(user_totp::table).execute(conn).await?;
+            delete(club_officers::table).execute(conn).await?;
+            delete(users::table).execute(conn).await?;
+            delete(club_socials::table).execute(conn).await?;
+            delete(clubs::table).execute(conn).await?;
+            delete(categories::table).execute(conn).await?;
+
+            for batch in manifest.categories.chunks(INSERT_BATCH) {
+                insert_into(categories::table)
+                    .values(batch)
+                    .execute(conn)
+                    .await?;
+            }
+            for batch in manifest.clubs.chunks(INSERT_BATCH) {
+                insert_into(clubs::table)
+                    .values(batch)
+                    .execute(conn)
+                    .await?;
+            }
+            for batch in manifest.club_socials.chunks(INSERT_BATCH) {
+                insert_into(club_socials::table)
+                    .values(batch)
+                    .execute(conn)
+                    .await?;
+            }
+            for batch in manifest.club_categories.chunks(INSERT_BATCH) {
+                insert_into(club_categories::table)
+                    .values(batch)
+                    .execute(conn)
+                    .await?;
+            }
+            for batch in manifest.users.chunks(INSERT_BATCH) {
+                insert_into(users::table)
+                    .values(batch)
+                    .execute(conn)
+                    .await?;
+            }
+            for batch in manifest.club_officers.chunks(INSERT_BATCH) {
+                insert_into(club_officers::table)
+                    .values(batch)
+                    .execute(conn)
+                    .await?;
+            }
+            for batch in manifest.user_totp.chunks(INSERT_BATCH) {
+                insert_into(user_totp::table)
+                    .values(batch)
+                    .execute(conn)
+                    .await?;
+            }
+            for batch in manifest.club_applications.chunks(INSERT_BATCH) {
+                insert_into(club_applications::table)
+                    .values(batch)
+                    .execute(conn)
+                    .await?;
+            }
+            for batch in manifest.club_drafts.chunks(INSERT_BATCH) {
+                insert_into(club_drafts::table)
+                    .values(batch)
+                    .execute(conn)
+                    .await?;
+            }
+            // in id order, so reverts come after the revisions they restored
+            for batch in manifest.club_revisions.chunks(INSERT_BATCH) {
+                insert_into(club_revisions::table)
+                    .values(batch)
+                    .execute(conn)
+                    .await?;
+            }
+            for batch in manifest.leadership_transfers.chunks(INSERT_BATCH) {
+                insert_into(leadership_transfers::table)
+                    .values(batch)
+                    .execute(conn)
+                    .await?;
+            }
+            for batch in manifest.audit_log.chunks(INSERT_BATCH) {
+                insert_into(audit_log::table)
+                    .values(batch)
+                    .execute(conn)
+                    .await?;
+            }
+            for batch in manifest.club_reminders.chunks(INSERT_BATCH) {
+                insert_into(club_reminders::table)
+                    .values(batch)
+                    .execute(conn)
+                    .await?;
+            }
+
+            // rows were inserted with their ids, so move the sequences past them
+            for table in [
+                "categories",
+                "clubs",
+                "club_socials",
+                "club_categories",
+                "users",
+                "leadership_transfers",
+                "club_applications",
+                "club_drafts",
+                "club_revisions",
+                "audit_log",
+                "club_reminders",
+            ] {
+                sql_query(format!(
+                    "SELECT setval(pg_get_serial_sequence('{table}', 'id'), \
+                     COALESCE(MAX(id), 0) + 1, false) FROM {table}"
+                ))
+                .execute(conn)
+                .await?;
+            }
+            Ok(())
+        })
+    })
+    .await?;
+
+    for (name, bytes) in assets {
+        state
+            .assets
+            .put(&name, &bytes)
+            .await
+            .with_context(|| format!("could not write asset {name}"))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,887 @@
+use anyhow::{anyhow, bail, Context};
+use cca_club_hub::{
+    api::{
+        admin::{self, ClubRegisterRequest, ONBOARDING_ALLOWED_TIME},
+        applications,
+        club::{self, ClubResponse},
+        drafts, lifecycle, password,
+        reregistration::{self, SchoolYear},
+        transfer, two_factor,
+    },
+    audit::{self, Actor, Client, Entry},
+    backup,
+    config::AppConfig,
+    email,
+    models::{ApplicationStatus, Category, Club, ClubStatus, DraftStatus, User},
+    schema::*,
+    state::AppState,
+    tasks::Supervisor,
+};
+use clap::{Parser, Subcommand};
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use std::{fs, path::PathBuf, process, time::Duration};
+
+// how long to wait for emails a command queued before exiting
+const OUTBOX_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Parser)]
+#[command(
+    name = "cca-admin",
+    about = "Manage CCA Club Hub clubs from the command line"
+)]
+struct Cli {
+    /// Print JSON instead of human readable text
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Register a club and email its contact an onboarding link
+    Register {
+        #[arg(long)]
+        username: String,
+        #[arg(long)]
+        email: String,
+        #[arg(long)]
+        name: String,
+        #[arg(long, default_value = "")]
+        description: String,
+        #[arg(long, default_value = "")]
+        meet_time: String,
+    },
+    /// Register every club in a CSV file with the columns
+    /// username,email,name,description,meet_time
+    BulkRegister { file: PathBuf },
+    /// List clubs
+    List {
+        /// Only list featured clubs
+        #[arg(long)]
+        featured: bool,
+    },
+    /// Feature a club on the home page
+    Feature {
+        username: String,
+        /// Stop featuring the club instead
+        #[arg(long)]
+        off: bool,
+    },
+    /// Let a club publish profile edits without review when edits are moderated
+    Trust {
+        username: String,
+        /// Moderate the club's edits again instead
+        #[arg(long)]
+        off: bool,
+    },
+    /// Archive a club, hiding it and stopping its officers from logging in
+    Archive { username: String },
+    /// Hide a club everywhere, keeping it so it can be restored
+    Delete { username: String },
+    /// Make an archived, inactive or deleted club active again
+    Reactivate { username: String },
+    /// List which clubs have confirmed their profile this school year, unconfirmed first
+    Reregistration,
+    /// Remind the clubs that haven't confirmed their profile, or make them inactive once
+    /// the deadline has passed, without waiting for the server to do it
+    Remind,
+    /// Print a password reset link for a user without emailing it
+    ResetLink { username: String },
+    /// Print everything about a club
+    Show { username: String },
+    /// Turn off two-factor authentication for a user that lost their authenticator
+    #[command(name = "reset-2fa")]
+    Reset2fa { username: String },
+    /// Hand a club over to a new president, who confirms through a link emailed to them
+    Transfer {
+        username: String,
+        /// The new contact email of the club
+        #[arg(long)]
+        email: String,
+    },
+    /// List the leadership transfers of a club, newest first
+    Transfers { username: String },
+    /// List club applications, oldest first
+    Applications {
+        /// Only list applications that are pending, approved or rejected
+        #[arg(long)]
+        status: Option<ApplicationStatus>,
+    },
+    /// Approve a club application, registering the club and emailing its contact
+    Approve {
+        id: i32,
+        /// Username of the new club, made from its name if not given
+        #[arg(long)]
+        username: Option<String>,
+    },
+    /// Reject a club application, emailing its contact the reason
+    Reject {
+        id: i32,
+        #[arg(long)]
+        reason: String,
+    },
+    /// List profile edits waiting for review, oldest first
+    Drafts {
+        /// Only list drafts that are pending, approved, rejected or superseded
+        #[arg(long)]
+        status: Option<DraftStatus>,
+    },
+    /// Publish a club's pending profile edit
+    ApproveDraft {
+        id: i32,
+        /// Shown to the club
+        #[arg(long)]
+        comment: Option<String>,
+    },
+    /// Reject a club's pending profile edit, keeping the public profile as it is
+    RejectDraft {
+        id: i32,
+        /// Shown to the club
+        #[arg(long)]
+        comment: String,
+    },
+    /// Manage categories
+    #[command(subcommand)]
+    Categories(CategoryCommand),
+    /// Count the users whose password hash was made with old argon2 settings
+    PasswordHashes,
+    /// Write a backup of every club, category and asset to a tar archive
+    Backup { file: PathBuf },
+    /// Replace every club, category and asset with the contents of a backup
+    Restore {
+        file: PathBuf,
+        /// Actually replace the data, instead of only checking the backup
+        #[arg(long)]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum CategoryCommand {
+    /// List every category
+    List,
+    /// Add a category
+    Add { name: String },
+    /// Rename a category, keeping the clubs in it
+    Rename { from: String, to: String },
+    /// Remove a category from every club and delete it
+    Remove { name: String },
+}
+
+#[tokio::main]
+async fn main() {
+    dotenv::dotenv().ok();
+    let cli = Cli::parse();
+
+    let mut tasks = Supervisor::new();
+    let result = run(cli, &mut tasks).await;
+    // the state is gone by now, so the outbox stops once the emails the command queued are sent
+    tasks.shutdown(OUTBOX_TIMEOUT).await;
+    if let Err(e) = result {
+        eprintln!("error: {e:#}");
+        process::exit(1);
+    }
+}
+
+async fn run(cli: Cli, tasks: &mut Supervisor) -> anyhow::Result<()> {
+    let state = AppState::from_config(AppConfig::load()?)?;
+    let queued = state.outbox.queued();
+    tasks.spawn("email outbox", move |shutdown| {
+        email::send_queued(queued.clone(), shutdown)
+    });
+    let conn = &mut state.pool.get().await?;
+    let json = cli.json;
+
+    match cli.command {
+        Command::Register {
+            username,
+            email,
+            name,
+            description,
+            meet_time,
+        } => {
+            let club = admin::register_club(
+                &state,
+                ClubRegisterRequest {
+                    username,
+                    email,
+                    name,
+                    description,
+                    meet_time,
+                },
+            )
+            .await
+            .map_err(|e| anyhow!("{e}"))?;
+            record(
+                &state,
+                Entry::new(Actor::Cli, "club.register")
+                    .club(&club)
+                    .after(&club),
+            )
+            .await;
+            output(json, &Registered::from(&club), |r| {
+                println!(
+                    "registered {}, sent onboarding email to {}",
+                    r.username, r.email
+                )
+            })
+        }
+        Command::BulkRegister { file } => bulk_register(&state, json, file).await,
+        Command::List { featured } => {
+            let mut query = clubs::table.left_join(club_socials::table).into_boxed();
+            if featured {
+                query = query.filter(clubs::featured.eq(true));
+            }
+            let clubs = query.order(clubs::username).load(conn).await?;
+            let clubs = club::load_clubs(conn, clubs)
+                .await
+                .map_err(|e| anyhow!("{e}"))?;
+            output(json, &clubs, |clubs| {
+                for club in clubs {
+                    let featured = if club.featured { " (featured)" } else { "" };
+                    let status = match (club.deleted_at, club.status) {
+                        (Some(_), _) => " (deleted)".to_string(),
+                        (None, ClubStatus::Active) => String::new(),
+                        (None, status) => format!(" ({status})"),
+                    };
+                    println!(
+                        "{}\t{}\t{}{featured}{status}",
+                        club.id, club.club_name, club.email
+                    );
+                }
+            })
+        }
+        Command::Feature { username, off } => {
+            let updated = diesel::update(clubs::table.filter(clubs::username.eq(&username)))
+                .set(clubs::featured.eq(!off))
+                .execute(conn)
+                .await?;
+            if updated == 0 {
+                bail!("no club with username {username}");
+            }
+            record(
+                &state,
+                Entry::new(Actor::Cli, "club.feature")
+                    .club_named(&username)
+                    .after(serde_json::json!({ "featured": !off })),
+            )
+            .await;
+            output(
+                json,
+                &serde_json::json!({ "username": username, "featured": !off }),
+                |_| {
+                    let verb = if off { "unfeatured" } else { "featured" };
+                    println!("{verb} {username}")
+                },
+            )
+        }
+        Command::Trust { username, off } => {
+            let club = drafts::set_trusted(&state, &username, !off)
+                .await
+                .map_err(|e| anyhow!("{e}"))?;
+            record(
+                &state,
+                Entry::new(Actor::Cli, "club.trust")
+                    .club(&club)
+                    .before(serde_json::json!({ "trusted": club.trusted }))
+                    .after(serde_json::json!({ "trusted": !off })),
+            )
+            .await;
+            output(
+                json,
+                &serde_json::json!({ "username": username, "trusted": !off }),
+                |_| {
+                    let verb = if off { "untrusted" } else { "trusted" };
+                    println!("{verb} {username}")
+                },
+            )
+        }
+        Command::Archive { username } => {
+            let club = lifecycle::set_status(&state, &username, ClubStatus::Archived)
+                .await
+                .map_err(|e| anyhow!("{e}"))?;
+            let deleted = club.deleted_at.is_some();
+            record(
+                &state,
+                Entry::new(Actor::Cli, "club.archive")
+                    .club(&club)
+                    .before(lifecycle_state(club.status, deleted))
+                    .after(lifecycle_state(ClubStatus::Archived, deleted)),
+            )
+            .await;
+            output(
+                json,
+                &serde_json::json!({ "username": username, "status": ClubStatus::Archived }),
+                |_| println!("archived {username}"),
+            )
+        }
+        Command::Delete { username } => {
+            let club = lifecycle::delete(&state, &username)
+                .await
+                .map_err(|e| anyhow!("{e}"))?;
+            record(
+                &state,
+                Entry::new(Actor::Cli, "club.delete")
+                    .club(&club)
+                    .before(lifecycle_state(club.status, false))
+                    .after(lifecycle_state(club.status, true)),
+            )
+            .await;
+            output(
+                json,
+                &serde_json::json!({ "username": username, "deleted": true }),
+                |_| println!("deleted {username}, bring it back with `cca-admin reactivate`"),
+            )
+        }
+        Command::Reactivate { username } => {
+            let club = lifecycle::restore(&state, &username)
+                .await
+                .map_err(|e| anyhow!("{e}"))?;
+            record(
+                &state,
+                Entry::new(Actor::Cli, "club.restore")
+                    .club(&club)
+                    .before(lifecycle_state(club.status, club.deleted_at.is_some()))
+                    .after(lifecycle_state(ClubStatus::Active, false)),
+            )
+            .await;
+            output(
+                json,
+                &serde_json::json!({ "username": username, "status": ClubStatus::Active }),
+                |_| println!("reactivated {username}"),
+            )
+        }
+        Command::Reregistration => {
+            let year = SchoolYear::current(&state.config.reregistration, state.clock.now());
+            let report = reregistration::report(&state, &year)
+                .await
+                .map_err(|e| anyhow!("{e}"))?;
+            output(json, &report, |report| {
+                println!(
+                    "{} of {} clubs confirmed, deadline {}",
+                    report.confirmed,
+                    report.confirmed + report.unconfirmed,
+                    report.deadline
+                );
+                for club in &report.clubs {
+                    let confirmed = match (club.confirmed, club.last_reminded_at) {
+                        (true, _) => "confirmed".to_string(),
+                        (false, Some(at)) => {
+                            format!("{} reminders, last at {at}", club.reminders_sent)
+                        }
+                        (false, None) => "not reminded".to_string(),
+                    };
+                    println!(
+                        "{:<24}{:<10}{confirmed}",
+                        club.club,
+                        club.status.to_string()
+                    );
+                }
+            })
+        }
+        Command::Remind => {
+            let year = SchoolYear::current(&state.config.reregistration, state.clock.now());
+            let run = reregistration::run(&state, &year).await?;
+            output(json, &run, |run| {
+                for club in &run.reminded {
+                    println!("reminded {club}");
+                }
+                for club in &run.deactivated {
+                    println!("made {club} inactive");
+                }
+            })
+        }
+        Command::ResetLink { username } => {
+            let user = find_user(conn, &username).await?;
+            let link = password::issue_reset_link(&state, user.id, ONBOARDING_ALLOWED_TIME).await?;
+            // the link itself is as good as the password, so it stays out of the log
+            record(
+                &state,
+                Entry::new(Actor::Cli, "password.reset_link").target(&username),
+            )
+            .await;
+            output(
+                json,
+                &serde_json::json!({ "username": username, "link": link }),
+                |_| println!("{link}"),
+            )
+        }
+        Command::Show { username } => {
+            let club = clubs::table
+                .left_join(club_socials::table)
+                .filter(clubs::username.eq(&username))
+                .first(conn)
+                .await
+                .optional()?
+                .ok_or_else(|| anyhow!("no club with username {username}"))?;
+            let club = club::load_clubs(conn, vec![club])
+                .await
+                .map_err(|e| anyhow!("{e}"))?
+                .pop()
+                .context("`load_clubs` should return one club")?;
+            output(json, &club, print_club)
+        }
+        Command::Reset2fa { username } => {
+            let was_enabled = two_factor::reset(&state, &username)
+                .await
+                .map_err(|e| anyhow!("{e}"))?;
+            record(
+                &state,
+                Entry::new(Actor::Cli, "2fa.reset")
+                    .target(&username)
+                    .before(serde_json::json!({ "enabled": was_enabled }))
+                    .after(serde_json::json!({ "enabled": false })),
+            )
+            .await;
+            output(
+                json,
+                &serde_json::json!({ "username": username, "wasEnabled": was_enabled }),
+                |_| {
+                    if was_enabled {
+                        println!("turned off two-factor authentication for {username}")
+                    } else {
+                        println!("{username} doesn't have two-factor authentication")
+                    }
+                },
+            )
+        }
+        Command::Transfer { username, email } => {
+            let transfer = transfer::nominate(&state, &username, email, None)
+                .await
+                .map_err(|e| anyhow!("{e}"))?;
+            record(
+                &state,
+                Entry::new(Actor::Cli, "transfer.nominate")
+                    .club_named(&username)
+                    .after(&transfer),
+            )
+            .await;
+            output(json, &transfer, |transfer| {
+                println!(
+                    "emailed {} a link to take over {username}, valid until {}",
+                    transfer.new_email, transfer.expires_at
+                )
+            })
+        }
+        Command::Transfers { username } => {
+            let transfers = transfer::history(&state, &username)
+                .await
+                .map_err(|e| anyhow!("{e}"))?;
+            output(json, &transfers, |transfers| {
+                for transfer in transfers {
+                    let by = transfer.requested_by.as_deref().unwrap_or("an admin");
+                    match transfer.completed_at {
+                        Some(completed_at) => println!(
+                            "{completed_at}  {} -> {} ({}), requested by {by}",
+                            transfer.from.as_deref().unwrap_or("nobody"),
+                            transfer.to.as_deref().unwrap_or("a deleted user"),
+                            transfer.new_email,
+                        ),
+                        None => println!(
+                            "pending until {}  -> {}, requested by {by}",
+                            transfer.expires_at, transfer.new_email
+                        ),
+                    }
+                }
+            })
+        }
+        Command::Applications { status } => {
+            let applications = applications::list(&state, status)
+                .await
+                .map_err(|e| anyhow!("{e}"))?;
+            output(json, &applications, |applications| {
+                for application in applications {
+                    println!(
+                        "{:<6}{:<10}{} ({}, advisor {})",
+                        application.id,
+                        application.status.to_string(),
+                        application.club_name,
+                        application.email,
+                        application.advisor
+                    );
+                }
+            })
+        }
+        Command::Approve { id, username } => {
+            let club = applications::approve(&state, id, username)
+                .await
+                .map_err(|e| anyhow!("{e}"))?;
+            record(
+                &state,
+                Entry::new(Actor::Cli, "application.approve")
+                    .club(&club)
+                    .target(format!("application {id}"))
+                    .after(&club),
+            )
+            .await;
+            output(json, &Registered::from(&club), |club| {
+                println!("registered {} and emailed {}", club.username, club.email)
+            })
+        }
+        Command::Reject { id, reason } => {
+            let application = applications::reject(&state, id, reason)
+                .await
+                .map_err(|e| anyhow!("{e}"))?;
+            record(
+                &state,
+                Entry::new(Actor::Cli, "application.reject")
+                    .target(format!("application {id}"))
+                    .after(&application),
+            )
+            .await;
+            output(json, &application, |application| {
+                println!(
+                    "rejected {} and emailed {}",
+                    application.club_name, application.email
+                )
+            })
+        }
+        Command::Drafts { status } => {
+            let drafts = drafts::list(&state, status)
+                .await
+                .map_err(|e| anyhow!("{e}"))?;
+            output(json, &drafts, |drafts| {
+                for draft in drafts {
+                    println!(
+                        "{:<6}{:<12}{} by {} at {}",
+                        draft.id,
+                        draft.status.to_string(),
+                        draft.club,
+                        draft.submitted_by.as_deref().unwrap_or("a removed user"),
+                        draft.submitted_at
+                    );
+                }
+            })
+        }
+        Command::ApproveDraft { id, comment } => {
+            let draft = drafts::approve(&state, id, comment)
+                .await
+                .map_err(|e| anyhow!("{e}"))?;
+            record(
+                &state,
+                Entry::new(Actor::Cli, "draft.approve")
+                    .club_named(&draft.club)
+                    .target(format!("draft {id}"))
+                    .after(&draft),
+            )
+            .await;
+            output(json, &draft, |draft| {
+                println!("published the edit of {}", draft.club)
+            })
+        }
+        Command::RejectDraft { id, comment } => {
+            let draft = drafts::reject(&state, id, comment)
+                .await
+                .map_err(|e| anyhow!("{e}"))?;
+            record(
+                &state,
+                Entry::new(Actor::Cli, "draft.reject")
+                    .club_named(&draft.club)
+                    .target(format!("draft {id}"))
+                    .after(&draft),
+            )
+            .await;
+            output(json, &draft, |draft| {
+                println!("rejected the edit of {}", draft.club)
+            })
+        }
+        Command::Categories(command) => categories(&state, conn, json, command).await,
+        Command::PasswordHashes => {
+            let report = admin::hash_report(&state)
+                .await
+                .map_err(|e| anyhow!("{e}"))?;
+            output(json, &report, |report| {
+                for s in &report.settings {
+                    let current = if s.current { " (current)" } else { "" };
+                    println!("{}\t{}{current}", s.users, s.settings);
+                }
+                println!(
+                    "{} of {} users will be re-hashed when they next log in",
+                    report.outdated, report.total
+                );
+            })
+        }
+        Command::Backup { file } => {
+            let archive = backup::create(&state).await?;
+            fs::write(&file, &archive)
+                .with_context(|| format!("could not write {}", file.display()))?;
+            output(
+                json,
+                &serde_json::json!({ "file": file, "bytes": archive.len() }),
+                |_| println!("wrote backup to {}", file.display()),
+            )
+        }
+        Command::Restore { file, yes } => restore(&state, json, file, yes).await,
+    }
+}
+
+async fn restore(state: &AppState, json: bool, file: PathBuf, yes: bool) -> anyhow::Result<()> {
+    let archive =
+        fs::File::open(&file).with_context(|| format!("could not open {}", file.display()))?;
+    let backup = backup::read(archive)?;
+
+    let manifest = &backup.manifest;
+    let summary = serde_json::json!({
+        "createdAt": manifest.created_at,
+        "schemaVersion": manifest.schema_version,
+        "clubs": manifest.clubs.len(),
+        "categories": manifest.categories.len(),
+        "assets": manifest.assets.len(),
+        "restored": yes,
+    });
+    let text = format!(
+        "backup from {} (schema version {}) with {} clubs, {} categories and {} assets",
+        manifest.created_at,
+        manifest.schema_version,
+        manifest.clubs.len(),
+        manifest.categories.len(),
+        manifest.assets.len()
+    );
+
+    if !yes {
+        output(json, &summary, |_| println!("{text}"))?;
+        bail!("restoring replaces every club in the database, pass --yes to continue");
+    }
+
+    backup::restore(state, backup).await?;
+    record(
+        state,
+        Entry::new(Actor::Cli, "backup.restore").after(&summary),
+    )
+    .await;
+    output(json, &summary, |_| println!("restored {text}"))
+}
+
+async fn bulk_register(state: &AppState, json: bool, file: PathBuf) -> anyhow::Result<()> {
+    #[derive(Serialize)]
+    struct RowResult {
+        row: usize,
+        username: String,
+        registered: bool,
+        message: String,
+    }
+
+    let mut reader = csv::Reader::from_path(&file)
+        .with_context(|| format!("could not open {}", file.display()))?;
+    let mut results = Vec::new();
+
+    // row 1 is the header
+    for (row, parsed) in reader.deserialize::<ClubRegisterRequest>().enumerate() {
+        let row = row + 2;
+        let result = match parsed {
+            Ok(req) => {
+                let username = req.username.clone();
+                match admin::register_club(state, req).await {
+                    Ok(club) => {
+                        record(
+                            state,
+                            Entry::new(Actor::Cli, "club.register")
+                                .club(&club)
+                                .after(&club),
+                        )
+                        .await;
+                        RowResult {
+                            row,
+                            username,
+                            registered: true,
+                            message: format!("sent onboarding email to {}", club.email),
+                        }
+                    }
+                    Err(e) => RowResult {
+                        row,
+                        username,
+                        registered: false,
+                        message: e.to_string(),
+                    },
+                }
+            }
+            Err(e) => RowResult {
+                row,
+                username: String::new(),
+                registered: false,
+                message: e.to_string(),
+            },
+        };
+        results.push(result);
+    }
+
+    let failed = results.iter().filter(|r| !r.registered).count();
+    output(json, &results, |results| {
+        for r in results {
+            let status = if r.registered { "ok" } else { "FAILED" };
+            println!("row {}\t{}\t{status}\t{}", r.row, r.username, r.message);
+        }
+        println!(
+            "registered {} of {} clubs",
+            results.len() - failed,
+            results.len()
+        );
+    })?;
+
+    if failed > 0 {
+        bail!("{failed} rows could not be registered");
+    }
+    Ok(())
+}
+
+async fn categories(
+    state: &AppState,
+    conn: &mut AsyncPgConnection,
+    json: bool,
+    command: CategoryCommand,
+) -> anyhow::Result<()> {
+    match command {
+        CategoryCommand::List => {
+            let names: Vec<String> = categories::table
+                .order(categories::category_name)
+                .load::<Category>(conn)
+                .await?
+                .into_iter()
+                .map(|c| c.category_name)
+                .collect();
+            output(json, &names, |names| {
+                for name in names {
+                    println!("{name}")
+                }
+            })
+        }
+        CategoryCommand::Add { name } => {
+            diesel::insert_into(categories::table)
+                .values(categories::category_name.eq(&name))
+                .execute(conn)
+                .await
+                .with_context(|| format!("could not add category {name}"))?;
+            record(state, Entry::new(Actor::Cli, "category.add").target(&name)).await;
+            output(json, &serde_json::json!({ "added": name }), |_| {
+                println!("added category {name}")
+            })
+        }
+        CategoryCommand::Rename { from, to } => {
+            let updated =
+                diesel::update(categories::table.filter(categories::category_name.eq(&from)))
+                    .set(categories::category_name.eq(&to))
+                    .execute(conn)
+                    .await?;
+            if updated == 0 {
+                bail!("no category named {from}");
+            }
+            record(
+                state,
+                Entry::new(Actor::Cli, "category.rename")
+                    .target(&from)
+                    .after(serde_json::json!({ "name": to })),
+            )
+            .await;
+            output(
+                json,
+                &serde_json::json!({ "renamed": from, "to": to }),
+                |_| println!("renamed category {from} to {to}"),
+            )
+        }
+        CategoryCommand::Remove { name } => {
+            let category = categories::table
+                .filter(categories::category_name.eq(&name))
+                .first::<Category>(conn)
+                .await
+                .optional()?
+                .ok_or_else(|| anyhow!("no category named {name}"))?;
+
+            let clubs = diesel::delete(
+                club_categories::table.filter(club_categories::category_id.eq(category.id)),
+            )
+            .execute(conn)
+            .await?;
+            diesel::delete(categories::table.find(category.id))
+                .execute(conn)
+                .await?;
+            record(
+                state,
+                Entry::new(Actor::Cli, "category.remove")
+                    .target(&name)
+                    .after(serde_json::json!({ "clubs": clubs })),
+            )
+            .await;
+
+            output(
+                json,
+                &serde_json::json!({ "removed": name, "clubs": clubs }),
+                |_| println!("removed category {name} from {clubs} clubs"),
+            )
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Registered {
+    username: String,
+    email: String,
+}
+
+impl From<&Club> for Registered {
+    fn from(club: &Club) -> Self {
+        Self {
+            username: club.username.clone(),
+            email: club.email.clone(),
+        }
+    }
+}
+
+/// Writes `entry` to the audit log. There is no request to take an ip or user agent from.
+async fn record(state: &AppState, entry: Entry) {
+    audit::record(state, &Client::default(), entry).await;
+}
+
+/// What the audit log records of a club's place in its life, like the admin api does.
+fn lifecycle_state(status: ClubStatus, deleted: bool) -> serde_json::Value {
+    serde_json::json!({ "status": status, "deleted": deleted })
+}
+
+async fn find_user(conn: &mut AsyncPgConnection, username: &str) -> anyhow::Result<User> {
+    users::table
+        .filter(users::username.eq(username))
+        .first::<User>(conn)
+        .await
+        .optional()?
+        .ok_or_else(|| anyhow!("no user with username {username}"))
+}
+
+fn print_club(club: &ClubResponse) {
+    let show = |label: &str, value: &Option<String>| {
+        if let Some(value) = value {
+            println!("{label:<18}{value}");
+        }
+    };
+
+    println!("{:<18}{}", "username", club.id);
+    println!("{:<18}{}", "name", club.club_name);
+    println!("{:<18}{}", "email", club.email);
+    println!("{:<18}{}", "description", club.description);
+    println!("{:<18}{}", "meet time", club.meet_time);
+    println!("{:<18}{}", "featured", club.featured);
+    println!("{:<18}{}", "status", club.status);
+    if let Some(deleted_at) = club.deleted_at {
+        println!("{:<18}{}", "deleted at", deleted_at);
+    }
+    println!("{:<18}{}", "registered", club.created_at);
+    println!("{:<18}{}", "updated", club.updated_at);
+    println!("{:<18}{}", "profile picture", club.profile_picture_url);
+    println!("{:<18}{}", "categories", club.categories.join(", "));
+    show("website", &club.socials.website);
+    show("google classroom", &club.socials.google_classroom);
+    show("discord", &club.socials.discord);
+    show("instagram", &club.socials.instagram);
+    println!("about:\n{}", club.about);
+}
+
+/// Prints `value` as JSON, or with `text` otherwise.
+fn output<T: Serialize>(json: bool, value: &T, text: impl FnOnce(&T)) -> anyhow::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(value)?);
+    } else {
+        text(value);
+    }
+    Ok(())
+}
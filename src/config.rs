@@ -0,0 +1,694 @@
+use crate::keyring::Keys;
+use argon2::{Algorithm, Argon2, Params, Version};
+use chrono::{DateTime, NaiveDate, Utc};
+use lettre::Address;
+use serde::Deserialize;
+use std::{fmt, fs, path::Path, str::FromStr, time::Duration};
+use url::Url;
+
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+/// All server configuration, loaded once at startup.
+///
+/// Values come from an optional TOML file (`CONFIG_FILE`, or `config.toml` if it exists)
+/// and are then overridden by environment variables. See `config.sample.toml`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppConfig {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub auth: AuthConfig,
+    pub email: EmailConfig,
+    pub rate_limit: RateLimitConfig,
+    pub password_hash: PasswordHashConfig,
+    pub password_policy: PasswordPolicyConfig,
+    pub moderation: ModerationConfig,
+    pub reregistration: ReregistrationConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub port: u16,
+    /// seconds to let in-flight requests and background tasks finish on shutdown
+    pub shutdown_timeout: u64,
+    /// used to build links in emails
+    pub frontend_host: String,
+    /// origins browsers may call the api from, `frontend_host` if empty
+    pub allowed_origins: Vec<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            port: 8080,
+            shutdown_timeout: 10,
+            frontend_host: String::new(),
+            allowed_origins: Vec::new(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// The origins allowed by CORS, serialized like browsers send them in `Origin`.
+    pub fn allowed_origins(&self) -> Vec<String> {
+        let origins = if self.allowed_origins.is_empty() {
+            std::slice::from_ref(&self.frontend_host)
+        } else {
+            &self.allowed_origins
+        };
+
+        origins
+            .iter()
+            .filter_map(|origin| Url::parse(origin).ok())
+            .map(|url| url.origin().ascii_serialization())
+            .collect()
+    }
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub url: String,
+    /// apply pending migrations when the server starts
+    pub run_migrations: bool,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// base64 encoded HS256 secret, the key with kid `default`
+    pub jwt_secret: String,
+    pub admin_key: String,
+    /// more keys to sign and verify tokens with
+    pub jwt_keys: Vec<JwtKeyConfig>,
+    /// kid of the key new tokens are signed with, the first key that isn't retired if
+    /// empty
+    pub signing_key: String,
+    /// seconds a retired key keeps verifying tokens, should be at least the token lifetime
+    pub key_grace_period: u64,
+}
+
+impl fmt::Debug for DatabaseConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the url can have a password in it
+        let url = match Url::parse(&self.url) {
+            Ok(mut url) if url.password().is_some() => {
+                let _ = url.set_password(Some("redacted"));
+                url.to_string()
+            }
+            _ => self.url.clone(),
+        };
+        f.debug_struct("DatabaseConfig")
+            .field("url", &url)
+            .field("run_migrations", &self.run_migrations)
+            .finish()
+    }
+}
+
+impl fmt::Debug for AuthConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthConfig")
+            .field("jwt_secret", &Redacted(&self.jwt_secret))
+            .field("admin_key", &Redacted(&self.admin_key))
+            .field("jwt_keys", &self.jwt_keys)
+            .field("signing_key", &self.signing_key)
+            .field("key_grace_period", &self.key_grace_period)
+            .finish()
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            jwt_secret: String::new(),
+            admin_key: String::new(),
+            jwt_keys: Vec::new(),
+            signing_key: String::new(),
+            // 1 day, as long as a token lasts
+            key_grace_period: 24 * 60 * 60,
+        }
+    }
+}
+
+/// A key in the jwt keyring, see [`Keys`].
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JwtKeyConfig {
+    pub kid: String,
+    /// HS256, RS256 or EdDSA
+    #[serde(default)]
+    pub algorithm: jsonwebtoken::Algorithm,
+    /// HS256 only, base64 encoded
+    #[serde(default)]
+    pub secret: String,
+    /// RS256 and EdDSA, a PEM private key or a file containing one
+    #[serde(default)]
+    pub private_key: String,
+    #[serde(default)]
+    pub private_key_file: String,
+    /// when the key stops signing tokens, it still verifies them for
+    /// `key_grace_period` after
+    #[serde(default)]
+    pub retired_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmailConfig {
+    pub username: String,
+    pub password: String,
+    pub relay: String,
+}
+
+impl fmt::Debug for JwtKeyConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtKeyConfig")
+            .field("kid", &self.kid)
+            .field("algorithm", &self.algorithm)
+            .field("secret", &Redacted(&self.secret))
+            .field("private_key", &Redacted(&self.private_key))
+            .field("private_key_file", &self.private_key_file)
+            .field("retired_at", &self.retired_at)
+            .finish()
+    }
+}
+
+impl fmt::Debug for EmailConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EmailConfig")
+            .field("username", &self.username)
+            .field("password", &Redacted(&self.password))
+            .field("relay", &self.relay)
+            .finish()
+    }
+}
+
+impl Default for EmailConfig {
+    fn default() -> Self {
+        Self {
+            username: String::new(),
+            password: String::new(),
+            relay: "smtp.gmail.com".to_string(),
+        }
+    }
+}
+
+impl EmailConfig {
+    /// The address emails are sent from.
+    pub fn address(&self) -> Address {
+        self.username
+            .parse()
+            .expect("email username is checked when the config is loaded")
+    }
+}
+
+/// Argon2 settings for new password hashes. Existing hashes keep working after these
+/// change, and are re-hashed when their club logs in.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PasswordHashConfig {
+    /// argon2id, argon2i or argon2d
+    pub algorithm: String,
+    /// 19 (0x13) or 16 (0x10)
+    pub version: u32,
+    /// KiB
+    pub memory_cost: u32,
+    /// iterations
+    pub time_cost: u32,
+    /// lanes
+    pub parallelism: u32,
+}
+
+impl Default for PasswordHashConfig {
+    // the defaults of the argon2 crate, which every hash was made with before this was
+    // configurable
+    fn default() -> Self {
+        Self {
+            algorithm: "argon2id".to_string(),
+            version: 19,
+            memory_cost: Params::DEFAULT_M_COST,
+            time_cost: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl PasswordHashConfig {
+    pub fn argon2(&self) -> Result<Argon2<'static>, String> {
+        let algorithm = Algorithm::new(&self.algorithm)
+            .map_err(|_| format!("unknown algorithm {}", self.algorithm))?;
+        let version = Version::try_from(self.version)
+            .map_err(|_| format!("unknown version {}, expected 16 or 19", self.version))?;
+        let params = Params::new(self.memory_cost, self.time_cost, self.parallelism, None)
+            .map_err(|e| e.to_string())?;
+        Ok(Argon2::new(algorithm, version, params))
+    }
+}
+
+/// What new passwords have to satisfy, see [`crate::password_policy`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PasswordPolicyConfig {
+    /// characters
+    pub min_length: usize,
+    /// zxcvbn-style score from 0 (too guessable) to 4 (very unguessable)
+    pub min_strength: u8,
+}
+
+impl Default for PasswordPolicyConfig {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            min_strength: 3,
+        }
+    }
+}
+
+/// Whether club profile edits wait for an admin, see [`crate::api::drafts`].
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModerationConfig {
+    /// edits of clubs that aren't trusted become drafts instead of going live. Profile
+    /// pictures aren't included
+    pub edits: bool,
+}
+
+/// Yearly confirmation that club profiles are still accurate, see
+/// [`crate::api::reregistration`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReregistrationConfig {
+    /// email reminders and move clubs that don't confirm to inactive
+    pub enabled: bool,
+    /// month and day the school year starts, like `08-15`
+    pub school_year_start: String,
+    /// days after the school year starts that clubs have to confirm by
+    pub deadline_days: u32,
+    /// days between reminders to a club that hasn't confirmed
+    pub reminder_interval_days: u32,
+}
+
+impl Default for ReregistrationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            school_year_start: "08-01".to_string(),
+            deadline_days: 60,
+            reminder_interval_days: 14,
+        }
+    }
+}
+
+impl ReregistrationConfig {
+    /// The month and day of `school_year_start`.
+    pub fn school_year_start(&self) -> Result<(u32, u32), String> {
+        let invalid = || {
+            format!(
+                "invalid school year start {}, expected a month and day like 08-15",
+                self.school_year_start
+            )
+        };
+        let (month, day) = self.school_year_start.split_once('-').ok_or_else(invalid)?;
+        let month = month.parse().map_err(|_| invalid())?;
+        let day = day.parse().map_err(|_| invalid())?;
+        // every year has to have the date
+        NaiveDate::from_ymd_opt(2001, month, day).ok_or_else(invalid)?;
+        Ok((month, day))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub backend: RateLimitBackend,
+    /// header a reverse proxy puts the client ip in (e.g. `Fly-Client-IP`), the peer
+    /// address is used if empty
+    pub client_ip_header: String,
+    /// login attempts, per ip and per username
+    pub login: Limit,
+    /// password reset emails, per ip and per email
+    pub password_reset: Limit,
+    /// wrong admin keys, per ip
+    pub admin_key: Limit,
+    /// club applications, per ip
+    pub club_application: Limit,
+    /// failed logins within `failed_login_window` seconds that lock an account
+    pub max_failed_logins: u32,
+    pub failed_login_window: u64,
+    /// seconds a locked account stays locked
+    pub lockout: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            backend: RateLimitBackend::Memory,
+            client_ip_header: String::new(),
+            login: Limit {
+                requests: 10,
+                window: 60,
+            },
+            password_reset: Limit {
+                requests: 5,
+                window: 60 * 60,
+            },
+            admin_key: Limit {
+                requests: 5,
+                window: 60 * 60,
+            },
+            club_application: Limit {
+                requests: 3,
+                window: 24 * 60 * 60,
+            },
+            max_failed_logins: 5,
+            failed_login_window: 15 * 60,
+            lockout: 15 * 60,
+        }
+    }
+}
+
+/// Where rate limit counters are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitBackend {
+    /// per instance
+    Memory,
+    /// shared between every instance using the database
+    Postgres,
+}
+
+impl FromStr for RateLimitBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "memory" => Ok(Self::Memory),
+            "postgres" => Ok(Self::Postgres),
+            _ => Err(format!("expected memory or postgres, got {s}")),
+        }
+    }
+}
+
+/// At most `requests` requests every `window` seconds.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limit {
+    pub requests: u32,
+    pub window: u64,
+}
+
+impl Limit {
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.window)
+    }
+}
+
+/// Shows whether a secret is set, for `Debug` impls that mustn't print it.
+struct Redacted<'a>(&'a str);
+
+impl fmt::Debug for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            write!(f, "\"\"")
+        } else {
+            write!(f, "<redacted>")
+        }
+    }
+}
+
+/// Every problem found while loading the config, so they can all be fixed at once.
+#[derive(Debug, Clone)]
+pub struct ConfigError(pub Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid configuration:")?;
+        for problem in &self.0 {
+            write!(f, "\n  - {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl AppConfig {
+    /// Loads the config from the config file and the process environment.
+    pub fn load() -> Result<Self, ConfigError> {
+        Self::from_sources(read_config_file()?.as_deref(), |name| {
+            std::env::var(name).ok()
+        })
+    }
+
+    /// Like [`AppConfig::load`], but only reports values that couldn't be parsed.
+    /// Useful for commands that only need part of the config.
+    pub fn load_unchecked() -> Result<Self, ConfigError> {
+        let (config, problems) = Self::read_sources(read_config_file()?.as_deref(), |name| {
+            std::env::var(name).ok()
+        })?;
+
+        if problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError(problems))
+        }
+    }
+
+    /// Builds a config from TOML source and an environment lookup, then validates it.
+    pub fn from_sources(
+        toml_source: Option<&str>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ConfigError> {
+        let (config, mut problems) = Self::read_sources(toml_source, env)?;
+        problems.extend(config.problems());
+
+        if problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError(problems))
+        }
+    }
+
+    /// Returns the config along with every environment variable that couldn't be parsed.
+    fn read_sources(
+        toml_source: Option<&str>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<(Self, Vec<String>), ConfigError> {
+        let mut config = match toml_source {
+            Some(source) => toml::from_str::<AppConfig>(source)
+                .map_err(|e| ConfigError(vec![format!("config file: {e}")]))?,
+            None => AppConfig::default(),
+        };
+
+        let mut problems = Vec::new();
+        config.apply_env(&env, &mut problems);
+        Ok((config, problems))
+    }
+
+    fn apply_env(&mut self, env: &impl Fn(&str) -> Option<String>, problems: &mut Vec<String>) {
+        let mut set = |name: &str, apply: &mut dyn FnMut(&str) -> Result<(), String>| {
+            // empty variables (like the ones in .env.sample) count as unset
+            if let Some(value) = env(name).filter(|v| !v.is_empty()) {
+                if let Err(e) = apply(&value) {
+                    problems.push(format!("{name}: {e}"));
+                }
+            }
+        };
+
+        set("PORT", &mut |v| parse_into(v, &mut self.server.port));
+        set("SHUTDOWN_TIMEOUT", &mut |v| {
+            parse_into(v, &mut self.server.shutdown_timeout)
+        });
+        set("FRONTEND_HOST", &mut |v| {
+            parse_into(v, &mut self.server.frontend_host)
+        });
+        set("ALLOWED_ORIGINS", &mut |v| {
+            self.server.allowed_origins = v
+                .split(',')
+                .map(str::trim)
+                .filter(|origin| !origin.is_empty())
+                .map(str::to_string)
+                .collect();
+            Ok(())
+        });
+        set("DATABASE_URL", &mut |v| {
+            parse_into(v, &mut self.database.url)
+        });
+        set("RUN_MIGRATIONS", &mut |v| {
+            parse_into(v, &mut self.database.run_migrations)
+        });
+        set("JWT_SECRET", &mut |v| {
+            parse_into(v, &mut self.auth.jwt_secret)
+        });
+        set("JWT_SIGNING_KEY", &mut |v| {
+            parse_into(v, &mut self.auth.signing_key)
+        });
+        set("JWT_KEY_GRACE_PERIOD", &mut |v| {
+            parse_into(v, &mut self.auth.key_grace_period)
+        });
+        set("ADMIN_KEY", &mut |v| {
+            parse_into(v, &mut self.auth.admin_key)
+        });
+        set("EMAIL_USERNAME", &mut |v| {
+            parse_into(v, &mut self.email.username)
+        });
+        set("EMAIL_PASSWORD", &mut |v| {
+            parse_into(v, &mut self.email.password)
+        });
+        set("EMAIL_RELAY", &mut |v| parse_into(v, &mut self.email.relay));
+        set("PASSWORD_HASH_ALGORITHM", &mut |v| {
+            parse_into(v, &mut self.password_hash.algorithm)
+        });
+        set("PASSWORD_HASH_VERSION", &mut |v| {
+            parse_into(v, &mut self.password_hash.version)
+        });
+        set("PASSWORD_HASH_MEMORY_COST", &mut |v| {
+            parse_into(v, &mut self.password_hash.memory_cost)
+        });
+        set("PASSWORD_HASH_TIME_COST", &mut |v| {
+            parse_into(v, &mut self.password_hash.time_cost)
+        });
+        set("PASSWORD_HASH_PARALLELISM", &mut |v| {
+            parse_into(v, &mut self.password_hash.parallelism)
+        });
+        set("PASSWORD_MIN_LENGTH", &mut |v| {
+            parse_into(v, &mut self.password_policy.min_length)
+        });
+        set("PASSWORD_MIN_STRENGTH", &mut |v| {
+            parse_into(v, &mut self.password_policy.min_strength)
+        });
+        set("MODERATE_EDITS", &mut |v| {
+            parse_into(v, &mut self.moderation.edits)
+        });
+        set("REREGISTRATION_ENABLED", &mut |v| {
+            parse_into(v, &mut self.reregistration.enabled)
+        });
+        set("SCHOOL_YEAR_START", &mut |v| {
+            parse_into(v, &mut self.reregistration.school_year_start)
+        });
+        set("REREGISTRATION_DEADLINE_DAYS", &mut |v| {
+            parse_into(v, &mut self.reregistration.deadline_days)
+        });
+        set("REREGISTRATION_REMINDER_DAYS", &mut |v| {
+            parse_into(v, &mut self.reregistration.reminder_interval_days)
+        });
+        set("RATE_LIMIT_BACKEND", &mut |v| {
+            parse_into(v, &mut self.rate_limit.backend)
+        });
+        set("CLIENT_IP_HEADER", &mut |v| {
+            parse_into(v, &mut self.rate_limit.client_ip_header)
+        });
+    }
+
+    /// Checks the config for missing or malformed values.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let problems = self.problems();
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError(problems))
+        }
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut require = |value: &str, name: &str| {
+            if value.is_empty() {
+                problems.push(format!("{name} must be set"));
+                false
+            } else {
+                true
+            }
+        };
+
+        let has_host = require(
+            &self.server.frontend_host,
+            "server.frontend_host (FRONTEND_HOST)",
+        );
+        require(&self.database.url, "database.url (DATABASE_URL)");
+        require(&self.auth.admin_key, "auth.admin_key (ADMIN_KEY)");
+        let has_email = require(&self.email.username, "email.username (EMAIL_USERNAME)");
+        require(&self.email.password, "email.password (EMAIL_PASSWORD)");
+        require(&self.email.relay, "email.relay (EMAIL_RELAY)");
+
+        if has_host && Url::parse(&self.server.frontend_host).is_err() {
+            problems.push("server.frontend_host (FRONTEND_HOST) is not a valid url".to_string());
+        }
+        for origin in &self.server.allowed_origins {
+            if !Url::parse(origin).is_ok_and(|url| url.origin().is_tuple()) {
+                problems.push(format!(
+                    "server.allowed_origins (ALLOWED_ORIGINS): {origin} is not a valid origin"
+                ));
+            }
+        }
+        if let Err(e) = self.password_hash.argon2() {
+            problems.push(format!("password_hash: {e}"));
+        }
+        if self.password_policy.min_strength > 4 {
+            problems.push(
+                "password_policy.min_strength (PASSWORD_MIN_STRENGTH) must be between 0 and 4"
+                    .to_string(),
+            );
+        }
+        if let Err(e) = self.reregistration.school_year_start() {
+            problems.push(format!(
+                "reregistration.school_year_start (SCHOOL_YEAR_START): {e}"
+            ));
+        }
+        if self.reregistration.reminder_interval_days == 0 {
+            problems.push(
+                "reregistration.reminder_interval_days (REREGISTRATION_REMINDER_DAYS) must be positive"
+                    .to_string(),
+            );
+        }
+        let rate_limit = &self.rate_limit;
+        for (name, limit) in [
+            ("login", rate_limit.login),
+            ("password_reset", rate_limit.password_reset),
+            ("admin_key", rate_limit.admin_key),
+            ("club_application", rate_limit.club_application),
+        ] {
+            if limit.requests == 0 || limit.window == 0 {
+                problems.push(format!(
+                    "rate_limit.{name} needs at least one request and a window of at least a second"
+                ));
+            }
+        }
+        if rate_limit.max_failed_logins == 0 || rate_limit.failed_login_window == 0 {
+            problems.push(
+                "rate_limit.max_failed_logins and rate_limit.failed_login_window must be positive"
+                    .to_string(),
+            );
+        }
+        if let Err(e) = Keys::from_config(&self.auth) {
+            problems.push(format!("{e:#}"));
+        }
+        if has_email && self.email.username.parse::<Address>().is_err() {
+            problems
+                .push("email.username (EMAIL_USERNAME) is not a valid email address".to_string());
+        }
+
+        problems
+    }
+}
+
+fn read_config_file() -> Result<Option<String>, ConfigError> {
+    let path = match std::env::var("CONFIG_FILE") {
+        Ok(path) => path,
+        Err(_) if Path::new(DEFAULT_CONFIG_FILE).exists() => DEFAULT_CONFIG_FILE.to_string(),
+        Err(_) => return Ok(None),
+    };
+
+    fs::read_to_string(&path)
+        .map(Some)
+        .map_err(|e| ConfigError(vec![format!("could not read {path}: {e}")]))
+}
+
+fn parse_into<T: FromStr>(value: &str, field: &mut T) -> Result<(), String>
+where
+    T::Err: fmt::Display,
+{
+    *field = value.parse().map_err(|e| format!("{e}"))?;
+    Ok(())
+}
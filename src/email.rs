@@ -1,26 +1,132 @@
+use crate::{config::EmailConfig, tasks::Shutdown};
+use axum::async_trait;
 use lettre::{
-    message::Mailbox,
-    transport::smtp::{
-        authentication::Credentials, response::Response as LettreResponse, Error as LettreError,
-    },
-    Address, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+    message::Mailbox, transport::smtp::authentication::Credentials, Address, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
 };
-use std::env::var;
-
-lazy_static::lazy_static! {
-    static ref EMAIL_USERNAME: String = var("EMAIL_USERNAME").expect("email username must be set for password reset responses");
-    static ref EMAIL_PASSWORD: String = var("EMAIL_PASSWORD").expect("email access password must be set for password reset responses");
-    static ref CREDS: Credentials = Credentials::new(EMAIL_USERNAME.to_string(), EMAIL_PASSWORD.to_string());
-    pub static ref EMAIL_ADDRESS: Address = EMAIL_USERNAME.to_string().parse::<Address>().expect("invalid email username");
-    // maybe figure out a better place to the HOST var
-    pub static ref FRONTEND_HOST: String = var("FRONTEND_HOST").expect("FRONTEND_HOST must be set for correct password reset urls to be generated");
-}
-
-pub async fn sanity_check() -> Result<(), LettreError> {
-    let mbox = Mailbox::new(
-        Some("apathetic programmers".to_string()),
-        EMAIL_ADDRESS.clone(),
-    );
+use std::{future::Future, pin::Pin, sync::Arc};
+use tokio::sync::{mpsc, Mutex};
+
+/// Sends emails on behalf of the server. Swap it out to capture emails in tests.
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    /// The address emails are sent from.
+    fn address(&self) -> Address;
+
+    async fn send(&self, msg: Message) -> anyhow::Result<()>;
+}
+
+pub struct SmtpMailer {
+    address: Address,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpMailer {
+    pub fn from_config(config: &EmailConfig) -> anyhow::Result<SmtpMailer> {
+        Ok(SmtpMailer {
+            address: config.address(),
+            transport: AsyncSmtpTransport::<Tokio1Executor>::relay(&config.relay)?
+                .credentials(Credentials::new(
+                    config.username.clone(),
+                    config.password.clone(),
+                ))
+                .build(),
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    fn address(&self) -> Address {
+        self.address.clone()
+    }
+
+    async fn send(&self, msg: Message) -> anyhow::Result<()> {
+        self.transport.send(msg).await?;
+        Ok(())
+    }
+}
+
+/// Emails waiting to be sent in the background by [`send_queued`], so a request doesn't
+/// wait on the mail server and shutdown can wait for the ones that are still queued.
+#[derive(Clone)]
+pub struct Outbox {
+    queue: mpsc::UnboundedSender<QueuedEmail>,
+    queued: Queued,
+}
+
+/// The receiving end of an [`Outbox`]. Holding it doesn't keep the outbox open.
+#[derive(Clone)]
+pub struct Queued(Arc<Mutex<mpsc::UnboundedReceiver<QueuedEmail>>>);
+
+struct QueuedEmail {
+    what: &'static str,
+    send: Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>,
+}
+
+impl QueuedEmail {
+    async fn send(self) {
+        if let Err(e) = self.send.await {
+            eprintln!("failed to send {} email: {e}", self.what);
+        }
+    }
+}
+
+impl Default for Outbox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Outbox {
+    pub fn new() -> Self {
+        let (queue, queued) = mpsc::unbounded_channel();
+        Self {
+            queue,
+            queued: Queued(Arc::new(Mutex::new(queued))),
+        }
+    }
+
+    /// Queues `send`, which builds and sends a `what` email. Failures are only printed.
+    pub fn send<F>(&self, what: &'static str, send: F)
+    where
+        F: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let email = QueuedEmail {
+            what,
+            send: Box::pin(send),
+        };
+        // the receiver is kept in `queued`, so this can't fail
+        let _ = self.queue.send(email);
+    }
+
+    pub fn queued(&self) -> Queued {
+        self.queued.clone()
+    }
+}
+
+/// Sends queued emails one at a time. Once `shutdown` fires it keeps going until every
+/// [`Outbox`] has been dropped, since requests that are still finishing can queue more.
+pub async fn send_queued(queued: Queued, mut shutdown: Shutdown) {
+    let mut queue = queued.0.lock().await;
+    loop {
+        let email = tokio::select! {
+            email = queue.recv() => email,
+            _ = shutdown.cancelled() => break,
+        };
+        match email {
+            Some(email) => email.send().await,
+            None => return,
+        }
+    }
+
+    while let Some(email) = queue.recv().await {
+        email.send().await;
+    }
+}
+
+pub async fn sanity_check(mailer: &dyn Mailer) -> anyhow::Result<()> {
+    let mbox = Mailbox::new(Some("apathetic programmers".to_string()), mailer.address());
     let email = Message::builder()
         .to(mbox.clone())
         .from(mbox)
@@ -28,17 +134,5 @@ pub async fn sanity_check() -> Result<(), LettreError> {
         .body("SANITY CHECK".to_string())
         .unwrap();
 
-    send(email).await?;
-
-    Ok(())
-}
-
-pub async fn send(msg: Message) -> Result<LettreResponse, LettreError> {
-    let mailer: AsyncSmtpTransport<Tokio1Executor> =
-        AsyncSmtpTransport::<Tokio1Executor>::relay("smtp.gmail.com")
-            .unwrap()
-            .credentials(CREDS.clone())
-            .build();
-
-    mailer.send(msg).await
+    mailer.send(email).await
 }
@@ -1,10 +1,10 @@
 use axum::{
-    http::StatusCode,
+    http::{header::RETRY_AFTER, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
 use serde::Serialize;
-use std::borrow::Cow;
+use std::{borrow::Cow, fmt, time::Duration};
 
 #[derive(Debug, Clone)]
 pub struct ResponseStatusError(StatusCode, Cow<'static, str>);
@@ -15,6 +15,12 @@ impl ResponseStatusError {
     }
 }
 
+impl fmt::Display for ResponseStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.1)
+    }
+}
+
 impl IntoResponse for ResponseStatusError {
     fn into_response(self) -> Response {
         #[derive(Serialize)]
@@ -40,9 +46,20 @@ impl<S: Into<Cow<'static, str>>> From<(StatusCode, S)> for ResponseStatusError {
     }
 }
 
+/// What's wrong with one field of a request body.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
 pub enum AppError {
     InternalServerError(anyhow::Error),
     ResponseStatusError(ResponseStatusError),
+    /// 429 with a `Retry-After` header
+    TooManyRequests(Duration),
+    /// 422 listing every problem by field
+    InvalidFields(Vec<FieldError>),
 }
 
 pub type AppResult<T> = Result<T, AppError>;
@@ -56,6 +73,60 @@ impl IntoResponse for AppError {
             )
             .into_response(),
             AppError::ResponseStatusError(rse) => rse.into_response(),
+            AppError::TooManyRequests(retry_after) => {
+                // round up so clients never retry too early
+                let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                (
+                    [(RETRY_AFTER, secs.to_string())],
+                    AppError::from(
+                        StatusCode::TOO_MANY_REQUESTS,
+                        format!("too many requests, try again in {secs} seconds"),
+                    ),
+                )
+                    .into_response()
+            }
+            AppError::InvalidFields(errors) => {
+                #[derive(Serialize)]
+                struct InvalidFieldsResponse {
+                    status: u16,
+                    message: String,
+                    errors: Vec<FieldError>,
+                }
+
+                let status = StatusCode::UNPROCESSABLE_ENTITY;
+                (
+                    status,
+                    Json(InvalidFieldsResponse {
+                        status: status.as_u16(),
+                        message: invalid_fields_message(&errors),
+                        errors,
+                    }),
+                )
+                    .into_response()
+            }
+        }
+    }
+}
+
+fn invalid_fields_message(errors: &[FieldError]) -> String {
+    errors
+        .iter()
+        .map(|e| e.message.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::InternalServerError(err) => write!(f, "internal server error: {err}"),
+            AppError::ResponseStatusError(rse) => rse.fmt(f),
+            AppError::TooManyRequests(retry_after) => write!(
+                f,
+                "too many requests, try again in {} seconds",
+                retry_after.as_secs()
+            ),
+            AppError::InvalidFields(errors) => write!(f, "{}", invalid_fields_message(errors)),
         }
     }
 }
@@ -0,0 +1,241 @@
+use crate::config::{AuthConfig, JwtKeyConfig};
+use anyhow::{bail, Context};
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header};
+use openssl::pkey::{Id, PKey};
+use serde::Serialize;
+use std::fs;
+
+/// The kid of the key made from `auth.jwt_secret`. Tokens without a kid were signed
+/// before there was a keyring, so they are verified with it too.
+pub const DEFAULT_KID: &str = "default";
+
+/// A key tokens are signed and verified with.
+pub struct Key {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    pub(crate) encoding: EncodingKey,
+    pub(crate) decoding: DecodingKey,
+    // shared secrets are never published
+    jwk: Option<Jwk>,
+    retired_at: Option<DateTime<Utc>>,
+}
+
+impl Key {
+    fn from_config(config: &JwtKeyConfig) -> anyhow::Result<Key> {
+        let key = match config.algorithm {
+            Algorithm::HS256 => {
+                if config.secret.is_empty() {
+                    bail!("HS256 keys need a secret");
+                }
+                Key::secret(&config.kid, &config.secret)?
+            }
+            Algorithm::RS256 | Algorithm::EdDSA => {
+                let pem = if !config.private_key.is_empty() {
+                    config.private_key.clone().into_bytes()
+                } else if !config.private_key_file.is_empty() {
+                    fs::read(&config.private_key_file)
+                        .with_context(|| format!("could not read {}", config.private_key_file))?
+                } else {
+                    bail!(
+                        "{:?} keys need a private_key or private_key_file",
+                        config.algorithm
+                    );
+                };
+                Key::private(&config.kid, config.algorithm, &pem)?
+            }
+            other => bail!("unsupported algorithm {other:?}, expected HS256, RS256 or EdDSA"),
+        };
+        Ok(Key {
+            retired_at: config.retired_at,
+            ..key
+        })
+    }
+
+    /// An HS256 key from a base64 encoded secret.
+    fn secret(kid: &str, secret: &str) -> anyhow::Result<Key> {
+        Ok(Key {
+            kid: kid.to_string(),
+            algorithm: Algorithm::HS256,
+            encoding: EncodingKey::from_base64_secret(secret)
+                .context("secret is not valid base64")?,
+            decoding: DecodingKey::from_base64_secret(secret)?,
+            jwk: None,
+            retired_at: None,
+        })
+    }
+
+    /// An RS256 or EdDSA key from a PEM private key, with the public half derived from it.
+    fn private(kid: &str, algorithm: Algorithm, pem: &[u8]) -> anyhow::Result<Key> {
+        let pkey = PKey::private_key_from_pem(pem).context("not a PEM private key")?;
+        let (encoding, decoding, params) = match (algorithm, pkey.id()) {
+            (Algorithm::EdDSA, Id::ED25519) => {
+                let public = pkey.raw_public_key()?;
+                (
+                    EncodingKey::from_ed_der(&pkey.private_key_to_pkcs8()?),
+                    DecodingKey::from_ed_der(&public),
+                    JwkParams::Okp {
+                        crv: "Ed25519",
+                        x: base64url(&public),
+                    },
+                )
+            }
+            (Algorithm::RS256, Id::RSA) => {
+                let rsa = pkey.rsa()?;
+                let (n, e) = (rsa.n().to_vec(), rsa.e().to_vec());
+                (
+                    EncodingKey::from_rsa_der(&rsa.private_key_to_der()?),
+                    DecodingKey::from_rsa_raw_components(&n, &e),
+                    JwkParams::Rsa {
+                        n: base64url(&n),
+                        e: base64url(&e),
+                    },
+                )
+            }
+            (Algorithm::EdDSA, _) => bail!("EdDSA keys must be Ed25519 keys"),
+            _ => bail!("RS256 keys must be RSA keys"),
+        };
+
+        // ring only complains about some keys (e.g. RSA keys under 2048 bits) when
+        // signing, so find out now rather than on the first login
+        jsonwebtoken::encode(&Header::new(algorithm), &(), &encoding)
+            .context("the key can't sign tokens")?;
+
+        Ok(Key {
+            kid: kid.to_string(),
+            algorithm,
+            encoding,
+            decoding,
+            jwk: Some(Jwk {
+                kid: kid.to_string(),
+                alg: algorithm,
+                use_: "sig",
+                params,
+            }),
+            retired_at: None,
+        })
+    }
+
+    fn signs_at(&self, now: DateTime<Utc>) -> bool {
+        self.retired_at.is_none_or(|retired_at| now < retired_at)
+    }
+}
+
+/// Every key tokens are signed or verified with, by kid. Retired keys stop signing at
+/// their `retired_at`, and stop verifying after the grace period, so tokens signed just
+/// before a rotation stay valid until they expire.
+pub struct Keys {
+    keys: Vec<Key>,
+    signing_key: String,
+    grace_period: chrono::Duration,
+}
+
+impl Keys {
+    pub fn from_config(config: &AuthConfig) -> anyhow::Result<Keys> {
+        let mut keys = Vec::new();
+        if !config.jwt_secret.is_empty() {
+            keys.push(
+                Key::secret(DEFAULT_KID, &config.jwt_secret)
+                    .context("auth.jwt_secret (JWT_SECRET)")?,
+            );
+        }
+        for key in &config.jwt_keys {
+            keys.push(Key::from_config(key).with_context(|| format!("auth.jwt_keys {}", key.kid))?);
+        }
+
+        if keys.is_empty() {
+            bail!("auth.jwt_secret (JWT_SECRET) or auth.jwt_keys must be set");
+        }
+        for (i, key) in keys.iter().enumerate() {
+            if key.kid.is_empty() {
+                bail!("every key in auth.jwt_keys needs a kid");
+            }
+            if keys[..i].iter().any(|k| k.kid == key.kid) {
+                bail!("more than one jwt key has the kid {}", key.kid);
+            }
+        }
+        if !config.signing_key.is_empty() && !keys.iter().any(|k| k.kid == config.signing_key) {
+            bail!(
+                "auth.signing_key (JWT_SIGNING_KEY): there is no key {}",
+                config.signing_key
+            );
+        }
+
+        Ok(Keys {
+            keys,
+            signing_key: config.signing_key.clone(),
+            grace_period: chrono::Duration::seconds(config.key_grace_period as i64),
+        })
+    }
+
+    /// The key new tokens are signed with at `now`: `auth.signing_key` until it is
+    /// retired, otherwise the first key that isn't.
+    pub fn signing_key(&self, now: DateTime<Utc>) -> Option<&Key> {
+        let mut active = self.keys.iter().filter(|key| key.signs_at(now));
+        active
+            .clone()
+            .find(|key| key.kid == self.signing_key)
+            .or_else(|| active.next())
+    }
+
+    /// The key a token signed with `kid` is verified with, `None` if there is no such key
+    /// or its grace period is over.
+    pub fn verifying_key(&self, kid: Option<&str>, now: DateTime<Utc>) -> Option<&Key> {
+        let kid = kid.unwrap_or(DEFAULT_KID);
+        self.keys
+            .iter()
+            .find(|key| key.kid == kid)
+            .filter(|key| self.verifies_at(key, now))
+    }
+
+    /// The public keys that still verify tokens at `now`, so other services can check
+    /// our tokens without knowing any secret.
+    pub fn jwks(&self, now: DateTime<Utc>) -> JwkSet {
+        JwkSet {
+            keys: self
+                .keys
+                .iter()
+                .filter(|key| self.verifies_at(key, now))
+                .filter_map(|key| key.jwk.clone())
+                .collect(),
+        }
+    }
+
+    fn verifies_at(&self, key: &Key, now: DateTime<Utc>) -> bool {
+        match key.retired_at {
+            Some(retired_at) => retired_at
+                .checked_add_signed(self.grace_period)
+                .is_none_or(|expires_at| now < expires_at),
+            None => true,
+        }
+    }
+}
+
+/// A public key as a JSON Web Key (RFC 7517).
+#[derive(Debug, Clone, Serialize)]
+pub struct Jwk {
+    pub kid: String,
+    pub alg: Algorithm,
+    #[serde(rename = "use")]
+    pub use_: &'static str,
+    #[serde(flatten)]
+    pub params: JwkParams,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kty")]
+pub enum JwkParams {
+    #[serde(rename = "RSA")]
+    Rsa { n: String, e: String },
+    #[serde(rename = "OKP")]
+    Okp { crv: &'static str, x: String },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+fn base64url(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
@@ -1,16 +1,36 @@
 use std::io;
 
-use axum::{http::StatusCode, routing::get_service, Router};
+use axum::{
+    http::StatusCode,
+    routing::{get, get_service},
+    Router,
+};
+use config::AppConfig;
 use deadpool::managed::Pool;
 use diesel_async::{pooled_connection::AsyncDieselConnectionManager, AsyncPgConnection};
+use state::AppState;
+use tasks::Supervisor;
 use tower_http::services::ServeDir;
 
 pub mod api;
+pub mod assets;
+pub mod audit;
 pub mod auth;
+pub mod backup;
+pub mod clock;
+pub mod config;
 pub mod email;
 pub mod error;
+pub mod keyring;
+pub mod migrations;
 pub mod models;
+pub mod password_policy;
+pub mod rate_limit;
 pub mod schema;
+pub mod security;
+pub mod state;
+pub mod tasks;
+pub mod totp;
 
 pub type DbPool = Pool<AsyncDieselConnectionManager<AsyncPgConnection>>;
 
@@ -21,11 +41,53 @@ pub fn connect_to_db(db_url: &str) -> Pool<AsyncDieselConnectionManager<AsyncPgC
         .expect("failed to build database pool")
 }
 
-pub fn app() -> Router {
-    let serve = get_service(ServeDir::new("assets")).handle_error(handle_error);
-    Router::new()
+/// Builds the router and registers its background tasks with `tasks`. `config` is
+/// expected to have been validated by [`AppConfig::load`] or [`AppConfig::from_sources`].
+pub fn app(config: AppConfig, tasks: &mut Supervisor) -> anyhow::Result<Router> {
+    let state = AppState::from_config(config)?;
+    spawn_background_tasks(&state, tasks);
+    Ok(app_with_state(state))
+}
+
+/// Builds the router around an existing state, without starting any background tasks.
+pub fn app_with_state(state: AppState) -> Router {
+    let serve = get_service(ServeDir::new("assets"))
+        .handle_error(handle_error)
+        .layer(security::asset_csp());
+    let config = state.config.clone();
+    let app = Router::new()
         .nest("/api", api::app())
-        .nest("/assets", serve)
+        .route("/.well-known/jwks.json", get(api::auth::jwks))
+        .nest_service("/assets", serve)
+        .with_state(state);
+    security::protect(app, &config.server)
+}
+
+pub fn spawn_background_tasks(state: &AppState, tasks: &mut Supervisor) {
+    let queued = state.outbox.queued();
+    tasks.spawn("email outbox", move |shutdown| {
+        email::send_queued(queued.clone(), shutdown)
+    });
+
+    let (resets, clock) = (state.resets.clone(), state.clock.clone());
+    tasks.spawn("reset token cleanup", move |shutdown| {
+        api::password::clean_expired_resets(resets.clone(), clock.clone(), shutdown)
+    });
+
+    let (limits, clock) = (state.limits.clone(), state.clock.clone());
+    tasks.spawn("rate limit cleanup", move |shutdown| {
+        rate_limit::clean_expired_limits(limits.clone(), clock.clone(), shutdown)
+    });
+
+    let gc_state = state.clone();
+    tasks.spawn("asset gc", move |shutdown| {
+        api::edit::collect_orphaned_pfps(gc_state.clone(), shutdown)
+    });
+
+    let reminder_state = state.clone();
+    tasks.spawn("club reminders", move |shutdown| {
+        api::reregistration::remind_unconfirmed_clubs(reminder_state.clone(), shutdown)
+    });
 }
 
 async fn handle_error(_: io::Error) -> error::AppError {
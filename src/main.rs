@@ -1,35 +1,153 @@
-use axum::{http::Method, Extension};
-use cca_club_hub::{auth::ensure_jwt_secret_is_valid, connect_to_db, email};
-use envconfig::Envconfig;
-use tower_http::cors::{Any, CorsLayer};
-
-#[derive(Envconfig)]
-struct Config {
-    #[envconfig(from = "DATABASE_URL")]
-    pub db_url: String,
-    #[envconfig(from = "PORT", default = "8080")]
-    pub port: u16,
+use cca_club_hub::{
+    config::AppConfig,
+    email, migrations,
+    state::AppState,
+    tasks::{self, Supervisor},
+};
+use clap::{Parser, Subcommand};
+use std::{
+    net::SocketAddr,
+    process,
+    time::{Duration, Instant},
+};
+
+#[derive(Parser)]
+#[command(about = "The CCA Club Hub backend")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the web server (the default)
+    Serve,
+    /// Apply pending database migrations
+    Migrate {
+        /// Only report applied and pending migrations
+        #[arg(long, conflicts_with = "revert")]
+        dry_run: bool,
+        /// Revert the most recently applied migration
+        #[arg(long)]
+        revert: bool,
+    },
 }
 
 #[tokio::main]
 async fn main() {
     dotenv::dotenv().ok();
 
-    let config = Config::init_from_env().unwrap();
-    ensure_jwt_secret_is_valid();
-    if let Err(e) = email::sanity_check().await {
+    match Cli::parse().command.unwrap_or(Command::Serve) {
+        Command::Serve => serve().await,
+        Command::Migrate { dry_run, revert } => {
+            if let Err(e) = migrate(dry_run, revert) {
+                eprintln!("migration failed: {e}");
+                process::exit(1);
+            }
+        }
+    }
+}
+
+fn migrate(dry_run: bool, revert: bool) -> anyhow::Result<()> {
+    let config = AppConfig::load_unchecked()?;
+    anyhow::ensure!(
+        !config.database.url.is_empty(),
+        "database.url (DATABASE_URL) must be set"
+    );
+    let db_url = &config.database.url;
+
+    if revert {
+        println!("reverted {}", migrations::revert_last(db_url)?);
+    } else if !dry_run {
+        for version in migrations::run_pending(db_url)? {
+            println!("applied {version}");
+        }
+    }
+
+    let status = migrations::status(db_url)?;
+    println!("applied migrations:");
+    for version in &status.applied {
+        println!("  {version}");
+    }
+    println!("pending migrations:");
+    for version in &status.pending {
+        println!("  {version}");
+    }
+    if status.pending.is_empty() {
+        println!("  (none)");
+    }
+
+    Ok(())
+}
+
+async fn serve() {
+    let config = AppConfig::load().unwrap_or_else(|e| {
+        eprintln!("{e}");
+        process::exit(1);
+    });
+
+    if config.database.run_migrations {
+        let db_url = config.database.url.clone();
+        let applied = tokio::task::spawn_blocking(move || migrations::run_pending(&db_url))
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|applied| applied)
+            .unwrap_or_else(|e| {
+                eprintln!("failed to run migrations: {e}");
+                process::exit(1);
+            });
+        for version in applied {
+            eprintln!("applied migration {version}");
+        }
+    }
+    let port = config.server.port;
+    let drain_timeout = Duration::from_secs(config.server.shutdown_timeout);
+
+    let state = AppState::from_config(config).unwrap_or_else(|e| {
+        eprintln!("failed to set up the server: {e}");
+        process::exit(1);
+    });
+    if let Err(e) = email::sanity_check(state.mailer.as_ref()).await {
         eprintln!("email sanity check failed. forgot password will not work: {e}")
     };
 
-    let pool = connect_to_db(&config.db_url);
-    let cors = CorsLayer::new()
-        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::OPTIONS])
-        .allow_headers(Any)
-        .allow_origin(Any);
-    let app = cca_club_hub::app().layer(Extension(pool)).layer(cors);
-
-    axum::Server::bind(&([0, 0, 0, 0], config.port).into())
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+    let mut supervisor = Supervisor::new();
+    cca_club_hub::spawn_background_tasks(&state, &mut supervisor);
+    let app = cca_club_hub::app_with_state(state);
+
+    let mut shutdown = supervisor.shutdown_signal();
+    // the server is dropped at the end of this block together with the app state it holds,
+    // which tells the email outbox that no more emails are coming
+    let drain_deadline = {
+        let server = axum::Server::bind(&([0, 0, 0, 0], port).into())
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .with_graceful_shutdown(async move { shutdown.cancelled().await });
+        tokio::pin!(server);
+
+        tokio::select! {
+            result = &mut server => {
+                result.unwrap();
+                None
+            }
+            _ = tasks::termination_signal() => {
+                eprintln!(
+                    "shutting down, waiting up to {}s for requests to finish",
+                    drain_timeout.as_secs()
+                );
+                supervisor.cancel();
+                let drain_deadline = Instant::now() + drain_timeout;
+                match tokio::time::timeout(drain_timeout, server).await {
+                    Ok(result) => result.unwrap(),
+                    Err(_) => eprintln!("requests still running after the drain timeout, dropping them"),
+                }
+                Some(drain_deadline)
+            }
+        }
+    };
+
+    // background tasks were cancelled together with the server, so they share its deadline
+    let remaining = drain_deadline.map_or(drain_timeout, |d| {
+        d.saturating_duration_since(Instant::now())
+    });
+    supervisor.shutdown(remaining).await;
 }
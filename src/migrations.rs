@@ -0,0 +1,74 @@
+use anyhow::anyhow;
+use diesel::{pg::PgConnection, sql_query, Connection, RunQueryDsl};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+
+/// The `migrations/` directory, compiled into the binary.
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+// arbitrary key shared by every instance so only one of them migrates at a time
+const MIGRATION_LOCK_KEY: i64 = 0x0063_6361_5f68_7562;
+
+#[derive(Debug, Clone, Default)]
+pub struct MigrationStatus {
+    pub applied: Vec<String>,
+    pub pending: Vec<String>,
+}
+
+/// Lists which migrations have been applied to the database and which are pending.
+pub fn status(db_url: &str) -> anyhow::Result<MigrationStatus> {
+    let conn = &mut PgConnection::establish(db_url)?;
+    status_of(conn)
+}
+
+/// Applies every pending migration and returns the versions that were applied.
+pub fn run_pending(db_url: &str) -> anyhow::Result<Vec<String>> {
+    let conn = &mut PgConnection::establish(db_url)?;
+    with_lock(conn, |conn| {
+        Ok(conn
+            .run_pending_migrations(MIGRATIONS)
+            .map_err(|e| anyhow!(e))?
+            .into_iter()
+            .map(|v| v.to_string())
+            .collect())
+    })
+}
+
+/// Reverts the most recently applied migration and returns its version.
+pub fn revert_last(db_url: &str) -> anyhow::Result<String> {
+    let conn = &mut PgConnection::establish(db_url)?;
+    with_lock(conn, |conn| {
+        Ok(conn
+            .revert_last_migration(MIGRATIONS)
+            .map_err(|e| anyhow!(e))?
+            .to_string())
+    })
+}
+
+fn status_of(conn: &mut PgConnection) -> anyhow::Result<MigrationStatus> {
+    let applied = conn
+        .applied_migrations()
+        .map_err(|e| anyhow!(e))?
+        .into_iter()
+        .map(|v| v.to_string())
+        .collect();
+    let pending = conn
+        .pending_migrations(MIGRATIONS)
+        .map_err(|e| anyhow!(e))?
+        .into_iter()
+        .map(|m| m.name().version().to_string())
+        .collect();
+
+    Ok(MigrationStatus { applied, pending })
+}
+
+/// Runs `f` while holding a postgres advisory lock, so concurrently starting
+/// instances don't race each other.
+fn with_lock<T>(
+    conn: &mut PgConnection,
+    f: impl FnOnce(&mut PgConnection) -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    sql_query(format!("SELECT pg_advisory_lock({MIGRATION_LOCK_KEY})")).execute(conn)?;
+    let result = f(conn);
+    sql_query(format!("SELECT pg_advisory_unlock({MIGRATION_LOCK_KEY})")).execute(conn)?;
+    result
+}
@@ -1,12 +1,21 @@
 use crate::schema::*;
-use diesel::prelude::*;
+use chrono::{DateTime, Utc};
+use diesel::{
+    deserialize::{self, FromSql},
+    pg::{Pg, PgValue},
+    prelude::*,
+    serialize::{self, Output, ToSql},
+    sql_types::Text,
+    AsExpression, FromSqlRow,
+};
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
 
-#[derive(Debug, Clone, Queryable, Insertable, Identifiable)]
+#[derive(Debug, Clone, Queryable, Insertable, Identifiable, Serialize, Deserialize)]
 pub struct Club {
     pub id: i32,
     pub username: String,
     pub email: String,
-    pub password_hash: String,
     pub club_name: String,
     pub description: String,
     pub about: String,
@@ -14,9 +23,82 @@ pub struct Club {
     pub profile_picture_url: String,
     pub banner_url: String,
     pub featured: bool,
+    /// edits are published without review when edits are moderated
+    pub trusted: bool,
+    /// only active clubs are listed publicly
+    pub status: ClubStatus,
+    /// when the club was soft deleted, which hides it like archiving does
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// the unix epoch for clubs registered before this was recorded
+    pub created_at: DateTime<Utc>,
+    /// when the profile was last published
+    pub updated_at: DateTime<Utc>,
+    /// last time the profile was confirmed to still be accurate, see
+    /// [`crate::api::reregistration`]
+    pub confirmed_at: Option<DateTime<Utc>>,
+    /// made inactive by the confirmation deadline rather than by an admin
+    #[serde(default)]
+    pub missed_deadline: bool,
 }
 
-#[derive(Debug, Clone, Queryable, Identifiable, Associations)]
+/// Where a [`Club`] is in its life.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, Serialize, Deserialize)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "lowercase")]
+pub enum ClubStatus {
+    Active,
+    /// stopped meeting, but its officers can still log in and bring it back
+    Inactive,
+    /// closed for good; its officers can't log in
+    Archived,
+}
+
+impl ClubStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ClubStatus::Active => "active",
+            ClubStatus::Inactive => "inactive",
+            ClubStatus::Archived => "archived",
+        }
+    }
+}
+
+impl fmt::Display for ClubStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ClubStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ClubStatus, String> {
+        match s {
+            "active" => Ok(ClubStatus::Active),
+            "inactive" => Ok(ClubStatus::Inactive),
+            "archived" => Ok(ClubStatus::Archived),
+            other => Err(format!(
+                "unknown status {other}, expected active, inactive or archived"
+            )),
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for ClubStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        <str as ToSql<Text, Pg>>::to_sql(self.as_str(), out)
+    }
+}
+
+impl FromSql<Text, Pg> for ClubStatus {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<ClubStatus> {
+        Ok(String::from_sql(bytes)?.parse()?)
+    }
+}
+
+#[derive(
+    Debug, Clone, Queryable, Insertable, Identifiable, Associations, Serialize, Deserialize,
+)]
 #[diesel(belongs_to(Club))]
 pub struct ClubSocial {
     pub id: i32,
@@ -27,14 +109,16 @@ pub struct ClubSocial {
     pub instagram: Option<String>,
 }
 
-#[derive(Debug, Clone, Queryable, Identifiable)]
+#[derive(Debug, Clone, Queryable, Insertable, Identifiable, Serialize, Deserialize)]
 #[diesel(table_name = categories)]
 pub struct Category {
     pub id: i32,
     pub category_name: String,
 }
 
-#[derive(Debug, Clone, Queryable, Identifiable, Associations)]
+#[derive(
+    Debug, Clone, Queryable, Insertable, Identifiable, Associations, Serialize, Deserialize,
+)]
 #[diesel(belongs_to(Club))]
 #[diesel(belongs_to(Category))]
 #[diesel(table_name = club_categories)]
@@ -43,3 +127,351 @@ pub struct ClubCategory {
     pub club_id: i32,
     pub category_id: i32,
 }
+
+/// Someone who logs in. What they can do to a club depends on their [`Role`] in it.
+#[derive(Debug, Clone, Queryable, Insertable, Identifiable, Serialize, Deserialize)]
+pub struct User {
+    pub id: i32,
+    pub username: String,
+    pub email: String,
+    pub password_hash: String,
+    /// tokens issued before this don't work anymore, see [`crate::auth::revoke_sessions`]
+    pub sessions_revoked_at: Option<DateTime<Utc>>,
+}
+
+/// What an officer may do to their club, each role allowing everything the ones before
+/// it do.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    AsExpression,
+    FromSqlRow,
+    Serialize,
+    Deserialize,
+)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// edits the club's page
+    Editor,
+    /// runs the club with the president
+    Officer,
+    /// owns the club, and decides who else manages it
+    President,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Editor => "editor",
+            Role::Officer => "officer",
+            Role::President => "president",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Role, String> {
+        match s {
+            "editor" => Ok(Role::Editor),
+            "officer" => Ok(Role::Officer),
+            "president" => Ok(Role::President),
+            other => Err(format!(
+                "unknown role {other}, expected president, officer or editor"
+            )),
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for Role {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        <str as ToSql<Text, Pg>>::to_sql(self.as_str(), out)
+    }
+}
+
+impl FromSql<Text, Pg> for Role {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Role> {
+        Ok(String::from_sql(bytes)?.parse()?)
+    }
+}
+
+#[derive(
+    Debug, Clone, Queryable, Insertable, Identifiable, Associations, Serialize, Deserialize,
+)]
+#[diesel(belongs_to(Club))]
+#[diesel(belongs_to(User))]
+#[diesel(table_name = club_officers, primary_key(club_id, user_id))]
+pub struct ClubOfficer {
+    pub club_id: i32,
+    pub user_id: i32,
+    pub role: Role,
+}
+
+/// Where a [`ClubApplication`] is in review.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, Serialize, Deserialize)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "lowercase")]
+pub enum ApplicationStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+impl ApplicationStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ApplicationStatus::Pending => "pending",
+            ApplicationStatus::Approved => "approved",
+            ApplicationStatus::Rejected => "rejected",
+        }
+    }
+}
+
+impl fmt::Display for ApplicationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ApplicationStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ApplicationStatus, String> {
+        match s {
+            "pending" => Ok(ApplicationStatus::Pending),
+            "approved" => Ok(ApplicationStatus::Approved),
+            "rejected" => Ok(ApplicationStatus::Rejected),
+            other => Err(format!(
+                "unknown status {other}, expected pending, approved or rejected"
+            )),
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for ApplicationStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        <str as ToSql<Text, Pg>>::to_sql(self.as_str(), out)
+    }
+}
+
+impl FromSql<Text, Pg> for ApplicationStatus {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<ApplicationStatus> {
+        Ok(String::from_sql(bytes)?.parse()?)
+    }
+}
+
+/// A club asking to be listed, see [`crate::api::applications`].
+#[derive(Debug, Clone, Queryable, Insertable, Identifiable, Serialize, Deserialize)]
+pub struct ClubApplication {
+    pub id: i32,
+    pub club_name: String,
+    pub description: String,
+    pub meet_time: String,
+    pub advisor: String,
+    pub email: String,
+    pub status: ApplicationStatus,
+    pub submitted_at: DateTime<Utc>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    pub rejection_reason: Option<String>,
+    pub club_id: Option<i32>,
+}
+
+/// Where a [`ClubDraft`] is in review.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, Serialize, Deserialize)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "lowercase")]
+pub enum DraftStatus {
+    Pending,
+    Approved,
+    Rejected,
+    /// replaced by a newer draft before it was reviewed
+    Superseded,
+}
+
+impl DraftStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DraftStatus::Pending => "pending",
+            DraftStatus::Approved => "approved",
+            DraftStatus::Rejected => "rejected",
+            DraftStatus::Superseded => "superseded",
+        }
+    }
+}
+
+impl fmt::Display for DraftStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for DraftStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DraftStatus, String> {
+        match s {
+            "pending" => Ok(DraftStatus::Pending),
+            "approved" => Ok(DraftStatus::Approved),
+            "rejected" => Ok(DraftStatus::Rejected),
+            "superseded" => Ok(DraftStatus::Superseded),
+            other => Err(format!(
+                "unknown status {other}, expected pending, approved, rejected or superseded"
+            )),
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for DraftStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        <str as ToSql<Text, Pg>>::to_sql(self.as_str(), out)
+    }
+}
+
+impl FromSql<Text, Pg> for DraftStatus {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<DraftStatus> {
+        Ok(String::from_sql(bytes)?.parse()?)
+    }
+}
+
+/// A profile edit waiting for an admin to publish it, see [`crate::api::drafts`].
+#[derive(
+    Debug, Clone, Queryable, Insertable, Identifiable, Associations, Serialize, Deserialize,
+)]
+#[diesel(belongs_to(Club))]
+#[diesel(table_name = club_drafts)]
+pub struct ClubDraft {
+    pub id: i32,
+    pub club_id: i32,
+    pub submitted_by: Option<i32>,
+    pub submitted_at: DateTime<Utc>,
+    pub club_name: String,
+    pub description: String,
+    pub about: String,
+    pub meet_time: String,
+    pub category_ids: Vec<i32>,
+    pub website: Option<String>,
+    pub google_classroom: Option<String>,
+    pub discord: Option<String>,
+    pub instagram: Option<String>,
+    pub status: DraftStatus,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    pub review_comment: Option<String>,
+}
+
+/// A published version of a club's profile, see [`crate::api::revisions`].
+#[derive(
+    Debug, Clone, Queryable, Insertable, Identifiable, Associations, Serialize, Deserialize,
+)]
+#[diesel(belongs_to(Club))]
+#[diesel(table_name = club_revisions)]
+pub struct ClubRevision {
+    pub id: i32,
+    pub club_id: i32,
+    pub edited_by: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub club_name: String,
+    pub description: String,
+    pub about: String,
+    pub meet_time: String,
+    pub category_ids: Vec<i32>,
+    pub website: Option<String>,
+    pub google_classroom: Option<String>,
+    pub discord: Option<String>,
+    pub instagram: Option<String>,
+    pub reverted_from: Option<i32>,
+}
+
+/// An email asking a club to confirm its profile, see [`crate::api::reregistration`].
+#[derive(
+    Debug, Clone, Queryable, Insertable, Identifiable, Associations, Serialize, Deserialize,
+)]
+#[diesel(belongs_to(Club))]
+#[diesel(table_name = club_reminders)]
+pub struct ClubReminder {
+    pub id: i32,
+    pub club_id: i32,
+    /// start of the school year the reminder is for
+    pub school_year: DateTime<Utc>,
+    pub token_hash: String,
+    pub sent_at: DateTime<Utc>,
+}
+
+/// Something someone did, see [`crate::audit`].
+#[derive(Debug, Clone, Queryable, Insertable, Identifiable, Serialize, Deserialize)]
+#[diesel(table_name = audit_log)]
+pub struct AuditEntry {
+    pub id: i32,
+    pub created_at: DateTime<Utc>,
+    pub actor_id: Option<i32>,
+    pub actor: String,
+    pub action: String,
+    pub club_id: Option<i32>,
+    pub target: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+}
+
+/// An emailed invitation to become an officer of a club, see [`crate::api::officers`].
+#[derive(Debug, Clone, Queryable, Identifiable, Associations)]
+#[diesel(belongs_to(Club))]
+#[diesel(table_name = club_invitations)]
+pub struct ClubInvitation {
+    pub id: i32,
+    pub club_id: i32,
+    pub email: String,
+    pub role: Role,
+    pub token_hash: String,
+    pub invited_by: Option<i32>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A handover of a club's presidency and contact email, see [`crate::api::transfer`].
+/// Pending until `completed_at` is set.
+#[derive(
+    Debug, Clone, Queryable, Insertable, Identifiable, Associations, Serialize, Deserialize,
+)]
+#[diesel(belongs_to(Club))]
+#[diesel(table_name = leadership_transfers)]
+pub struct LeadershipTransfer {
+    pub id: i32,
+    pub club_id: i32,
+    pub new_email: String,
+    pub token_hash: Option<String>,
+    pub requested_by: Option<i32>,
+    pub requested_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub old_email: Option<String>,
+    pub from_user: Option<i32>,
+    pub to_user: Option<i32>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// A user's TOTP secret and recovery codes, see [`crate::api::two_factor`].
+#[derive(
+    Debug, Clone, Queryable, Insertable, Identifiable, Associations, Serialize, Deserialize,
+)]
+#[diesel(belongs_to(User))]
+#[diesel(table_name = user_totp, primary_key(user_id))]
+pub struct UserTotp {
+    pub user_id: i32,
+    pub secret: String,
+    pub enabled: bool,
+    pub last_used_step: i64,
+    pub recovery_codes: Vec<String>,
+}
@@ -0,0 +1,575 @@
+use crate::{
+    config::PasswordPolicyConfig,
+    error::{AppError, AppResult, FieldError},
+};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+
+/// Words about the site itself, rejected like a user's own username and club names.
+const SITE_WORDS: &[&str] = &["cca", "clubhub", "ccaclubhub"];
+
+// longer passwords are only estimated up to here, which keeps the estimate cheap and
+// can only make it more conservative
+const MAX_ESTIMATED_LENGTH: usize = 100;
+
+// guesses for guessing a character with no pattern, and the least a part of a longer
+// password counts for (as in zxcvbn)
+const BRUTEFORCE_CARDINALITY: f64 = 10.0;
+const MIN_SUBMATCH_GUESSES: f64 = 50.0;
+// years within this many of the current one are guessed first
+const MIN_YEAR_SPACE: i32 = 20;
+// qwerty rows, each shifted half a key right of the one above
+const KEYBOARD_ROWS: &[&str] = &["1234567890-=", "qwertyuiop[]", "asdfghjkl;'", "zxcvbnm,./"];
+const KEYBOARD_SHIFTED: &[(char, char)] = &[
+    ('!', '1'),
+    ('@', '2'),
+    ('#', '3'),
+    ('$', '4'),
+    ('%', '5'),
+    ('^', '6'),
+    ('&', '7'),
+    ('*', '8'),
+    ('(', '9'),
+    (')', '0'),
+    ('_', '-'),
+    ('+', '='),
+    ('{', '['),
+    ('}', ']'),
+    (':', ';'),
+    ('"', '\''),
+    ('<', ','),
+    ('>', '.'),
+    ('?', '/'),
+];
+// the number of keys next to the average key
+const KEYBOARD_AVERAGE_DEGREE: f64 = 4.6;
+
+lazy_static! {
+    /// Passwords from breach dumps, by rank (1 is the most common).
+    static ref COMMON_PASSWORDS: HashMap<&'static str, usize> =
+        include_str!("../data/common_passwords.txt")
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .enumerate()
+            .map(|(i, password)| (password, i + 1))
+            .collect();
+}
+
+/// How hard a password is to guess, estimated like zxcvbn does: by finding the cheapest
+/// way to build it out of common passwords, sequences, repeats, keyboard patterns,
+/// years and random characters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Strength {
+    /// log10 of the guesses an attacker would need
+    pub guesses_log10: f64,
+    /// 0 (too guessable) to 4 (very unguessable)
+    pub score: u8,
+    /// what made the password guessable, if anything did
+    pub warning: Option<&'static str>,
+}
+
+/// Every way `password` falls short of the policy, empty if it's fine. `rejected` are
+/// words the password must not contain, like the username and club names, and `year` is
+/// the current year.
+pub fn problems(
+    config: &PasswordPolicyConfig,
+    password: &str,
+    rejected: &[&str],
+    year: i32,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+    if password.chars().count() < config.min_length {
+        problems.push(format!(
+            "password must be at least {} characters long",
+            config.min_length
+        ));
+    }
+
+    let rejected = rejected
+        .iter()
+        .chain(SITE_WORDS)
+        .map(|word| normalize(word))
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>();
+    let normalized = normalize(password);
+    // short words only count on their own, or they'd reject too much
+    let contains_rejected = rejected
+        .iter()
+        .any(|word| normalized == *word || (word.len() >= 4 && normalized.contains(word)));
+    let lower = password.to_lowercase();
+    let common = COMMON_PASSWORDS.contains_key(lower.as_str())
+        || COMMON_PASSWORDS.contains_key(unleet(&lower).as_str());
+
+    if contains_rejected {
+        problems.push("password must not contain your username or club name".to_string());
+    }
+    if common {
+        problems.push("password is a common password that appears in data breaches".to_string());
+    }
+    if !contains_rejected && !common {
+        let user_inputs = rejected.iter().map(String::as_str).collect::<Vec<_>>();
+        let strength = strength(password, &user_inputs, year);
+        if strength.score < config.min_strength {
+            problems.push(match strength.warning {
+                Some(warning) => format!("password is too easy to guess: {warning}"),
+                None => "password is too easy to guess, add another word or two (uncommon \
+                         words are better)"
+                    .to_string(),
+            });
+        }
+    }
+
+    problems
+}
+
+/// Fails with a field error on `field` if `password` isn't allowed, `user_inputs` being
+/// the words it must not contain.
+pub fn enforce(
+    config: &PasswordPolicyConfig,
+    field: &'static str,
+    password: &str,
+    user_inputs: &[&str],
+    year: i32,
+) -> AppResult<()> {
+    let problems = problems(config, password, user_inputs, year);
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::InvalidFields(
+            problems
+                .into_iter()
+                .map(|message| FieldError { field, message })
+                .collect(),
+        ))
+    }
+}
+
+/// Lowercases, undoes l33t substitutions and drops everything but letters and digits,
+/// so `Chess Club` and `ch3ss-club` compare equal.
+fn normalize(s: &str) -> String {
+    unleet(&s.to_lowercase())
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .collect()
+}
+
+fn unleet(s: &str) -> String {
+    s.chars().map(unleet_char).collect()
+}
+
+fn unleet_char(c: char) -> char {
+    match c {
+        '4' | '@' => 'a',
+        '3' => 'e',
+        '1' | '!' => 'i',
+        '|' => 'l',
+        '0' => 'o',
+        '5' | '$' => 's',
+        '7' => 't',
+        c => c,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Pattern {
+    Dictionary { rank: usize },
+    UserInput,
+    Sequence,
+    Repeat,
+    Spatial,
+    Year,
+    Bruteforce,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Match {
+    start: usize,
+    end: usize,
+    pattern: Pattern,
+    guesses_log10: f64,
+}
+
+/// Estimates how hard `password` is to guess. `user_inputs` are words an attacker
+/// would try first, like the username, and years close to `year` are tried before others.
+pub fn strength(password: &str, user_inputs: &[&str], year: i32) -> Strength {
+    let chars = password
+        .chars()
+        .take(MAX_ESTIMATED_LENGTH)
+        .collect::<Vec<_>>();
+    let (guesses_log10, sequence) = most_guessable(&chars, user_inputs, year);
+
+    let score = [1e3, 1e6, 1e8, 1e10]
+        .iter()
+        .take_while(|threshold| guesses_log10 >= (*threshold + 5.0_f64).log10())
+        .count() as u8;
+    let warning = if score <= 2 {
+        warning(&sequence, chars.len())
+    } else {
+        None
+    };
+
+    Strength {
+        guesses_log10,
+        score,
+        warning,
+    }
+}
+
+fn warning(sequence: &[Match], length: usize) -> Option<&'static str> {
+    let longest = sequence
+        .iter()
+        .filter(|m| m.pattern != Pattern::Bruteforce)
+        .max_by_key(|m| m.end - m.start)?;
+    Some(match longest.pattern {
+        Pattern::Dictionary { rank }
+            if sequence.len() == 1 && longest.end - longest.start == length =>
+        {
+            if rank <= 100 {
+                "this is one of the most common passwords"
+            } else {
+                "this is a very common password"
+            }
+        }
+        Pattern::Dictionary { .. } => {
+            "common passwords are easy to guess, even with numbers or symbols added"
+        }
+        Pattern::UserInput => "passwords based on your username or club name are easy to guess",
+        Pattern::Sequence => "sequences like abc or 6543 are easy to guess",
+        Pattern::Repeat => "repeats like aaa or abcabc are easy to guess",
+        Pattern::Spatial => "rows and short patterns of keys are easy to guess",
+        Pattern::Year => "recent years are easy to guess",
+        Pattern::Bruteforce => return None,
+    })
+}
+
+/// The cheapest way to build `chars` out of matches, as log10 guesses and the matches
+/// used.
+fn most_guessable(chars: &[char], user_inputs: &[&str], year: i32) -> (f64, Vec<Match>) {
+    let n = chars.len();
+    if n == 0 {
+        return (0.0, Vec::new());
+    }
+
+    let mut matches = find_matches(chars, user_inputs, year);
+    // parts of a longer password count for at least a few guesses
+    for m in &mut matches {
+        if m.end - m.start < n {
+            m.guesses_log10 = m.guesses_log10.max(MIN_SUBMATCH_GUESSES.log10());
+        }
+    }
+    let mut ending_at = vec![Vec::new(); n + 1];
+    for m in matches {
+        ending_at[m.end].push(m);
+    }
+
+    // best[j][l]: the least log10 guesses covering the first j characters with l
+    // matches, and the last of those matches
+    let mut best = vec![vec![None::<(f64, Match)>; n + 1]; n + 1];
+    for end in 1..=n {
+        let bruteforce = (0..end).map(|start| Match {
+            start,
+            end,
+            pattern: Pattern::Bruteforce,
+            guesses_log10: bruteforce_guesses_log10(end - start, end - start < n),
+        });
+        for m in ending_at[end].iter().copied().chain(bruteforce) {
+            if m.start == 0 {
+                update(&mut best[end][1], m.guesses_log10, m);
+                continue;
+            }
+            for count in 1..=m.start {
+                // two bruteforce matches in a row are always worse than one
+                if let Some((guesses, previous)) = best[m.start][count] {
+                    if previous.pattern == Pattern::Bruteforce && m.pattern == Pattern::Bruteforce {
+                        continue;
+                    }
+                    update(&mut best[end][count + 1], guesses + m.guesses_log10, m);
+                }
+            }
+        }
+    }
+
+    // like zxcvbn, an attacker also has to guess how many parts there are and in what
+    // order: l! * product + 10000^(l - 1)
+    let (count, guesses_log10) = (1..=n)
+        .filter_map(|count| best[n][count].map(|(guesses, _)| (count, guesses)))
+        .map(|(count, guesses)| {
+            let factorial_log10 = (1..=count).map(|i| (i as f64).log10()).sum::<f64>();
+            let ordered = guesses + factorial_log10;
+            if count == 1 {
+                (count, ordered)
+            } else {
+                (count, add_log10(ordered, 4.0 * (count - 1) as f64))
+            }
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .expect("a single bruteforce match always covers the password");
+
+    let mut sequence = Vec::with_capacity(count);
+    let (mut end, mut count) = (n, count);
+    while count > 0 {
+        let (_, m) = best[end][count].expect("every step of the best sequence was recorded");
+        sequence.push(m);
+        end = m.start;
+        count -= 1;
+    }
+    sequence.reverse();
+    (guesses_log10, sequence)
+}
+
+fn update(slot: &mut Option<(f64, Match)>, guesses_log10: f64, m: Match) {
+    if slot.is_none_or(|(best, _)| guesses_log10 < best) {
+        *slot = Some((guesses_log10, m));
+    }
+}
+
+/// log10(10^a + 10^b)
+fn add_log10(a: f64, b: f64) -> f64 {
+    let (high, low) = if a > b { (a, b) } else { (b, a) };
+    high + (1.0 + 10f64.powf(low - high)).log10()
+}
+
+fn bruteforce_guesses_log10(length: usize, submatch: bool) -> f64 {
+    let guesses = length as f64 * BRUTEFORCE_CARDINALITY.log10();
+    let min = if submatch {
+        (MIN_SUBMATCH_GUESSES + 1.0).log10()
+    } else {
+        1.0
+    };
+    guesses.max(min)
+}
+
+fn find_matches(chars: &[char], user_inputs: &[&str], year: i32) -> Vec<Match> {
+    let mut matches = Vec::new();
+    dictionary_matches(chars, user_inputs, &mut matches);
+    sequence_matches(chars, &mut matches);
+    repeat_matches(chars, user_inputs, year, &mut matches);
+    spatial_matches(chars, &mut matches);
+    year_matches(chars, year, &mut matches);
+    matches
+}
+
+fn dictionary_matches(chars: &[char], user_inputs: &[&str], matches: &mut Vec<Match>) {
+    let user_inputs = user_inputs
+        .iter()
+        .enumerate()
+        .map(|(i, word)| (word.to_lowercase(), i + 1))
+        .collect::<HashMap<_, _>>();
+    let lower = chars
+        .iter()
+        .map(|c| c.to_lowercase().next().unwrap_or(*c))
+        .collect::<Vec<_>>();
+
+    for start in 0..chars.len() {
+        for end in start + 3..=chars.len() {
+            let word = &lower[start..end];
+            let as_typed = word.iter().collect::<String>();
+            let reversed = word.iter().rev().collect::<String>();
+            let unleeted = word.iter().copied().map(unleet_char).collect::<String>();
+            let substitutions = word.iter().filter(|c| unleet_char(**c) != **c).count();
+            let case = uppercase_variations_log10(&chars[start..end]);
+
+            let candidates = [
+                (&as_typed, 0.0),
+                (&reversed, 2f64.log10()),
+                // each substituted character could have been either
+                (&unleeted, substitutions as f64 * 2f64.log10()),
+            ];
+            for (candidate, extra) in candidates {
+                if let Some(rank) = user_inputs.get(candidate.as_str()) {
+                    matches.push(Match {
+                        start,
+                        end,
+                        pattern: Pattern::UserInput,
+                        guesses_log10: (*rank as f64).log10() + extra + case,
+                    });
+                }
+                if let Some(rank) = COMMON_PASSWORDS.get(candidate.as_str()) {
+                    matches.push(Match {
+                        start,
+                        end,
+                        pattern: Pattern::Dictionary { rank: *rank },
+                        guesses_log10: (*rank as f64).log10() + extra + case,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Guesses for the capitalization of a word: none for all lowercase, one extra bit for
+/// the usual patterns, and every combination of the capitals otherwise.
+fn uppercase_variations_log10(word: &[char]) -> f64 {
+    let upper = word.iter().filter(|c| c.is_uppercase()).count();
+    let lower = word.iter().filter(|c| c.is_lowercase()).count();
+    if upper == 0 {
+        return 0.0;
+    }
+    let first_only = upper == 1 && word[0].is_uppercase();
+    let last_only = upper == 1 && word[word.len() - 1].is_uppercase();
+    if lower == 0 || first_only || last_only {
+        return 2f64.log10();
+    }
+    // sum of C(upper + lower, i) for i up to the smaller count
+    let total = upper + lower;
+    let variations = (1..=upper.min(lower))
+        .map(|i| binomial(total, i))
+        .sum::<f64>();
+    variations.log10()
+}
+
+fn binomial(n: usize, k: usize) -> f64 {
+    (1..=k).fold(1.0, |acc, i| acc * (n + 1 - i) as f64 / i as f64)
+}
+
+fn sequence_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let mut start = 0;
+    while start + 2 < chars.len() {
+        let delta = chars[start + 1] as i64 - chars[start] as i64;
+        if delta.abs() != 1 {
+            start += 1;
+            continue;
+        }
+        let mut end = start + 2;
+        while end < chars.len() && chars[end] as i64 - chars[end - 1] as i64 == delta {
+            end += 1;
+        }
+        if end - start >= 3 {
+            let first = chars[start];
+            // starting at either end of the alphabet or digits is the first thing to try
+            let base: f64 = if matches!(first, 'a' | 'A' | 'z' | 'Z' | '0' | '1' | '9') {
+                4.0
+            } else if first.is_ascii_digit() {
+                10.0
+            } else {
+                26.0
+            };
+            let direction: f64 = if delta < 0 { 2.0 } else { 1.0 };
+            matches.push(Match {
+                start,
+                end,
+                pattern: Pattern::Sequence,
+                guesses_log10: (base * (end - start) as f64 * direction).log10(),
+            });
+        }
+        start = end - 1;
+    }
+}
+
+fn repeat_matches(chars: &[char], user_inputs: &[&str], year: i32, matches: &mut Vec<Match>) {
+    let mut start = 0;
+    while start < chars.len() {
+        // the repeat covering the most characters from here, with the shortest chunk
+        let longest = (1..=(chars.len() - start) / 2)
+            .map(|chunk| {
+                let base = &chars[start..start + chunk];
+                let repeats = chars[start..]
+                    .chunks(chunk)
+                    .take_while(|next| *next == base)
+                    .count();
+                (chunk, repeats)
+            })
+            .filter(|(chunk, repeats)| *repeats >= 3 || (*chunk > 1 && *repeats >= 2))
+            .max_by_key(|(chunk, repeats)| (chunk * repeats, std::cmp::Reverse(*chunk)));
+
+        let Some((chunk, repeats)) = longest else {
+            start += 1;
+            continue;
+        };
+        let (base_guesses, _) = most_guessable(&chars[start..start + chunk], user_inputs, year);
+        matches.push(Match {
+            start,
+            end: start + chunk * repeats,
+            pattern: Pattern::Repeat,
+            guesses_log10: base_guesses + (repeats as f64).log10(),
+        });
+        start += chunk * repeats;
+    }
+}
+
+/// Where a key is on the keyboard, and whether shift is needed to type it.
+fn key_position(c: char) -> Option<((i32, i32), bool)> {
+    let lower = c.to_lowercase().next().unwrap_or(c);
+    let (key, shifted) = match KEYBOARD_SHIFTED.iter().find(|(s, _)| *s == c) {
+        Some((_, key)) => (*key, true),
+        None => (lower, c.is_uppercase()),
+    };
+    KEYBOARD_ROWS.iter().enumerate().find_map(|(row, keys)| {
+        keys.chars()
+            .position(|k| k == key)
+            .map(|col| ((row as i32, col as i32), shifted))
+    })
+}
+
+/// The direction from one key to a neighbouring one, `None` if they aren't neighbours.
+fn key_step(from: (i32, i32), to: (i32, i32)) -> Option<(i32, i32)> {
+    let step = (to.0 - from.0, to.1 - from.1);
+    // rows are shifted right, so the keys above are at the same column and one right
+    match step {
+        (0, -1) | (0, 1) | (-1, 0) | (-1, 1) | (1, -1) | (1, 0) => Some(step),
+        _ => None,
+    }
+}
+
+fn spatial_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let keys = chars.iter().map(|c| key_position(*c)).collect::<Vec<_>>();
+    let mut start = 0;
+    while start < chars.len() {
+        let mut end = start + 1;
+        let mut turns = 0;
+        let mut direction = None;
+        let mut shifted = keys[start].is_some_and(|(_, shifted)| shifted);
+        while end < chars.len() {
+            let (Some((from, _)), Some((to, to_shifted))) = (keys[end - 1], keys[end]) else {
+                break;
+            };
+            let Some(step) = key_step(from, to) else {
+                break;
+            };
+            if direction != Some(step) {
+                turns += 1;
+                direction = Some(step);
+            }
+            shifted |= to_shifted;
+            end += 1;
+        }
+        if end - start >= 3 {
+            let starting_keys = KEYBOARD_ROWS.iter().map(|row| row.len()).sum::<usize>() as f64;
+            let guesses_log10 = starting_keys.log10()
+                + turns as f64 * KEYBOARD_AVERAGE_DEGREE.log10()
+                + ((end - start) as f64).log10()
+                + if shifted { 2f64.log10() } else { 0.0 };
+            matches.push(Match {
+                start,
+                end,
+                pattern: Pattern::Spatial,
+                guesses_log10,
+            });
+        }
+        start = end;
+    }
+}
+
+fn year_matches(chars: &[char], current_year: i32, matches: &mut Vec<Match>) {
+    for start in 0..chars.len().saturating_sub(3) {
+        let digits = &chars[start..start + 4];
+        if !digits.iter().all(char::is_ascii_digit) {
+            continue;
+        }
+        let year = digits
+            .iter()
+            .collect::<String>()
+            .parse::<i32>()
+            .unwrap_or(0);
+        if (1900..=2039).contains(&year) {
+            let space = (year - current_year).abs().max(MIN_YEAR_SPACE);
+            matches.push(Match {
+                start,
+                end: start + 4,
+                pattern: Pattern::Year,
+                guesses_log10: f64::from(space).log10(),
+            });
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use crate::{
+    clock::Clock,
+    config::Limit,
+    error::{AppError, AppResult},
+    schema::*,
+    state::AppState,
+    tasks::{self, Shutdown},
+    DbPool,
+};
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::request::Parts,
+};
+use chrono::{DateTime, Utc};
+use diesel::{
+    delete, sql_query,
+    sql_types::{Integer, Text, Timestamptz},
+    ExpressionMethods, OptionalExtension, QueryDsl, QueryableByName,
+};
+use diesel_async::RunQueryDsl;
+use std::{collections::HashMap, convert::Infallible, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::sync::Mutex;
+
+/// The hits counted against a key since its window started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, QueryableByName)]
+pub struct Window {
+    #[diesel(sql_type = Integer)]
+    pub hits: i32,
+    #[diesel(sql_type = Timestamptz)]
+    pub resets_at: DateTime<Utc>,
+}
+
+impl Window {
+    fn retry_after(&self, now: DateTime<Utc>) -> Duration {
+        (self.resets_at - now).to_std().unwrap_or_default()
+    }
+}
+
+/// Where rate limit counters are kept. [`PgRateLimits`] is shared between instances,
+/// [`RateLimits`] keeps them in memory.
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Counts a hit against `key`, starting a new window of length `window` if the
+    /// last one is over.
+    async fn hit(&self, key: &str, window: Duration, now: DateTime<Utc>) -> anyhow::Result<Window>;
+
+    /// The current window of `key`, without counting a hit.
+    async fn get(&self, key: &str, now: DateTime<Utc>) -> anyhow::Result<Option<Window>>;
+
+    async fn clear(&self, key: &str) -> anyhow::Result<()>;
+
+    /// Drops every window that is over.
+    async fn clean(&self, now: DateTime<Utc>) -> anyhow::Result<()>;
+}
+
+/// Rate limit windows kept in memory, keyed by what is being limited.
+#[derive(Default)]
+pub struct RateLimits(pub HashMap<String, Window>);
+
+impl RateLimits {
+    /// Counts a hit against `key`, starting a new window of length `window` if the
+    /// last one is over.
+    pub fn hit(&mut self, key: &str, window: Duration, now: DateTime<Utc>) -> Window {
+        let resets_at = chrono::Duration::from_std(window)
+            .ok()
+            .and_then(|window| now.checked_add_signed(window))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        let entry = self
+            .0
+            .entry(key.to_string())
+            .or_insert(Window { hits: 0, resets_at });
+        if entry.resets_at <= now {
+            *entry = Window { hits: 0, resets_at };
+        }
+        entry.hits += 1;
+        *entry
+    }
+
+    pub fn get(&self, key: &str, now: DateTime<Utc>) -> Option<Window> {
+        self.0.get(key).filter(|w| w.resets_at > now).copied()
+    }
+
+    pub fn clear(&mut self, key: &str) {
+        self.0.remove(key);
+    }
+
+    pub fn clean(&mut self, now: DateTime<Utc>) {
+        self.0.retain(|_, w| w.resets_at > now);
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for Mutex<RateLimits> {
+    async fn hit(&self, key: &str, window: Duration, now: DateTime<Utc>) -> anyhow::Result<Window> {
+        Ok(self.lock().await.hit(key, window, now))
+    }
+
+    async fn get(&self, key: &str, now: DateTime<Utc>) -> anyhow::Result<Option<Window>> {
+        Ok(self.lock().await.get(key, now))
+    }
+
+    async fn clear(&self, key: &str) -> anyhow::Result<()> {
+        self.lock().await.clear(key);
+        Ok(())
+    }
+
+    async fn clean(&self, now: DateTime<Utc>) -> anyhow::Result<()> {
+        self.lock().await.clean(now);
+        Ok(())
+    }
+}
+
+/// Keeps rate limit windows in the `rate_limits` table, so every instance sees the same
+/// counts.
+pub struct PgRateLimits(pub DbPool);
+
+#[async_trait]
+impl RateLimitStore for PgRateLimits {
+    async fn hit(&self, key: &str, window: Duration, now: DateTime<Utc>) -> anyhow::Result<Window> {
+        let conn = &mut self.0.get().await?;
+        // the upsert keeps concurrent hits from different instances from losing counts
+        Ok(sql_query(
+            "INSERT INTO rate_limits (key, hits, resets_at) VALUES ($1, 1, $3) \
+             ON CONFLICT (key) DO UPDATE SET \
+             hits = CASE WHEN rate_limits.resets_at <= $2 THEN 1 ELSE rate_limits.hits + 1 END, \
+             resets_at = CASE WHEN rate_limits.resets_at <= $2 \
+             THEN EXCLUDED.resets_at ELSE rate_limits.resets_at END \
+             RETURNING hits, resets_at",
+        )
+        .bind::<Text, _>(key)
+        .bind::<Timestamptz, _>(now)
+        .bind::<Timestamptz, _>(now + chrono::Duration::from_std(window)?)
+        .get_result::<Window>(conn)
+        .await?)
+    }
+
+    async fn get(&self, key: &str, now: DateTime<Utc>) -> anyhow::Result<Option<Window>> {
+        let conn = &mut self.0.get().await?;
+        Ok(rate_limits::table
+            .find(key)
+            .filter(rate_limits::resets_at.gt(now))
+            .select((rate_limits::hits, rate_limits::resets_at))
+            .first::<(i32, DateTime<Utc>)>(conn)
+            .await
+            .optional()?
+            .map(|(hits, resets_at)| Window { hits, resets_at }))
+    }
+
+    async fn clear(&self, key: &str) -> anyhow::Result<()> {
+        let conn = &mut self.0.get().await?;
+        delete(rate_limits::table.find(key)).execute(conn).await?;
+        Ok(())
+    }
+
+    async fn clean(&self, now: DateTime<Utc>) -> anyhow::Result<()> {
+        let conn = &mut self.0.get().await?;
+        delete(rate_limits::table.filter(rate_limits::resets_at.le(now)))
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Counts a request against `key`, failing with a 429 once `limit` is used up.
+pub async fn hit(state: &AppState, key: &str, limit: &Limit) -> AppResult<()> {
+    let now = state.clock.now();
+    let window = state.limits.hit(key, limit.window(), now).await?;
+    if window.hits > limit.requests as i32 {
+        Err(AppError::TooManyRequests(window.retry_after(now)))
+    } else {
+        Ok(())
+    }
+}
+
+/// Fails with a 429 if `key` has already used up `limit`, without counting a request.
+pub async fn check(state: &AppState, key: &str, limit: &Limit) -> AppResult<()> {
+    let now = state.clock.now();
+    match state.limits.get(key, now).await? {
+        Some(window) if window.hits >= limit.requests as i32 => {
+            Err(AppError::TooManyRequests(window.retry_after(now)))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Fails with a 429 while `username` is locked out after too many failed logins.
+pub async fn check_lockout(state: &AppState, username: &str) -> AppResult<()> {
+    let now = state.clock.now();
+    match state.limits.get(&format!("locked:{username}"), now).await? {
+        Some(window) => Err(AppError::TooManyRequests(window.retry_after(now))),
+        None => Ok(()),
+    }
+}
+
+/// Counts a failed login, locking `username` once there have been too many.
+pub async fn record_failed_login(state: &AppState, username: &str) -> AppResult<()> {
+    let config = &state.config.rate_limit;
+    let now = state.clock.now();
+    let key = format!("failed-login:{username}");
+
+    let failures = state
+        .limits
+        .hit(&key, Duration::from_secs(config.failed_login_window), now)
+        .await?;
+    if failures.hits >= config.max_failed_logins as i32 {
+        eprintln!(
+            "locking {username} for {}s after {} failed logins",
+            config.lockout, failures.hits
+        );
+        state
+            .limits
+            .hit(
+                &format!("locked:{username}"),
+                Duration::from_secs(config.lockout),
+                now,
+            )
+            .await?;
+        state.limits.clear(&key).await?;
+    }
+    Ok(())
+}
+
+/// Forgets the failed logins of `username` after a successful one.
+pub async fn clear_failed_logins(state: &AppState, username: &str) -> AppResult<()> {
+    state
+        .limits
+        .clear(&format!("failed-login:{username}"))
+        .await?;
+    Ok(())
+}
+
+// 1 hour
+const CLEAN_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Background job that drops finished windows so they don't pile up.
+pub async fn clean_expired_limits(
+    limits: Arc<dyn RateLimitStore>,
+    clock: Arc<dyn Clock>,
+    shutdown: Shutdown,
+) {
+    tasks::run_every(shutdown, CLEAN_INTERVAL, || {
+        let limits = limits.clone();
+        let now = clock.now();
+        async move {
+            if let Err(e) = limits.clean(now).await {
+                eprintln!("failed to clean rate limits: {e}");
+            }
+        }
+    })
+    .await
+}
+
+/// The ip a request came from. Read from `rate_limit.client_ip_header` when set, since
+/// behind a proxy the peer address is the proxy's.
+#[derive(Debug, Clone)]
+pub struct ClientIp(pub String);
+
+#[async_trait]
+impl FromRequestParts<AppState> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let header = &state.config.rate_limit.client_ip_header;
+        let ip = if header.is_empty() {
+            parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip().to_string())
+        } else {
+            parts
+                .headers
+                .get(header)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.trim().to_string())
+        };
+
+        // every request without an address shares one bucket
+        Ok(ClientIp(ip.unwrap_or_else(|| "unknown".to_string())))
+    }
+}
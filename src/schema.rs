@@ -1,5 +1,21 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    audit_log (id) {
+        id -> Int4,
+        created_at -> Timestamptz,
+        actor_id -> Nullable<Int4>,
+        actor -> Varchar,
+        action -> Varchar,
+        club_id -> Nullable<Int4>,
+        target -> Nullable<Varchar>,
+        ip -> Nullable<Varchar>,
+        user_agent -> Nullable<Varchar>,
+        before -> Nullable<Jsonb>,
+        after -> Nullable<Jsonb>,
+    }
+}
+
 diesel::table! {
     categories (id) {
         id -> Int4,
@@ -7,6 +23,22 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    club_applications (id) {
+        id -> Int4,
+        club_name -> Varchar,
+        description -> Varchar,
+        meet_time -> Varchar,
+        advisor -> Varchar,
+        email -> Varchar,
+        status -> Varchar,
+        submitted_at -> Timestamptz,
+        reviewed_at -> Nullable<Timestamptz>,
+        rejection_reason -> Nullable<Varchar>,
+        club_id -> Nullable<Int4>,
+    }
+}
+
 diesel::table! {
     club_categories (id) {
         id -> Int4,
@@ -15,6 +47,76 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    club_drafts (id) {
+        id -> Int4,
+        club_id -> Int4,
+        submitted_by -> Nullable<Int4>,
+        submitted_at -> Timestamptz,
+        club_name -> Varchar,
+        description -> Varchar,
+        about -> Text,
+        meet_time -> Varchar,
+        category_ids -> Array<Int4>,
+        website -> Nullable<Varchar>,
+        google_classroom -> Nullable<Varchar>,
+        discord -> Nullable<Varchar>,
+        instagram -> Nullable<Varchar>,
+        status -> Varchar,
+        reviewed_at -> Nullable<Timestamptz>,
+        review_comment -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    club_invitations (id) {
+        id -> Int4,
+        club_id -> Int4,
+        email -> Varchar,
+        role -> Varchar,
+        token_hash -> Varchar,
+        invited_by -> Nullable<Int4>,
+        expires_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    club_officers (club_id, user_id) {
+        club_id -> Int4,
+        user_id -> Int4,
+        role -> Varchar,
+    }
+}
+
+diesel::table! {
+    club_reminders (id) {
+        id -> Int4,
+        club_id -> Int4,
+        school_year -> Timestamptz,
+        token_hash -> Varchar,
+        sent_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    club_revisions (id) {
+        id -> Int4,
+        club_id -> Int4,
+        edited_by -> Nullable<Int4>,
+        created_at -> Timestamptz,
+        club_name -> Varchar,
+        description -> Varchar,
+        about -> Text,
+        meet_time -> Varchar,
+        category_ids -> Array<Int4>,
+        website -> Nullable<Varchar>,
+        google_classroom -> Nullable<Varchar>,
+        discord -> Nullable<Varchar>,
+        instagram -> Nullable<Varchar>,
+        reverted_from -> Nullable<Int4>,
+    }
+}
+
 diesel::table! {
     club_socials (id) {
         id -> Int4,
@@ -31,7 +133,6 @@ diesel::table! {
         id -> Int4,
         username -> Varchar,
         email -> Varchar,
-        password_hash -> Varchar,
         club_name -> Varchar,
         description -> Varchar,
         about -> Text,
@@ -39,11 +140,102 @@ diesel::table! {
         profile_picture_url -> Varchar,
         banner_url -> Varchar,
         featured -> Bool,
+        trusted -> Bool,
+        status -> Varchar,
+        deleted_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        confirmed_at -> Nullable<Timestamptz>,
+        missed_deadline -> Bool,
+    }
+}
+
+diesel::table! {
+    leadership_transfers (id) {
+        id -> Int4,
+        club_id -> Int4,
+        new_email -> Varchar,
+        token_hash -> Nullable<Varchar>,
+        requested_by -> Nullable<Int4>,
+        requested_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        old_email -> Nullable<Varchar>,
+        from_user -> Nullable<Int4>,
+        to_user -> Nullable<Int4>,
+        completed_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    password_resets (token_hash) {
+        token_hash -> Varchar,
+        user_id -> Int4,
+        expires_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    rate_limits (key) {
+        key -> Text,
+        hits -> Int4,
+        resets_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    user_totp (user_id) {
+        user_id -> Int4,
+        secret -> Varchar,
+        enabled -> Bool,
+        last_used_step -> Int8,
+        recovery_codes -> Array<Text>,
+    }
+}
+
+diesel::table! {
+    users (id) {
+        id -> Int4,
+        username -> Varchar,
+        email -> Varchar,
+        password_hash -> Varchar,
+        sessions_revoked_at -> Nullable<Timestamptz>,
     }
 }
 
+diesel::joinable!(audit_log -> clubs (club_id));
+diesel::joinable!(audit_log -> users (actor_id));
+diesel::joinable!(club_applications -> clubs (club_id));
 diesel::joinable!(club_categories -> categories (category_id));
 diesel::joinable!(club_categories -> clubs (club_id));
+diesel::joinable!(club_drafts -> clubs (club_id));
+diesel::joinable!(club_drafts -> users (submitted_by));
+diesel::joinable!(club_invitations -> clubs (club_id));
+diesel::joinable!(club_invitations -> users (invited_by));
+diesel::joinable!(club_officers -> clubs (club_id));
+diesel::joinable!(club_officers -> users (user_id));
+diesel::joinable!(club_reminders -> clubs (club_id));
+diesel::joinable!(club_revisions -> clubs (club_id));
+diesel::joinable!(club_revisions -> users (edited_by));
 diesel::joinable!(club_socials -> clubs (club_id));
+diesel::joinable!(leadership_transfers -> clubs (club_id));
+diesel::joinable!(password_resets -> users (user_id));
+diesel::joinable!(user_totp -> users (user_id));
 
-diesel::allow_tables_to_appear_in_same_query!(categories, club_categories, club_socials, clubs,);
+diesel::allow_tables_to_appear_in_same_query!(
+    audit_log,
+    categories,
+    club_applications,
+    club_categories,
+    club_drafts,
+    club_invitations,
+    club_officers,
+    club_reminders,
+    club_revisions,
+    club_socials,
+    clubs,
+    leadership_transfers,
+    password_resets,
+    rate_limits,
+    user_totp,
+    users,
+);
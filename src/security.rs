@@ -0,0 +1,68 @@
+use crate::config::ServerConfig;
+use axum::{
+    http::{
+        header::{
+            ACCEPT, AUTHORIZATION, CONTENT_SECURITY_POLICY, CONTENT_TYPE, REFERRER_POLICY,
+            STRICT_TRANSPORT_SECURITY, X_CONTENT_TYPE_OPTIONS,
+        },
+        HeaderValue, Method,
+    },
+    Router,
+};
+use std::time::Duration;
+use tower_http::{
+    cors::{AllowOrigin, CorsLayer},
+    set_header::SetResponseHeaderLayer,
+};
+
+// 1 year
+const HSTS: &str = "max-age=31536000; includeSubDomains";
+// uploads are only ever displayed as images, so nothing in them may run or load
+const ASSET_CSP: &str = "default-src 'none'; img-src 'self'; style-src 'unsafe-inline'; sandbox";
+// 1 hour
+const PREFLIGHT_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Only lets the configured origins call the api from a browser.
+pub fn cors(config: &ServerConfig) -> CorsLayer {
+    let origins = config
+        .allowed_origins()
+        .into_iter()
+        .filter_map(|origin| HeaderValue::from_str(&origin).ok());
+
+    CorsLayer::new()
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::PATCH,
+            Method::DELETE,
+            Method::OPTIONS,
+        ])
+        .allow_headers([ACCEPT, AUTHORIZATION, CONTENT_TYPE])
+        .allow_origin(AllowOrigin::list(origins))
+        .max_age(PREFLIGHT_MAX_AGE)
+}
+
+/// Adds CORS and the headers every response should carry. Handlers can still set
+/// their own values.
+pub fn protect(router: Router, config: &ServerConfig) -> Router {
+    router
+        .layer(cors(config))
+        .layer(SetResponseHeaderLayer::if_not_present(
+            STRICT_TRANSPORT_SECURITY,
+            HeaderValue::from_static(HSTS),
+        ))
+        .layer(SetResponseHeaderLayer::if_not_present(
+            X_CONTENT_TYPE_OPTIONS,
+            HeaderValue::from_static("nosniff"),
+        ))
+        .layer(SetResponseHeaderLayer::if_not_present(
+            REFERRER_POLICY,
+            HeaderValue::from_static("no-referrer"),
+        ))
+}
+
+/// Stops uploaded files from running scripts when opened directly.
+pub fn asset_csp() -> SetResponseHeaderLayer<HeaderValue> {
+    SetResponseHeaderLayer::overriding(CONTENT_SECURITY_POLICY, HeaderValue::from_static(ASSET_CSP))
+}
@@ -0,0 +1,53 @@
+use crate::{
+    api::password::{PgResets, ResetStore},
+    assets::{AssetStore, LocalAssetStore},
+    auth::Hasher,
+    clock::{Clock, SystemClock},
+    config::{AppConfig, RateLimitBackend},
+    connect_to_db,
+    email::{Mailer, Outbox, SmtpMailer},
+    keyring::Keys,
+    rate_limit::{PgRateLimits, RateLimitStore, RateLimits},
+    DbPool,
+};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Everything the handlers depend on. Build one with [`AppState::from_config`], or
+/// construct it directly to swap in other implementations (e.g. in tests).
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: DbPool,
+    pub config: Arc<AppConfig>,
+    pub mailer: Arc<dyn Mailer>,
+    /// emails sent in the background, see [`crate::email::send_queued`]
+    pub outbox: Outbox,
+    pub assets: Arc<dyn AssetStore>,
+    pub keys: Arc<Keys>,
+    pub hasher: Arc<Hasher>,
+    pub clock: Arc<dyn Clock>,
+    pub resets: Arc<dyn ResetStore>,
+    pub limits: Arc<dyn RateLimitStore>,
+}
+
+impl AppState {
+    pub fn from_config(config: AppConfig) -> anyhow::Result<AppState> {
+        let pool = connect_to_db(&config.database.url);
+        let limits: Arc<dyn RateLimitStore> = match config.rate_limit.backend {
+            RateLimitBackend::Memory => Arc::new(Mutex::new(RateLimits::default())),
+            RateLimitBackend::Postgres => Arc::new(PgRateLimits(pool.clone())),
+        };
+        Ok(AppState {
+            pool: pool.clone(),
+            mailer: Arc::new(SmtpMailer::from_config(&config.email)?),
+            outbox: Outbox::new(),
+            assets: Arc::new(LocalAssetStore::new("assets")),
+            keys: Arc::new(Keys::from_config(&config.auth)?),
+            hasher: Arc::new(Hasher::from_config(&config.password_hash)?),
+            clock: Arc::new(SystemClock),
+            resets: Arc::new(PgResets(pool)),
+            limits,
+            config: Arc::new(config),
+        })
+    }
+}
@@ -0,0 +1,174 @@
+use std::{any::Any, future::Future, time::Duration};
+
+use tokio::{sync::watch, task::JoinHandle, time};
+
+// how long to wait before restarting a background task that panicked
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+/// A handle that resolves once the server has started shutting down.
+#[derive(Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    pub fn is_cancelled(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    pub async fn cancelled(&mut self) {
+        while !self.is_cancelled() {
+            // the sender only goes away after shutdown, so treat that as cancelled too
+            if self.0.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Owns the long-running background jobs of the server.
+///
+/// Jobs are restarted when they panic and are all cancelled together when
+/// [`Supervisor::shutdown`] is called.
+pub struct Supervisor {
+    cancel: watch::Sender<bool>,
+    tasks: Vec<(&'static str, JoinHandle<()>)>,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        let (cancel, _) = watch::channel(false);
+        Self {
+            cancel,
+            tasks: Vec::new(),
+        }
+    }
+
+    pub fn shutdown_signal(&self) -> Shutdown {
+        Shutdown(self.cancel.subscribe())
+    }
+
+    /// Spawns a job that runs until it returns or the supervisor shuts down.
+    /// `task` is called again to restart the job if it panics.
+    pub fn spawn<F, Fut>(&mut self, name: &'static str, task: F)
+    where
+        F: Fn(Shutdown) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut shutdown = self.shutdown_signal();
+        let handle = tokio::spawn(async move {
+            loop {
+                // the job runs in its own task so its panics can be caught, and has to be
+                // aborted along with this one
+                let mut job = AbortOnDrop(tokio::spawn(task(shutdown.clone())));
+                let Err(err) = (&mut job.0).await else {
+                    return;
+                };
+                if !err.is_panic() {
+                    return;
+                }
+
+                eprintln!(
+                    "background task `{name}` panicked: {}",
+                    panic_message(&err.into_panic())
+                );
+                if shutdown.is_cancelled() {
+                    return;
+                }
+
+                tokio::select! {
+                    _ = time::sleep(RESTART_DELAY) => eprintln!("restarting background task `{name}`"),
+                    _ = shutdown.cancelled() => return,
+                }
+            }
+        });
+
+        self.tasks.push((name, handle));
+    }
+
+    /// Signals every job to stop without waiting for them.
+    pub fn cancel(&self) {
+        self.cancel.send_replace(true);
+    }
+
+    /// Cancels every job and waits up to `timeout` for them to finish.
+    /// Jobs still running after that are aborted.
+    pub async fn shutdown(self, timeout: Duration) {
+        self.cancel();
+
+        let deadline = time::Instant::now() + timeout;
+        for (name, mut handle) in self.tasks {
+            if time::timeout_at(deadline, &mut handle).await.is_err() {
+                eprintln!("background task `{name}` did not stop in time, aborting it");
+                handle.abort();
+                // the restart loop aborts the job when it is dropped
+                let _ = handle.await;
+            }
+        }
+    }
+}
+
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Runs `job` every `period` until `shutdown` fires. A job that is already
+/// running when shutdown starts is allowed to finish.
+pub async fn run_every<F, Fut>(mut shutdown: Shutdown, period: Duration, mut job: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let mut interval = time::interval(period);
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => job().await,
+            _ = shutdown.cancelled() => return,
+        }
+    }
+}
+
+/// Resolves when the process receives SIGINT or SIGTERM.
+pub async fn termination_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for ctrl-c");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+fn panic_message(payload: &Box<dyn Any + Send>) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "unknown panic payload"
+    }
+}
@@ -0,0 +1,122 @@
+//! Time-based one-time passwords (RFC 6238) as authenticator apps generate them:
+//! HMAC-SHA1, 6 digits, a new code every 30 seconds.
+
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+
+/// Seconds each code is valid for.
+pub const STEP: u64 = 30;
+pub const DIGITS: u32 = 6;
+/// Issuer shown in authenticator apps.
+pub const ISSUER: &str = "CCA Club Hub";
+
+// codes from the step before and after are accepted too, for clocks that are a little off
+const SKEW: u64 = 1;
+// 160 bits, as RFC 4226 recommends for HMAC-SHA1
+const SECRET_LEN: usize = 20;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// A new random secret, base32 encoded like authenticator apps expect.
+pub fn generate_secret() -> String {
+    let mut secret = [0; SECRET_LEN];
+    rand::thread_rng().fill_bytes(&mut secret);
+    base32_encode(&secret)
+}
+
+/// The time step `timestamp` falls in.
+pub fn step_at(timestamp: u64) -> u64 {
+    timestamp / STEP
+}
+
+/// The code for `step` (RFC 4226 HOTP with the step as the counter).
+pub fn code(secret: &[u8], step: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("hmac takes keys of any length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = usize::from(hash[hash.len() - 1] & 0x0f);
+    let truncated = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    truncated % 10u32.pow(DIGITS)
+}
+
+/// The step `code` was generated for if it is valid at `timestamp` and newer than
+/// `last_used_step`, so each code only works once.
+pub fn verify(secret: &str, code: &str, timestamp: u64, last_used_step: u64) -> Option<u64> {
+    let secret = base32_decode(secret)?;
+    // apps show codes as `123 456`
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code = code.parse::<u32>().ok()?;
+
+    let now = step_at(timestamp);
+    (now.saturating_sub(SKEW)..=now + SKEW)
+        .filter(|step| *step > last_used_step)
+        .find(|step| self::code(&secret, *step) == code)
+}
+
+/// The `otpauth://` URI authenticator apps are set up with, usually through a QR code.
+pub fn provisioning_uri(secret: &str, account: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={secret}&issuer={}&algorithm=SHA1&digits={DIGITS}&period={STEP}",
+        percent_encode(ISSUER),
+        percent_encode(account),
+        percent_encode(ISSUER),
+    )
+}
+
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(b).to_string()
+            }
+            b => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// RFC 4648 base32 without padding.
+pub fn base32_encode(bytes: &[u8]) -> String {
+    let mut result = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let (mut buffer, mut bits) = (0u32, 0);
+    for byte in bytes {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            result.push(char::from(BASE32_ALPHABET[(buffer >> bits) as usize & 31]));
+        }
+    }
+    if bits > 0 {
+        result.push(char::from(
+            BASE32_ALPHABET[(buffer << (5 - bits)) as usize & 31],
+        ));
+    }
+    result
+}
+
+/// Decodes base32, ignoring case, spaces and padding. `None` if it isn't base32.
+pub fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut result = Vec::with_capacity(s.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in s.bytes().filter(|c| !matches!(c, b' ' | b'=')) {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            result.push((buffer >> bits) as u8);
+        }
+    }
+    Some(result)
+}
@@ -0,0 +1,136 @@
+//! Clubs applying to be listed and admins reviewing them.
+
+mod common;
+
+use axum::{
+    http::{Method, StatusCode},
+    response::IntoResponse,
+};
+use cca_club_hub::{
+    api::applications,
+    error::AppError,
+    models::{ApplicationStatus, ClubApplication},
+    schema::{club_applications, clubs},
+};
+use common::TestApp;
+use diesel::QueryDsl;
+use diesel_async::RunQueryDsl;
+use serde_json::json;
+
+async fn apply(app: &TestApp, name: &str) -> i32 {
+    let (status, body) = app
+        .request(
+            Method::POST,
+            "/api/club/apply",
+            Some(json!({
+                "name": name,
+                "description": "we play chess",
+                "meetTime": "Fridays",
+                "advisor": "Mr. Smith",
+                "email": "applicant@example.com",
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "pending");
+    body["id"].as_i64().unwrap() as i32
+}
+
+fn status_of(error: Option<AppError>) -> Option<StatusCode> {
+    error.map(|e| e.into_response().status())
+}
+
+#[tokio::test]
+async fn applications_need_every_field() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let (status, body) = app
+        .request(
+            Method::POST,
+            "/api/club/apply",
+            Some(json!({
+                "name": " ",
+                "description": "we play chess",
+                "meetTime": "",
+                "advisor": "Mr. Smith",
+                "email": "not an email",
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let fields = body["errors"].to_string();
+    assert!(fields.contains("name") && fields.contains("email"));
+}
+
+#[tokio::test]
+async fn approving_an_application_registers_the_club() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let alphabet: Vec<char> = ('a'..='z').chain('0'..='9').collect();
+    let suffix = nanoid::nanoid!(8, &alphabet);
+    let id = apply(&app, &format!("Chess Club {suffix}")).await;
+
+    let club = applications::approve(&app.state, id, None)
+        .await
+        .unwrap_or_else(|e| panic!("approving failed: {e}"));
+    assert_eq!(club.username, format!("chess-club-{suffix}"));
+    assert_eq!(club.email, "applicant@example.com");
+    app.mailer.wait_for(1).await;
+    assert_eq!(app.mailer.count(), 1);
+
+    let conn = &mut app.state.pool.get().await.unwrap();
+    let application = club_applications::table
+        .find(id)
+        .first::<ClubApplication>(conn)
+        .await
+        .unwrap();
+    assert_eq!(application.status, ApplicationStatus::Approved);
+    assert_eq!(application.club_id, Some(club.id));
+    let listed = clubs::table
+        .find(club.id)
+        .select(clubs::club_name)
+        .first::<String>(conn)
+        .await
+        .unwrap();
+    assert_eq!(listed, format!("Chess Club {suffix}"));
+
+    let again = applications::approve(&app.state, id, None).await;
+    assert_eq!(status_of(again.err()), Some(StatusCode::CONFLICT));
+}
+
+#[tokio::test]
+async fn rejecting_an_application_emails_the_reason() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let id = apply(&app, "Debate Club").await;
+
+    let application = applications::reject(&app.state, id, "we already have one".to_string())
+        .await
+        .unwrap_or_else(|e| panic!("rejecting failed: {e}"));
+    assert_eq!(application.status, ApplicationStatus::Rejected);
+    assert_eq!(
+        application.rejection_reason.as_deref(),
+        Some("we already have one")
+    );
+
+    let email = app.mailer.0.lock().unwrap().last().unwrap().formatted();
+    assert!(String::from_utf8(email)
+        .unwrap()
+        .contains("we already have one"));
+
+    let again = applications::reject(&app.state, id, "again".to_string()).await;
+    assert_eq!(status_of(again.err()), Some(StatusCode::CONFLICT));
+}
+
+#[test]
+fn usernames_come_from_club_names() {
+    assert_eq!(applications::username_for("Chess Club!"), "chess-club");
+    assert_eq!(
+        applications::username_for("  Robotics & AI  "),
+        "robotics-ai"
+    );
+    assert_eq!(applications::username_for("!!!"), "");
+}
@@ -0,0 +1,284 @@
+//! The audit log and searching it.
+
+mod common;
+
+use axum::http::{Method, StatusCode};
+use chrono::{Duration, SecondsFormat, Utc};
+use common::TestApp;
+use serde_json::{json, Value};
+
+async fn search(app: &TestApp, query: &str) -> Vec<Value> {
+    let (status, body) = app
+        .request_with_token(
+            "admin",
+            Method::GET,
+            &format!("/api/admin/audit?{query}"),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    body.as_array().unwrap().clone()
+}
+
+#[tokio::test]
+async fn logins_are_recorded() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let (_, president) = app.club("correct horse").await;
+
+    let (status, _) = app
+        .request(
+            Method::POST,
+            "/api/auth/login",
+            Some(json!({ "username": president.username, "password": "wrong" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app
+        .request(
+            Method::POST,
+            "/api/auth/login",
+            Some(json!({ "username": president.username, "password": "correct horse" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let entries = search(&app, &format!("actor={}", president.username)).await;
+    let actions: Vec<_> = entries.iter().map(|entry| &entry["action"]).collect();
+    assert_eq!(actions, ["auth.login", "auth.login_failed"]);
+    // someone who isn't logged in isn't tied to the account they tried
+    assert_eq!(entries[0]["actorId"], president.id);
+    assert_eq!(entries[1]["actorId"], Value::Null);
+}
+
+#[tokio::test]
+async fn edits_are_recorded_with_what_changed() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let (club, president) = app.club("correct horse").await;
+    let before = Utc::now() - Duration::seconds(1);
+
+    let (status, _) = app
+        .request_as(
+            &president,
+            Method::POST,
+            &format!("/api/edit/{}/info", club.username),
+            Some(json!({
+                "clubName": club.club_name,
+                "description": club.description,
+                "about": "new about",
+                "meetTime": club.meet_time,
+                "categories": [],
+                "socials": {},
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let entries = search(&app, &format!("club={}&action=club.edit", club.username)).await;
+    assert_eq!(entries.len(), 1);
+    let entry = &entries[0];
+    assert_eq!(entry["actor"], president.username);
+    assert_eq!(entry["club"], club.username);
+    assert_eq!(entry["before"]["about"], "");
+    assert_eq!(entry["after"]["about"], "new about");
+
+    let time = |time: chrono::DateTime<Utc>| time.to_rfc3339_opts(SecondsFormat::Secs, true);
+    let query = format!("club={}&from={}", club.username, time(before));
+    assert_eq!(search(&app, &query).await.len(), 1);
+    let query = format!("club={}&to={}", club.username, time(before));
+    assert!(search(&app, &query).await.is_empty());
+}
+
+#[tokio::test]
+async fn admin_actions_are_recorded() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let (club, _) = app.club("correct horse").await;
+
+    let (status, _) = app
+        .request_with_token(
+            "admin",
+            Method::POST,
+            &format!("/api/admin/trust/{}", club.username),
+            Some(json!({ "trusted": true })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let entries = search(&app, &format!("club={}", club.username)).await;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["actor"], "admin");
+    assert_eq!(entries[0]["action"], "club.trust");
+    assert_eq!(entries[0]["before"], json!({ "trusted": false }));
+    assert_eq!(entries[0]["after"], json!({ "trusted": true }));
+
+    let (status, _) = app.request(Method::GET, "/api/admin/audit", None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app
+        .request_with_token("admin", Method::GET, "/api/admin/audit?limit=0", None)
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn officer_changes_are_recorded() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let (club, president) = app.club("correct horse").await;
+    let user = app.user_without_clubs("correct horse").await;
+    let officers = format!("/api/officers/{}", club.username);
+
+    let sent = app.mailer.count();
+    let (status, _) = app
+        .request_as(
+            &president,
+            Method::POST,
+            &format!("{officers}/invitations"),
+            Some(json!({ "email": user.email, "role": "officer" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    app.mailer.wait_for(sent + 1).await;
+    let token = {
+        let emails = app.mailer.0.lock().unwrap();
+        let email = String::from_utf8(emails.last().unwrap().formatted()).unwrap();
+        let start = email.find("/invitation/").unwrap() + "/invitation/".len();
+        email[start..]
+            .chars()
+            .take_while(|c| !c.is_whitespace())
+            .collect::<String>()
+    };
+    let (status, _) = app
+        .request_as(
+            &user,
+            Method::POST,
+            &format!("/api/invitations/{token}/accept"),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app
+        .request_as(
+            &president,
+            Method::DELETE,
+            &format!("{officers}/officers/{}", user.username),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let entries = search(&app, &format!("club={}", club.username)).await;
+    let actions: Vec<_> = entries
+        .iter()
+        .map(|entry| (&entry["actor"], &entry["action"], &entry["target"]))
+        .collect();
+    assert_eq!(
+        actions,
+        [
+            (
+                &json!(president.username),
+                &json!("officer.remove"),
+                &json!(user.username)
+            ),
+            (
+                &json!(user.username),
+                &json!("officer.join"),
+                &json!(club.username)
+            ),
+            (
+                &json!(president.username),
+                &json!("officer.invite"),
+                &json!(user.email)
+            ),
+        ]
+    );
+    assert_eq!(entries[0]["before"], json!({ "role": "officer" }));
+    assert_eq!(entries[1]["after"], json!({ "role": "officer" }));
+}
+
+#[tokio::test]
+async fn president_transfers_are_recorded() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let (club, president) = app.club("correct horse").await;
+    let transfer = format!("/api/officers/{}/transfer", club.username);
+
+    let (status, _) = app
+        .request_as(
+            &president,
+            Method::POST,
+            &transfer,
+            Some(json!({ "email": "next@example.com" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app
+        .request_as(&president, Method::DELETE, &transfer, None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let entries = search(&app, &format!("club={}", club.username)).await;
+    let actions: Vec<_> = entries.iter().map(|entry| &entry["action"]).collect();
+    assert_eq!(actions, ["transfer.cancel", "transfer.nominate"]);
+    assert_eq!(entries[1]["actor"], president.username.as_str());
+    assert_eq!(entries[1]["after"]["newEmail"], "next@example.com");
+}
+
+#[tokio::test]
+async fn invitation_changes_are_recorded() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let (club, president) = app.club("correct horse").await;
+    let invitations = format!("/api/officers/{}/invitations", club.username);
+
+    let (status, body) = app
+        .request_as(
+            &president,
+            Method::POST,
+            &invitations,
+            Some(json!({ "email": "new@example.com", "role": "editor" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let id = body["id"].as_i64().unwrap();
+    let (status, _) = app
+        .request_as(
+            &president,
+            Method::POST,
+            &format!("{invitations}/{id}/resend"),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app
+        .request_as(
+            &president,
+            Method::DELETE,
+            &format!("{invitations}/{id}"),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let entries = search(&app, &format!("club={}", club.username)).await;
+    let actions: Vec<_> = entries.iter().map(|entry| &entry["action"]).collect();
+    assert_eq!(
+        actions,
+        [
+            "officer.invitation_revoke",
+            "officer.invitation_resend",
+            "officer.invite"
+        ]
+    );
+    assert!(entries
+        .iter()
+        .all(|entry| entry["target"] == "new@example.com"));
+    assert_eq!(entries[0]["before"], json!({ "role": "editor" }));
+}
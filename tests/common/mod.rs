@@ -0,0 +1,262 @@
+//! Helpers for tests that run requests against a real database. They are skipped unless
+//! `TEST_DATABASE_URL` points at a postgres database the tests may write to.
+
+#![allow(dead_code)]
+
+use axum::{
+    async_trait,
+    body::Body,
+    http::{HeaderMap, Method, Request, StatusCode},
+    Router,
+};
+use cca_club_hub::{
+    api::{
+        admin::{self, ClubRegisterRequest},
+        password::Resets,
+    },
+    assets::LocalAssetStore,
+    auth::{self, Hasher},
+    clock::SystemClock,
+    config::AppConfig,
+    connect_to_db,
+    email::{self, Mailer, Outbox},
+    keyring::Keys,
+    migrations,
+    models::{Club, User},
+    rate_limit::RateLimits,
+    schema::users,
+    state::AppState,
+    tasks::Supervisor,
+};
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
+use lettre::{Address, Message};
+use serde_json::Value;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tower::ServiceExt;
+
+/// Keeps every email instead of sending it.
+#[derive(Default)]
+pub struct RecordingMailer(pub Mutex<Vec<Message>>);
+
+#[async_trait]
+impl Mailer for RecordingMailer {
+    fn address(&self) -> Address {
+        "hub@example.com".parse().unwrap()
+    }
+
+    async fn send(&self, msg: Message) -> anyhow::Result<()> {
+        self.0.lock().unwrap().push(msg);
+        Ok(())
+    }
+}
+
+impl RecordingMailer {
+    pub fn count(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    /// Waits for emails sent in the background until there are `count` of them.
+    pub async fn wait_for(&self, count: usize) {
+        for _ in 0..100 {
+            if self.count() >= count {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+}
+
+pub struct TestApp {
+    pub state: AppState,
+    pub mailer: Arc<RecordingMailer>,
+    /// runs the email outbox
+    pub tasks: Supervisor,
+}
+
+/// Builds the app against `TEST_DATABASE_URL`, or returns `None` (skipping the test)
+/// when it isn't set.
+pub async fn test_app() -> Option<TestApp> {
+    let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+        eprintln!("TEST_DATABASE_URL is not set, skipping");
+        return None;
+    };
+    let migrate_url = url.clone();
+    tokio::task::spawn_blocking(move || migrations::run_pending(&migrate_url))
+        .await
+        .unwrap()
+        .expect("failed to migrate the test database");
+
+    let mut config = AppConfig::default();
+    config.server.frontend_host = "http://localhost:3000".to_string();
+    config.database.url = url.clone();
+    config.auth.jwt_secret = "c2VjcmV0c2VjcmV0c2VjcmV0c2VjcmV0".to_string();
+    config.auth.admin_key = "admin".to_string();
+    config.email.username = "hub@example.com".to_string();
+
+    let mailer = Arc::new(RecordingMailer::default());
+    let state = AppState {
+        pool: connect_to_db(&url),
+        mailer: mailer.clone(),
+        outbox: Outbox::new(),
+        assets: Arc::new(LocalAssetStore::new(
+            std::env::temp_dir().join("cca-test-assets"),
+        )),
+        keys: Arc::new(Keys::from_config(&config.auth).unwrap()),
+        hasher: Arc::new(Hasher::from_config(&config.password_hash).unwrap()),
+        clock: Arc::new(SystemClock),
+        resets: Arc::new(tokio::sync::Mutex::new(Resets::default())),
+        limits: Arc::new(tokio::sync::Mutex::new(RateLimits::default())),
+        config: Arc::new(config),
+    };
+
+    let mut tasks = Supervisor::new();
+    let queued = state.outbox.queued();
+    tasks.spawn("email outbox", move |shutdown| {
+        email::send_queued(queued.clone(), shutdown)
+    });
+
+    Some(TestApp {
+        state,
+        mailer,
+        tasks,
+    })
+}
+
+impl TestApp {
+    /// Registers a club with a unique username and sets the password of its president.
+    pub async fn club(&self, password: &str) -> (Club, User) {
+        let username = format!("test-{}", nanoid::nanoid!(10, &nanoid::alphabet::SAFE));
+        let club = admin::register_club(
+            &self.state,
+            ClubRegisterRequest {
+                username: username.clone(),
+                email: format!("{username}@example.com"),
+                name: username,
+                description: String::new(),
+                meet_time: String::new(),
+            },
+        )
+        .await
+        .unwrap_or_else(|e| panic!("failed to register a club: {e}"));
+
+        let conn = &mut self.state.pool.get().await.unwrap();
+        let president = diesel::update(users::table.filter(users::username.eq(&club.username)))
+            .set(users::password_hash.eq(self.state.hasher.hash(password).unwrap()))
+            .get_result(conn)
+            .await
+            .unwrap();
+        (club, president)
+    }
+
+    /// Like [`TestApp::club`], only returning the president.
+    pub async fn user(&self, password: &str) -> User {
+        self.club(password).await.1
+    }
+
+    /// Registers a user with a unique username who isn't an officer of any club.
+    pub async fn user_without_clubs(&self, password: &str) -> User {
+        let username = format!("user-{}", nanoid::nanoid!(10, &nanoid::alphabet::SAFE));
+        let conn = &mut self.state.pool.get().await.unwrap();
+        diesel::insert_into(users::table)
+            .values((
+                users::username.eq(&username),
+                users::email.eq(format!("{username}@example.com")),
+                users::password_hash.eq(self.state.hasher.hash(password).unwrap()),
+            ))
+            .get_result(conn)
+            .await
+            .unwrap()
+    }
+
+    /// Sends a request with an optional json body, returning the status and json response
+    /// (`Null` if the body is empty).
+    pub async fn request(
+        &self,
+        method: Method,
+        uri: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        self.send(method, uri, None, body).await
+    }
+
+    /// Like [`TestApp::request`], logged in as `user`.
+    pub async fn request_as(
+        &self,
+        user: &User,
+        method: Method,
+        uri: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let conn = &mut self.state.pool.get().await.unwrap();
+        let clubs = auth::officer_clubs(conn, user.id).await.unwrap();
+        let token = auth::generate_jwt(
+            &self.state.keys,
+            self.state.clock.as_ref(),
+            user,
+            clubs,
+            Duration::from_secs(60 * 60),
+        )
+        .unwrap();
+        self.send(method, uri, Some(&token), body).await
+    }
+
+    /// Like [`TestApp::request`], with `token` as the bearer token.
+    pub async fn request_with_token(
+        &self,
+        token: &str,
+        method: Method,
+        uri: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        self.send(method, uri, Some(token), body).await
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let (status, _, json) = self.response(method, uri, token, body).await;
+        (status, json)
+    }
+
+    /// Like [`TestApp::request_with_token`], also returning the response headers.
+    pub async fn response(
+        &self,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> (StatusCode, HeaderMap, Value) {
+        let router: Router = cca_club_hub::app_with_state(self.state.clone());
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json");
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {token}"));
+        }
+        let request = match body {
+            Some(body) => request.body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        }
+        .unwrap();
+
+        let response = router.oneshot(request).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let json = if bytes.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_slice(&bytes).unwrap()
+        };
+        (status, headers, json)
+    }
+}
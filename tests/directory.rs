@@ -0,0 +1,208 @@
+//! Exporting the club directory and importing it back.
+
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::TestApp;
+use serde_json::{json, Value};
+
+async fn import(app: &TestApp, query: &str, rows: Value) -> (StatusCode, Value) {
+    app.request_with_token(
+        "admin",
+        Method::POST,
+        &format!("/api/admin/directory/import?format=json{query}"),
+        Some(rows),
+    )
+    .await
+}
+
+async fn info(app: &TestApp, username: &str) -> (StatusCode, Value) {
+    app.request(Method::GET, &format!("/api/club/info/{username}"), None)
+        .await
+}
+
+fn new_username() -> String {
+    format!("test-{}", nanoid::nanoid!(10, &nanoid::alphabet::SAFE))
+}
+
+#[tokio::test]
+async fn exports_list_every_club() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let (club, _) = app.club("correct horse").await;
+
+    let (status, body) = app
+        .request_with_token(
+            "admin",
+            Method::GET,
+            "/api/admin/directory/export/clubs",
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let row = body
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|row| row["username"] == club.username)
+        .unwrap();
+    assert_eq!(row["email"], club.email);
+    assert_eq!(row["name"], club.club_name);
+    assert_eq!(row["categories"], "");
+
+    let (status, _) = app
+        .request(Method::GET, "/api/admin/directory/export/clubs", None)
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app
+        .request_with_token(
+            "admin",
+            Method::GET,
+            "/api/admin/directory/export/officers",
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn dry_runs_show_what_would_change() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let (changed, _) = app.club("correct horse").await;
+    let (unchanged, _) = app.club("correct horse").await;
+    let new = new_username();
+
+    let (status, report) = import(
+        &app,
+        "&dryRun=true",
+        json!([
+            {
+                "username": changed.username,
+                "email": changed.email,
+                "name": changed.club_name,
+                "description": "now with more chess",
+            },
+            {
+                "username": unchanged.username,
+                "email": unchanged.email,
+                "name": unchanged.club_name,
+            },
+            { "username": new, "email": format!("{new}@example.com"), "name": "New Club" },
+        ]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(report["applied"], false);
+    assert_eq!(report["new"], json!([new]));
+    assert_eq!(
+        report["changed"],
+        json!([{ "username": changed.username, "fields": ["description"] }])
+    );
+    assert_eq!(report["unchanged"], json!([unchanged.username]));
+
+    assert_eq!(info(&app, &changed.username).await.1["description"], "");
+    assert_eq!(info(&app, &new).await.0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn invalid_rows_stop_the_import() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let (club, _) = app.club("correct horse").await;
+
+    let (status, report) = import(
+        &app,
+        "",
+        json!([
+            {
+                "username": club.username,
+                "email": club.email,
+                "name": club.club_name,
+                "description": "changed",
+            },
+            { "username": club.username, "email": "not an email", "name": "Again" },
+            {
+                "username": new_username(),
+                "email": "new@example.com",
+                "name": "New",
+                "categories": "no such category",
+            },
+        ]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let errors: Vec<_> = report["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| {
+            (
+                error["row"].as_u64().unwrap(),
+                error["field"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(errors, [(2, "username"), (2, "email"), (3, "categories")]);
+    assert_eq!(info(&app, &club.username).await.1["description"], "");
+}
+
+#[tokio::test]
+async fn a_failing_club_does_not_stop_the_others() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let (club, _) = app.club("correct horse").await;
+    let taken = app.user_without_clubs("correct horse").await;
+    let new = new_username();
+
+    let (status, report) = import(
+        &app,
+        "",
+        json!([
+            {
+                "username": club.username,
+                "email": club.email,
+                "name": club.club_name,
+                "meet_time": "Fridays",
+            },
+            { "username": taken.username, "email": "taken@example.com", "name": "Taken" },
+            {
+                "username": new,
+                "email": format!("{new}@example.com"),
+                "name": "New Club",
+                "about": "all about it",
+                "website": "https://example.com",
+            },
+        ]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(report["applied"], true);
+    assert_eq!(
+        report["failed"],
+        json!([{
+            "row": 2,
+            "field": null,
+            "message": "a user with that username already exists",
+        }])
+    );
+
+    assert_eq!(info(&app, &club.username).await.1["meetTime"], "Fridays");
+    // the club of the failed row was rolled back with it
+    assert_eq!(info(&app, &taken.username).await.0, StatusCode::NOT_FOUND);
+    let (status, created) = info(&app, &new).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(created["about"], "all about it");
+    assert_eq!(created["socials"]["website"], "https://example.com");
+
+    let emails = app.mailer.0.lock().unwrap();
+    let onboarded = emails
+        .iter()
+        .map(|email| String::from_utf8(email.formatted()).unwrap())
+        .any(|email| email.contains(&format!("{new}@example.com")) && email.contains("Welcome"));
+    assert!(onboarded);
+}
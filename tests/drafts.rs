@@ -0,0 +1,165 @@
+//! Moderated profile edits.
+
+mod common;
+
+use axum::http::{Method, StatusCode};
+use cca_club_hub::models::{Club, User};
+use common::TestApp;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+/// A test app with moderated edits.
+async fn moderated_app() -> Option<TestApp> {
+    let mut app = common::test_app().await?;
+    Arc::make_mut(&mut app.state.config).moderation.edits = true;
+    Some(app)
+}
+
+async fn edit(app: &TestApp, user: &User, club: &Club, about: &str) -> (StatusCode, Value) {
+    app.request_as(
+        user,
+        Method::POST,
+        &format!("/api/edit/{}/info", club.username),
+        Some(json!({
+            "clubName": club.club_name,
+            "description": "a description",
+            "about": about,
+            "meetTime": "Mondays",
+            "categories": [],
+            "socials": {},
+        })),
+    )
+    .await
+}
+
+async fn public_about(app: &TestApp, club: &Club) -> Value {
+    let (_, body) = app
+        .request(
+            Method::GET,
+            &format!("/api/club/info/{}", club.username),
+            None,
+        )
+        .await;
+    body["about"].clone()
+}
+
+#[tokio::test]
+async fn moderated_edits_wait_for_an_admin() {
+    let Some(app) = moderated_app().await else {
+        return;
+    };
+    let (club, president) = app.club("correct horse").await;
+
+    let (status, body) = edit(&app, &president, &club, "first draft").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["published"], false);
+    let first = body["draft"]["id"].as_i64().unwrap();
+    let (_, body) = edit(&app, &president, &club, "second draft").await;
+    let second = body["draft"]["id"].as_i64().unwrap();
+    assert_eq!(public_about(&app, &club).await, "");
+
+    let (status, _) = app
+        .request_with_token(
+            "admin",
+            Method::POST,
+            &format!("/api/admin/drafts/{first}/approve"),
+            Some(json!({})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, body) = app
+        .request_with_token(
+            "admin",
+            Method::POST,
+            &format!("/api/admin/drafts/{second}/approve"),
+            Some(json!({ "comment": "looks good" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "approved");
+    assert_eq!(public_about(&app, &club).await, "second draft");
+
+    let (status, body) = app
+        .request_as(
+            &president,
+            Method::GET,
+            &format!("/api/edit/{}/drafts", club.username),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let statuses: Vec<&str> = body
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|draft| draft["status"].as_str().unwrap())
+        .collect();
+    assert_eq!(statuses, ["approved", "superseded"]);
+    assert_eq!(body[0]["reviewComment"], "looks good");
+    assert_eq!(body[0]["submittedBy"], president.username.as_str());
+}
+
+#[tokio::test]
+async fn rejected_drafts_keep_the_public_profile() {
+    let Some(app) = moderated_app().await else {
+        return;
+    };
+    let (club, president) = app.club("correct horse").await;
+    let (_, body) = edit(&app, &president, &club, "something inappropriate").await;
+    let id = body["draft"]["id"].as_i64().unwrap();
+    let reject = format!("/api/admin/drafts/{id}/reject");
+
+    let (status, _) = app
+        .request_with_token("admin", Method::POST, &reject, Some(json!({})))
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (status, body) = app
+        .request_with_token(
+            "admin",
+            Method::POST,
+            &reject,
+            Some(json!({ "comment": "please keep it school appropriate" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "rejected");
+    assert_eq!(public_about(&app, &club).await, "");
+
+    let (_, body) = app
+        .request_with_token(
+            "admin",
+            Method::GET,
+            "/api/admin/drafts?status=pending",
+            None,
+        )
+        .await;
+    assert!(!body
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|draft| draft["club"] == club.username.as_str()));
+}
+
+#[tokio::test]
+async fn trusted_clubs_skip_moderation() {
+    let Some(app) = moderated_app().await else {
+        return;
+    };
+    let (club, president) = app.club("correct horse").await;
+
+    let (status, _) = app
+        .request_with_token(
+            "admin",
+            Method::POST,
+            &format!("/api/admin/trust/{}", club.username),
+            Some(json!({ "trusted": true })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = edit(&app, &president, &club, "published right away").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["published"], true);
+    assert_eq!(public_about(&app, &club).await, "published right away");
+}
@@ -0,0 +1,94 @@
+//! Responses must not reveal whether an account exists.
+
+mod common;
+
+use axum::http::{Method, StatusCode};
+use cca_club_hub::{auth::Hasher, config::PasswordHashConfig};
+use serde_json::json;
+
+#[tokio::test]
+async fn login_fails_the_same_for_unknown_users() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let user = app.user("correct horse").await;
+
+    let wrong_password = app
+        .request(
+            Method::POST,
+            "/api/auth/login",
+            Some(json!({ "username": user.username, "password": "battery staple" })),
+        )
+        .await;
+    let unknown_user = app
+        .request(
+            Method::POST,
+            "/api/auth/login",
+            Some(json!({ "username": "no-such-user", "password": "battery staple" })),
+        )
+        .await;
+
+    assert_eq!(wrong_password.0, StatusCode::UNAUTHORIZED);
+    assert_eq!(wrong_password, unknown_user);
+}
+
+#[tokio::test]
+async fn login_still_works_with_the_right_password() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let user = app.user("correct horse").await;
+
+    let (status, body) = app
+        .request(
+            Method::POST,
+            "/api/auth/login",
+            Some(json!({ "username": user.username, "password": "correct horse" })),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["token"].is_string());
+}
+
+#[tokio::test]
+async fn reset_request_answers_the_same_for_unknown_emails() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let user = app.user("correct horse").await;
+    // the onboarding email
+    let sent = app.mailer.count();
+
+    let known = app
+        .request(
+            Method::POST,
+            "/api/password/reset",
+            Some(json!({ "email": user.email })),
+        )
+        .await;
+    let unknown = app
+        .request(
+            Method::POST,
+            "/api/password/reset",
+            Some(json!({ "email": "nobody@example.com" })),
+        )
+        .await;
+
+    assert_eq!(known.0, StatusCode::OK);
+    assert_eq!(known, unknown);
+
+    // only the real user gets an email
+    app.mailer.wait_for(sent + 1).await;
+    assert_eq!(app.mailer.count(), sent + 1);
+}
+
+#[test]
+fn dummy_verification_never_succeeds() {
+    let hasher = Hasher::from_config(&PasswordHashConfig::default()).unwrap();
+    assert!(!hasher.verify_or_dummy("not a real password", None).unwrap());
+    assert!(!hasher.verify_or_dummy("", None).unwrap());
+
+    let hash = hasher.hash("hunter2").unwrap();
+    assert!(hasher.verify_or_dummy("hunter2", Some(&hash)).unwrap());
+}
@@ -0,0 +1,188 @@
+use cca_club_hub::{
+    api::{
+        admin::ONBOARDING_ALLOWED_TIME,
+        password::{ResetError, Resets},
+    },
+    auth,
+    clock::{Clock, MockClock},
+    config::{AuthConfig, JwtKeyConfig},
+    keyring::Keys,
+    models::User,
+};
+use chrono::{Duration, TimeZone, Utc};
+use std::time::Duration as StdDuration;
+
+const HOUR: StdDuration = StdDuration::from_secs(60 * 60);
+
+fn clock() -> MockClock {
+    MockClock::new(Utc.with_ymd_and_hms(2023, 9, 1, 12, 0, 0).unwrap())
+}
+
+fn keys() -> Keys {
+    Keys::from_config(&AuthConfig {
+        jwt_secret: "c2VjcmV0c2VjcmV0c2VjcmV0c2VjcmV0".to_string(),
+        admin_key: "admin".to_string(),
+        ..Default::default()
+    })
+    .unwrap()
+}
+
+fn user() -> User {
+    User {
+        id: 7,
+        username: "chess".to_string(),
+        email: "chess@example.com".to_string(),
+        password_hash: String::new(),
+        sessions_revoked_at: None,
+    }
+}
+
+#[test]
+fn reset_link_is_valid_until_its_last_second() {
+    let clock = clock();
+    let mut resets = Resets::default();
+    resets.insert("uid".to_string(), 7, HOUR, clock.now());
+
+    clock.advance(Duration::minutes(60));
+    assert_eq!(resets.user_for("uid", clock.now()), Ok(7));
+
+    clock.advance(Duration::seconds(1));
+    assert_eq!(
+        resets.user_for("uid", clock.now()),
+        Err(ResetError::Expired)
+    );
+    // expired links are forgotten
+    assert_eq!(
+        resets.user_for("uid", clock.now()),
+        Err(ResetError::Invalid)
+    );
+}
+
+#[test]
+fn unknown_reset_link_is_invalid() {
+    let mut resets = Resets::default();
+    assert_eq!(
+        resets.user_for("missing", clock().now()),
+        Err(ResetError::Invalid)
+    );
+}
+
+#[test]
+fn reset_link_survives_the_clock_going_backwards() {
+    let clock = clock();
+    let mut resets = Resets::default();
+    resets.insert("uid".to_string(), 7, HOUR, clock.now());
+
+    clock.advance(Duration::days(-1));
+    assert_eq!(resets.user_for("uid", clock.now()), Ok(7));
+}
+
+#[test]
+fn onboarding_link_lasts_seven_days() {
+    let clock = clock();
+    let mut resets = Resets::default();
+    resets.insert("uid".to_string(), 7, ONBOARDING_ALLOWED_TIME, clock.now());
+
+    clock.advance(Duration::days(6) + Duration::hours(23));
+    assert_eq!(resets.user_for("uid", clock.now()), Ok(7));
+
+    clock.advance(Duration::hours(1));
+    assert_eq!(resets.user_for("uid", clock.now()), Ok(7));
+
+    clock.advance(Duration::seconds(1));
+    assert_eq!(
+        resets.user_for("uid", clock.now()),
+        Err(ResetError::Expired)
+    );
+}
+
+#[test]
+fn clean_only_drops_expired_links() {
+    let clock = clock();
+    let mut resets = Resets::default();
+    resets.insert("short".to_string(), 1, HOUR, clock.now());
+    resets.insert("long".to_string(), 2, ONBOARDING_ALLOWED_TIME, clock.now());
+
+    clock.advance(Duration::hours(2));
+    resets.clean(clock.now());
+
+    assert_eq!(
+        resets.user_for("short", clock.now()),
+        Err(ResetError::Invalid)
+    );
+    assert_eq!(resets.user_for("long", clock.now()), Ok(2));
+}
+
+#[test]
+fn jwt_expires_after_its_lifetime() {
+    let (clock, keys) = (clock(), keys());
+    let token = auth::generate_jwt(&keys, &clock, &user(), vec![3], HOUR).unwrap();
+
+    let claims = auth::validate_jwt(&keys, &clock, &token).unwrap();
+    assert_eq!(claims.username, "chess");
+    assert_eq!(claims.user_id, 7);
+    assert_eq!(claims.clubs, vec![3]);
+
+    clock.advance(Duration::hours(1));
+    assert!(auth::validate_jwt(&keys, &clock, &token).is_ok());
+
+    clock.advance(Duration::seconds(1));
+    assert!(auth::validate_jwt(&keys, &clock, &token).is_err());
+}
+
+#[test]
+fn jwt_issued_by_a_mock_clock_in_the_past_is_expired_now() {
+    let keys = keys();
+    let past = MockClock::new(Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap());
+    let token = auth::generate_jwt(&keys, &past, &user(), vec![3], HOUR).unwrap();
+
+    assert!(auth::validate_jwt(&keys, &past, &token).is_ok());
+    assert!(auth::validate_jwt(&keys, &clock(), &token).is_err());
+}
+
+#[test]
+fn tampered_jwt_is_rejected() {
+    let (clock, keys) = (clock(), keys());
+    let mut token = auth::generate_jwt(&keys, &clock, &user(), vec![3], HOUR).unwrap();
+    token.push('x');
+
+    assert!(auth::validate_jwt(&keys, &clock, &token).is_err());
+}
+
+#[test]
+fn retired_jwt_key_verifies_until_its_grace_period_is_over() {
+    let clock = clock();
+    let keys = Keys::from_config(&AuthConfig {
+        jwt_keys: vec![
+            JwtKeyConfig {
+                kid: "old".to_string(),
+                algorithm: Default::default(),
+                secret: "b2xkc2VjcmV0b2xkc2VjcmV0b2xkc2VjcmV0".to_string(),
+                private_key: String::new(),
+                private_key_file: String::new(),
+                retired_at: Some(clock.now() + Duration::hours(1)),
+            },
+            JwtKeyConfig {
+                kid: "new".to_string(),
+                algorithm: Default::default(),
+                secret: "bmV3c2VjcmV0bmV3c2VjcmV0bmV3c2VjcmV0".to_string(),
+                private_key: String::new(),
+                private_key_file: String::new(),
+                retired_at: None,
+            },
+        ],
+        key_grace_period: 24 * 60 * 60,
+        ..Default::default()
+    })
+    .unwrap();
+
+    let old = auth::generate_jwt(&keys, &clock, &user(), vec![3], 48 * HOUR).unwrap();
+    assert_eq!(keys.signing_key(clock.now()).unwrap().kid, "old");
+
+    clock.advance(Duration::hours(1));
+    assert_eq!(keys.signing_key(clock.now()).unwrap().kid, "new");
+    assert!(auth::validate_jwt(&keys, &clock, &old).is_ok());
+
+    clock.advance(Duration::hours(24));
+    assert!(auth::validate_jwt(&keys, &clock, &old).is_err());
+}
@@ -0,0 +1,115 @@
+//! Archiving, soft deleting and restoring clubs.
+
+mod common;
+
+use axum::http::{Method, StatusCode};
+use cca_club_hub::models::Club;
+use common::TestApp;
+use serde_json::{json, Value};
+
+async fn listed(app: &TestApp, token: Option<&str>, query: &str, club: &Club) -> Option<Value> {
+    let uri = format!("/api/club/list{query}");
+    let (status, body) = match token {
+        Some(token) => app.request_with_token(token, Method::GET, &uri, None).await,
+        None => app.request(Method::GET, &uri, None).await,
+    };
+    assert_eq!(status, StatusCode::OK);
+    body.as_array()
+        .unwrap()
+        .iter()
+        .find(|listed| listed["id"] == club.username)
+        .cloned()
+}
+
+async fn admin(app: &TestApp, method: Method, club: &Club, action: &str) -> StatusCode {
+    let uri = format!("/api/admin/clubs/{}{action}", club.username);
+    app.request_with_token("admin", method, &uri, None).await.0
+}
+
+async fn login(app: &TestApp, username: &str) -> StatusCode {
+    app.request(
+        Method::POST,
+        "/api/auth/login",
+        Some(json!({ "username": username, "password": "correct horse" })),
+    )
+    .await
+    .0
+}
+
+#[tokio::test]
+async fn archived_clubs_are_hidden_and_locked() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let (club, president) = app.club("correct horse").await;
+    assert_eq!(
+        listed(&app, None, "", &club).await.unwrap()["status"],
+        "active"
+    );
+
+    assert_eq!(
+        admin(&app, Method::POST, &club, "/archive").await,
+        StatusCode::OK
+    );
+    assert!(listed(&app, None, "", &club).await.is_none());
+    let info = format!("/api/club/info/{}", club.username);
+    let (status, _) = app.request(Method::GET, &info, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // only admins can ask for every club
+    let (status, _) = app
+        .request(Method::GET, "/api/club/list?all=true", None)
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let archived = listed(&app, Some("admin"), "?all=true", &club).await;
+    assert_eq!(archived.unwrap()["status"], "archived");
+
+    assert_eq!(
+        login(&app, &president.username).await,
+        StatusCode::FORBIDDEN
+    );
+    let (status, _) = app
+        .request_as(
+            &president,
+            Method::GET,
+            &format!("/api/officers/{}", club.username),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    assert_eq!(
+        admin(&app, Method::POST, &club, "/restore").await,
+        StatusCode::OK
+    );
+    assert_eq!(login(&app, &president.username).await, StatusCode::OK);
+    let (status, _) = app.request(Method::GET, &info, None).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn deleted_clubs_can_be_restored() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let (club, _) = app.club("correct horse").await;
+
+    assert_eq!(admin(&app, Method::DELETE, &club, "").await, StatusCode::OK);
+    assert_eq!(
+        admin(&app, Method::DELETE, &club, "").await,
+        StatusCode::CONFLICT
+    );
+    assert!(listed(&app, None, "", &club).await.is_none());
+    let deleted = listed(&app, Some("admin"), "?all=true", &club)
+        .await
+        .unwrap();
+    assert_eq!(deleted["status"], "active");
+    assert!(deleted["deletedAt"].is_string());
+
+    assert_eq!(
+        admin(&app, Method::POST, &club, "/restore").await,
+        StatusCode::OK
+    );
+    let restored = listed(&app, None, "", &club).await.unwrap();
+    assert_eq!(restored["deletedAt"], Value::Null);
+}
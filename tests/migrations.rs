@@ -0,0 +1,63 @@
+//! Migrating databases that already hold clubs.
+
+use cca_club_hub::{
+    api::reregistration::SchoolYear, config::AppConfig, migrations::MIGRATIONS, models::Club,
+    schema::clubs,
+};
+use chrono::Utc;
+use diesel::{migration::Migration, pg::PgConnection, sql_query, Connection, RunQueryDsl};
+use diesel_migrations::MigrationHarness;
+
+/// Creates an empty database next to `TEST_DATABASE_URL`, runs `f` against its url and
+/// drops it again.
+fn with_scratch_database(f: impl FnOnce(&str)) {
+    let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+        eprintln!("TEST_DATABASE_URL is not set, skipping");
+        return;
+    };
+    let name = format!("clubtest_{}", Utc::now().timestamp_nanos());
+    let admin = &mut PgConnection::establish(&url).unwrap();
+    sql_query(format!("CREATE DATABASE {name}"))
+        .execute(admin)
+        .unwrap();
+    let mut scratch = url::Url::parse(&url).unwrap();
+    scratch.set_path(&name);
+
+    f(scratch.as_str());
+
+    sql_query(format!("DROP DATABASE {name} WITH (FORCE)"))
+        .execute(admin)
+        .unwrap();
+}
+
+/// Applies the pending migrations that come before `version`.
+fn migrate_until(conn: &mut PgConnection, version: &str) {
+    loop {
+        let pending = conn.pending_migrations(MIGRATIONS).unwrap();
+        if pending[0].name().version().to_string() == version {
+            return;
+        }
+        conn.run_migration(&*pending[0]).unwrap();
+    }
+}
+
+#[test]
+fn clubs_from_before_reregistration_have_to_confirm() {
+    with_scratch_database(|url| {
+        let conn = &mut PgConnection::establish(url).unwrap();
+        migrate_until(conn, "20261019230000");
+        sql_query(
+            "INSERT INTO clubs (username, email, club_name, description, about, meet_time, \
+             profile_picture_url, banner_url, featured) \
+             VALUES ('chess', 'chess@example.com', 'Chess Club', '', '', '', '', '', false)",
+        )
+        .execute(conn)
+        .unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+
+        let club = clubs::table.first::<Club>(conn).unwrap();
+        let year = SchoolYear::current(&AppConfig::default().reregistration, Utc::now());
+        assert!(club.created_at < year.start);
+        assert!(!year.is_confirmed(&club));
+    });
+}
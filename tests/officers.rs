@@ -0,0 +1,320 @@
+//! Per-person logins and club officer roles.
+
+mod common;
+
+use axum::http::{Method, StatusCode};
+use cca_club_hub::{
+    auth,
+    models::{ClubOfficer, Role, User},
+    schema::club_officers,
+};
+use common::TestApp;
+use diesel_async::RunQueryDsl;
+use serde_json::{json, Value};
+
+fn club_info() -> Value {
+    json!({
+        "clubName": "Renamed Club",
+        "description": "a description",
+        "about": "about us",
+        "meetTime": "Mondays",
+        "categories": [],
+        "socials": {},
+    })
+}
+
+#[tokio::test]
+async fn login_tokens_list_the_clubs_a_user_manages() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let (club, president) = app.club("correct horse").await;
+
+    let (status, body) = app
+        .request(
+            Method::POST,
+            "/api/auth/login",
+            Some(json!({ "username": president.username, "password": "correct horse" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let claims = auth::validate_jwt(
+        &app.state.keys,
+        app.state.clock.as_ref(),
+        body["token"].as_str().unwrap(),
+    )
+    .unwrap_or_else(|_| panic!("the token should be valid"));
+    assert_eq!(claims.user_id, president.id);
+    assert_eq!(claims.clubs, vec![club.id]);
+}
+
+#[tokio::test]
+async fn only_officers_can_edit_a_club() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let (club, president) = app.club("correct horse").await;
+    let (_, other_president) = app.club("correct horse").await;
+    let editor = app.user_without_clubs("correct horse").await;
+    let uri = format!("/api/edit/{}/info", club.username);
+
+    let (status, _) = app
+        .request_as(&other_president, Method::POST, &uri, Some(club_info()))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = app
+        .request_as(&editor, Method::POST, &uri, Some(club_info()))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let conn = &mut app.state.pool.get().await.unwrap();
+    diesel::insert_into(club_officers::table)
+        .values(ClubOfficer {
+            club_id: club.id,
+            user_id: editor.id,
+            role: Role::Editor,
+        })
+        .execute(conn)
+        .await
+        .unwrap();
+
+    for user in [&president, &editor] {
+        let (status, _) = app
+            .request_as(user, Method::POST, &uri, Some(club_info()))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    let (status, _) = app
+        .request_as(
+            &president,
+            Method::POST,
+            "/api/edit/no-such-club/info",
+            Some(club_info()),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+/// The token in the link of the last email sent.
+fn last_invitation_token(app: &TestApp) -> String {
+    let emails = app.mailer.0.lock().unwrap();
+    let email = String::from_utf8(emails.last().unwrap().formatted()).unwrap();
+    let start = email.find("/invitation/").unwrap() + "/invitation/".len();
+    email[start..]
+        .chars()
+        .take_while(|c| !c.is_whitespace())
+        .collect()
+}
+
+/// Invites `email` as an editor, waiting for the invitation email if it is sent.
+async fn invite(app: &TestApp, president: &User, club: &str, email: &str) -> (StatusCode, Value) {
+    let sent = app.mailer.count();
+    let response = app
+        .request_as(
+            president,
+            Method::POST,
+            &format!("/api/officers/{club}/invitations"),
+            Some(json!({ "email": email, "role": "editor" })),
+        )
+        .await;
+    if response.0 == StatusCode::OK {
+        app.mailer.wait_for(sent + 1).await;
+    }
+    response
+}
+
+#[tokio::test]
+async fn invitations_create_accounts_once() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let (club, president) = app.club("correct horse").await;
+    let (status, body) = invite(&app, &president, &club.username, "new@example.com").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["expired"], false);
+    let token = last_invitation_token(&app);
+
+    let (status, body) = app
+        .request(Method::GET, &format!("/api/invitations/{token}"), None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["club"], club.username.as_str());
+    assert_eq!(body["role"], "editor");
+
+    let username = format!("new-{}", club.username);
+    let register = json!({ "username": username, "password": "a brand new one" });
+    let (status, body) = app
+        .request(
+            Method::POST,
+            &format!("/api/invitations/{token}/register"),
+            Some(register.clone()),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let claims = auth::validate_jwt(
+        &app.state.keys,
+        app.state.clock.as_ref(),
+        body["token"].as_str().unwrap(),
+    )
+    .unwrap_or_else(|_| panic!("the token should be valid"));
+    assert_eq!(claims.clubs, vec![club.id]);
+
+    let (status, _) = app
+        .request(
+            Method::POST,
+            &format!("/api/invitations/{token}/register"),
+            Some(register),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, body) = app
+        .request_as(
+            &president,
+            Method::GET,
+            &format!("/api/officers/{}", club.username),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let new_officer = body
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|officer| officer["username"] == username.as_str())
+        .unwrap();
+    assert_eq!(new_officer["email"], "new@example.com");
+    assert_eq!(new_officer["role"], "editor");
+}
+
+#[tokio::test]
+async fn presidents_manage_invitations_and_officers() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let (club, president) = app.club("correct horse").await;
+    let user = app.user_without_clubs("correct horse").await;
+    let officers = format!("/api/officers/{}", club.username);
+
+    let (status, body) = invite(&app, &president, &club.username, &user.email).await;
+    assert_eq!(status, StatusCode::OK);
+    let id = body["id"].as_i64().unwrap();
+    let old_token = last_invitation_token(&app);
+    let (status, _) = invite(&app, &president, &club.username, &user.email).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    // resending replaces the link
+    let sent = app.mailer.count();
+    let (status, _) = app
+        .request_as(
+            &president,
+            Method::POST,
+            &format!("{officers}/invitations/{id}/resend"),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    app.mailer.wait_for(sent + 1).await;
+    let token = last_invitation_token(&app);
+    assert_ne!(token, old_token);
+    let (status, _) = app
+        .request_as(
+            &user,
+            Method::POST,
+            &format!("/api/invitations/{old_token}/accept"),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = app
+        .request_as(
+            &user,
+            Method::POST,
+            &format!("/api/invitations/{token}/accept"),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // editors can't invite or remove anyone
+    let (status, _) = invite(&app, &user, &club.username, "someone@example.com").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = app
+        .request_as(
+            &user,
+            Method::DELETE,
+            &format!("{officers}/officers/{}", president.username),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = app
+        .request_as(
+            &president,
+            Method::DELETE,
+            &format!("{officers}/officers/{}", president.username),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = app
+        .request_as(
+            &president,
+            Method::DELETE,
+            &format!("{officers}/officers/{}", user.username),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app.request_as(&user, Method::GET, &officers, None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn revoked_invitations_stop_working() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let (club, president) = app.club("correct horse").await;
+    let (_, body) = invite(&app, &president, &club.username, "new@example.com").await;
+    let id = body["id"].as_i64().unwrap();
+    let token = last_invitation_token(&app);
+
+    let invitations = format!("/api/officers/{}/invitations", club.username);
+    let (_, body) = app
+        .request_as(&president, Method::GET, &invitations, None)
+        .await;
+    assert_eq!(body[0]["email"], "new@example.com");
+    assert_eq!(body[0]["invitedBy"], president.username.as_str());
+
+    let (status, _) = app
+        .request_as(
+            &president,
+            Method::DELETE,
+            &format!("{invitations}/{id}"),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = app
+        .request(Method::GET, &format!("/api/invitations/{token}"), None)
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (_, body) = app
+        .request_as(&president, Method::GET, &invitations, None)
+        .await;
+    assert_eq!(body, json!([]));
+}
+
+#[test]
+fn roles_are_ordered_by_what_they_can_do() {
+    assert!(Role::Editor < Role::Officer && Role::Officer < Role::President);
+    assert_eq!("officer".parse::<Role>(), Ok(Role::Officer));
+    assert_eq!(Role::President.to_string(), "president");
+    assert!("owner".parse::<Role>().is_err());
+}
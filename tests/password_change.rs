@@ -0,0 +1,87 @@
+//! Changing the password while logged in.
+
+mod common;
+
+use axum::http::{Method, StatusCode};
+use cca_club_hub::api::password;
+use serde_json::json;
+use std::time::Duration;
+
+#[tokio::test]
+async fn password_change_needs_the_current_password() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let user = app.user("correct horse").await;
+
+    let (status, _) = app
+        .request_as(
+            &user,
+            Method::POST,
+            "/api/auth/password",
+            Some(json!({ "currentPassword": "battery staple", "newPassword": "a brand new one" })),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn password_change_rejects_short_passwords() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let user = app.user("correct horse").await;
+
+    let (status, body) = app
+        .request_as(
+            &user,
+            Method::POST,
+            "/api/auth/password",
+            Some(json!({ "currentPassword": "correct horse", "newPassword": "short" })),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["errors"][0]["field"], "newPassword");
+}
+
+#[tokio::test]
+async fn password_change_notifies_the_user_and_invalidates_reset_links() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let user = app.user("correct horse").await;
+    let link = password::issue_reset_link(&app.state, user.id, Duration::from_secs(60 * 60))
+        .await
+        .unwrap();
+    let uid = link.rsplit('/').next().unwrap();
+    let sent = app.mailer.count();
+
+    let (status, _) = app
+        .request_as(
+            &user,
+            Method::POST,
+            "/api/auth/password",
+            Some(json!({ "currentPassword": "correct horse", "newPassword": "a brand new one" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = app
+        .request(Method::GET, &format!("/api/password/check/{uid}"), None)
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    app.mailer.wait_for(sent + 1).await;
+    assert_eq!(app.mailer.count(), sent + 1);
+
+    let (status, _) = app
+        .request(
+            Method::POST,
+            "/api/auth/login",
+            Some(json!({ "username": user.username, "password": "a brand new one" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+}
@@ -0,0 +1,82 @@
+//! Password hashes made with older settings are upgraded on login.
+
+mod common;
+
+use axum::http::{Method, StatusCode};
+use cca_club_hub::{
+    auth::{HashSettings, Hasher},
+    config::PasswordHashConfig,
+    schema::users,
+};
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
+use serde_json::json;
+use std::sync::Arc;
+
+fn config(memory_cost: u32, time_cost: u32, parallelism: u32) -> PasswordHashConfig {
+    PasswordHashConfig {
+        memory_cost,
+        time_cost,
+        parallelism,
+        ..PasswordHashConfig::default()
+    }
+}
+
+#[test]
+fn current_hashes_are_kept() {
+    let hasher = Hasher::from_config(&config(8192, 3, 2)).unwrap();
+    let hash = hasher.hash("correct horse").unwrap();
+    assert!(!hasher.needs_rehash(&hash));
+
+    let weaker = Hasher::from_config(&config(8192, 2, 2)).unwrap();
+    assert!(hasher.needs_rehash(&weaker.hash("correct horse").unwrap()));
+    assert!(hasher.needs_rehash("not a phc string"));
+}
+
+#[tokio::test]
+async fn logging_in_rehashes_with_the_configured_settings() {
+    let Some(mut app) = common::test_app().await else {
+        return;
+    };
+    let (_, president) = app.club("correct horse").await;
+    let weak = Hasher::from_config(&config(1024, 1, 1)).unwrap();
+    let conn = &mut app.state.pool.get().await.unwrap();
+    diesel::update(users::table.find(president.id))
+        .set(users::password_hash.eq(weak.hash("correct horse").unwrap()))
+        .execute(conn)
+        .await
+        .unwrap();
+    let configured = config(8192, 3, 2);
+    app.state.hasher = Arc::new(Hasher::from_config(&configured).unwrap());
+
+    let (status, _) = app
+        .request(
+            Method::POST,
+            "/api/auth/login",
+            Some(json!({ "username": president.username, "password": "correct horse" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let stored: String = users::table
+        .find(president.id)
+        .select(users::password_hash)
+        .first(conn)
+        .await
+        .unwrap();
+    let settings = HashSettings::of(&stored).unwrap();
+    assert_eq!(
+        (
+            settings.memory_cost,
+            settings.time_cost,
+            settings.parallelism
+        ),
+        (
+            configured.memory_cost,
+            configured.time_cost,
+            configured.parallelism
+        )
+    );
+    assert!(stored.contains("$m=8192,t=3,p=2$"));
+    assert!(!app.state.hasher.needs_rehash(&stored));
+}
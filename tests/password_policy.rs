@@ -0,0 +1,86 @@
+use cca_club_hub::{config::PasswordPolicyConfig, password_policy};
+
+const YEAR: i32 = 2026;
+
+fn problems(password: &str) -> Vec<String> {
+    password_policy::problems(
+        &PasswordPolicyConfig::default(),
+        password,
+        &["chess", "Chess Club"],
+        YEAR,
+    )
+}
+
+#[test]
+fn common_passwords_are_weak() {
+    for password in ["password", "123456", "qwertyuiop", "P@ssw0rd", "aaaaaaaa"] {
+        let strength = password_policy::strength(password, &[], YEAR);
+        assert!(strength.score <= 1, "{password}: {strength:?}");
+        assert!(strength.warning.is_some(), "{password}");
+    }
+}
+
+#[test]
+fn patterns_are_weak() {
+    for password in ["abcdefghij", "1qaz2wsx3edc", "abcabcabcabc", "Summer2024!"] {
+        let strength = password_policy::strength(password, &[], YEAR);
+        assert!(strength.score <= 2, "{password}: {strength:?}");
+    }
+}
+
+#[test]
+fn long_unpatterned_passwords_are_strong() {
+    for password in [
+        "correct horse battery staple",
+        "Tq8#vLw2!pZr",
+        "glimmer-otter-basalt",
+    ] {
+        let strength = password_policy::strength(password, &[], YEAR);
+        assert_eq!(strength.score, 4, "{password}: {strength:?}");
+    }
+}
+
+#[test]
+fn policy_accepts_strong_passwords() {
+    assert_eq!(problems("glimmer-otter-basalt"), Vec::<String>::new());
+}
+
+#[test]
+fn policy_rejects_short_and_empty_passwords() {
+    assert!(problems("").iter().any(|p| p.contains("at least 8")));
+    assert!(problems("Tq8#vLw").iter().any(|p| p.contains("at least 8")));
+}
+
+#[test]
+fn policy_rejects_the_club_username_and_name() {
+    for password in ["chess-rocks-hard-2026", "ch3ssclub!!!", "xx CHESS CLUB xx"] {
+        assert!(
+            problems(password).iter().any(|p| p.contains("username")),
+            "{password}"
+        );
+    }
+}
+
+#[test]
+fn policy_rejects_breached_passwords() {
+    for password in ["password123", "iloveyou", "Trustno1"] {
+        assert!(
+            problems(password).iter().any(|p| p.contains("breaches")),
+            "{password}"
+        );
+    }
+}
+
+#[test]
+fn policy_explains_weak_passwords() {
+    let problems = problems("12345678910");
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].contains("too easy to guess"), "{problems:?}");
+}
+
+#[test]
+fn years_near_the_current_one_are_weaker() {
+    let near = password_policy::strength("1999", &[], 2001);
+    let far = password_policy::strength("1999", &[], 2039);
+    assert!(near.guesses_log10 < far.guesses_log10, "{near:?} {far:?}");
+}
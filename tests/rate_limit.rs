@@ -0,0 +1,128 @@
+//! Throttled logins, account lockout and admin key attempts.
+
+mod common;
+
+use axum::http::{header::RETRY_AFTER, Method, StatusCode};
+use cca_club_hub::{
+    clock::MockClock,
+    rate_limit::{PgRateLimits, RateLimitStore},
+};
+use chrono::{Duration, Utc};
+use common::TestApp;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+/// Moves the clock of `app` to one only the test controls.
+fn mock_clock(app: &mut TestApp) -> Arc<MockClock> {
+    let clock = Arc::new(MockClock::new(Utc::now()));
+    app.state.clock = clock.clone();
+    clock
+}
+
+async fn login(app: &TestApp, username: &str, password: &str) -> (StatusCode, Option<u64>, Value) {
+    let (status, headers, body) = app
+        .response(
+            Method::POST,
+            "/api/auth/login",
+            None,
+            Some(json!({ "username": username, "password": password })),
+        )
+        .await;
+    let retry_after = headers
+        .get(RETRY_AFTER)
+        .map(|value| value.to_str().unwrap().parse().unwrap());
+    (status, retry_after, body)
+}
+
+#[tokio::test]
+async fn failed_logins_lock_the_account_until_the_lockout_is_over() {
+    let Some(mut app) = common::test_app().await else {
+        return;
+    };
+    let clock = mock_clock(&mut app);
+    let (_, president) = app.club("correct horse").await;
+    let limits = &app.state.config.rate_limit;
+    let (max_failed_logins, lockout) = (limits.max_failed_logins, limits.lockout);
+
+    for _ in 0..max_failed_logins {
+        let (status, _, _) = login(&app, &president.username, "wrong").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+    // even the right password is turned away while locked
+    let (status, retry_after, _) = login(&app, &president.username, "correct horse").await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(retry_after, Some(lockout));
+
+    clock.advance(Duration::seconds(lockout as i64 - 1));
+    let (status, retry_after, _) = login(&app, &president.username, "correct horse").await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(retry_after, Some(1));
+
+    clock.advance(Duration::seconds(1));
+    let (status, _, body) = login(&app, &president.username, "correct horse").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["token"].is_string());
+}
+
+#[tokio::test]
+async fn failed_logins_are_forgotten_after_their_window() {
+    let Some(mut app) = common::test_app().await else {
+        return;
+    };
+    let clock = mock_clock(&mut app);
+    let (_, president) = app.club("correct horse").await;
+    let limits = &app.state.config.rate_limit;
+    let (max_failed_logins, window) = (limits.max_failed_logins, limits.failed_login_window);
+
+    for _ in 1..max_failed_logins {
+        login(&app, &president.username, "wrong").await;
+    }
+    clock.advance(Duration::seconds(window as i64));
+    let (status, _, _) = login(&app, &president.username, "wrong").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _, _) = login(&app, &president.username, "correct horse").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn only_wrong_admin_keys_are_counted() {
+    let Some(mut app) = common::test_app().await else {
+        return;
+    };
+    let clock = mock_clock(&mut app);
+    let limit = app.state.config.rate_limit.admin_key;
+    let audit = |token: &'static str| {
+        app.response(Method::GET, "/api/admin/audit?limit=1", Some(token), None)
+    };
+
+    for _ in 0..limit.requests * 2 {
+        assert_eq!(audit("admin").await.0, StatusCode::OK);
+    }
+    for _ in 0..limit.requests {
+        assert_eq!(audit("not the key").await.0, StatusCode::UNAUTHORIZED);
+    }
+    let (status, headers, _) = audit("not the key").await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(headers[RETRY_AFTER], limit.window.to_string());
+    // once the limit is reached, the right key has to wait as well
+    assert_eq!(audit("admin").await.0, StatusCode::TOO_MANY_REQUESTS);
+
+    clock.advance(Duration::seconds(limit.window as i64));
+    assert_eq!(audit("admin").await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn long_keys_are_stored() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let limits = PgRateLimits(app.state.pool.clone());
+    let key = format!("login:{}", "x".repeat(1000));
+
+    let window = limits
+        .hit(&key, std::time::Duration::from_secs(60), Utc::now())
+        .await
+        .unwrap();
+    assert_eq!(window.hits, 1);
+    limits.clear(&key).await.unwrap();
+}
@@ -0,0 +1,187 @@
+//! Yearly profile confirmation, its reminders and deadline.
+
+mod common;
+
+use axum::http::{Method, StatusCode};
+use cca_club_hub::{
+    api::reregistration::{self, SchoolYear},
+    models::{Club, ClubStatus},
+    schema::clubs,
+};
+use chrono::{Duration, TimeZone, Utc};
+use common::TestApp;
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
+use serde_json::Value;
+
+/// A club that was registered before the current school year started.
+async fn old_club(app: &TestApp) -> Club {
+    let (club, _) = app.club("correct horse").await;
+    let conn = &mut app.state.pool.get().await.unwrap();
+    diesel::update(clubs::table.find(club.id))
+        .set(clubs::created_at.eq(Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap()))
+        .get_result(conn)
+        .await
+        .unwrap()
+}
+
+/// The token in the confirmation link emailed to `club`.
+fn confirmation_token(app: &TestApp, club: &Club) -> String {
+    let emails = app.mailer.0.lock().unwrap();
+    let email = emails
+        .iter()
+        .map(|email| String::from_utf8(email.formatted()).unwrap())
+        .find(|email| email.contains(&club.email) && email.contains("/confirm/"))
+        .unwrap();
+    let start = email.find("/confirm/").unwrap() + "/confirm/".len();
+    email[start..]
+        .chars()
+        .take_while(|c| !c.is_whitespace())
+        .collect()
+}
+
+async fn listed(app: &TestApp, club: &Club) -> bool {
+    let info = format!("/api/club/info/{}", club.username);
+    app.request(Method::GET, &info, None).await.0 == StatusCode::OK
+}
+
+#[tokio::test]
+async fn unconfirmed_clubs_are_reminded_then_made_inactive() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let (confirming, forgetful) = (old_club(&app).await, old_club(&app).await);
+    let (new, _) = app.club("correct horse").await;
+    let now = Utc::now();
+    let current = SchoolYear::current(&app.state.config.reregistration, now);
+    let before_deadline = SchoolYear {
+        deadline: now + Duration::days(1),
+        ..current
+    };
+
+    let run = reregistration::run(&app.state, &before_deadline)
+        .await
+        .unwrap();
+    assert!(run.reminded.contains(&confirming.username));
+    assert!(run.reminded.contains(&forgetful.username));
+    assert!(!run.reminded.contains(&new.username));
+    // not again until the reminder interval has passed
+    let run = reregistration::run(&app.state, &before_deadline)
+        .await
+        .unwrap();
+    assert!(!run.reminded.contains(&confirming.username));
+
+    let link = format!("/api/confirm/{}", confirmation_token(&app, &confirming));
+    let (status, body) = app.request(Method::GET, &link, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["club"], confirming.username);
+    assert_eq!(body["confirmedAt"], Value::Null);
+    let (status, body) = app.request(Method::POST, &link, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["confirmedAt"].is_string());
+    let (status, _) = app
+        .request(Method::GET, "/api/confirm/not-a-token", None)
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let after_deadline = SchoolYear {
+        deadline: now - Duration::seconds(1),
+        ..current
+    };
+    let run = reregistration::run(&app.state, &after_deadline)
+        .await
+        .unwrap();
+    assert!(run.deactivated.contains(&forgetful.username));
+    assert!(!run.deactivated.contains(&confirming.username));
+    assert!(!listed(&app, &forgetful).await);
+    assert!(listed(&app, &confirming).await);
+
+    let (status, body) = app
+        .request_with_token("admin", Method::GET, "/api/admin/reregistration", None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let report = body["clubs"].as_array().unwrap();
+    let find = |club: &Club| {
+        report
+            .iter()
+            .find(|entry| entry["club"] == club.username)
+            .unwrap()
+    };
+    assert_eq!(find(&forgetful)["confirmed"], false);
+    assert_eq!(find(&forgetful)["status"], "inactive");
+    assert_eq!(find(&forgetful)["remindersSent"], 1);
+    assert_eq!(find(&confirming)["confirmed"], true);
+    assert_eq!(find(&new)["remindersSent"], 0);
+
+    // confirming late brings the club back
+    let link = format!("/api/confirm/{}", confirmation_token(&app, &forgetful));
+    let (status, body) = app.request(Method::POST, &link, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "active");
+    assert!(listed(&app, &forgetful).await);
+
+    let (status, body) = app
+        .request_with_token(
+            "admin",
+            Method::GET,
+            &format!("/api/admin/audit?club={}", forgetful.username),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let actions: Vec<_> = body
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| {
+            (
+                entry["actor"].as_str().unwrap(),
+                entry["action"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        actions,
+        [
+            (forgetful.email.as_str(), "club.confirm"),
+            ("system", "club.deactivate")
+        ]
+    );
+}
+
+#[tokio::test]
+async fn confirming_keeps_an_inactive_status_set_by_an_admin() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let club = old_club(&app).await;
+    let conn = &mut app.state.pool.get().await.unwrap();
+    diesel::update(clubs::table.find(club.id))
+        .set(clubs::status.eq(ClubStatus::Inactive))
+        .execute(conn)
+        .await
+        .unwrap();
+
+    let now = Utc::now();
+    let before_deadline = SchoolYear {
+        deadline: now + Duration::days(1),
+        ..SchoolYear::current(&app.state.config.reregistration, now)
+    };
+    let run = reregistration::run(&app.state, &before_deadline)
+        .await
+        .unwrap();
+    assert!(run.reminded.contains(&club.username));
+
+    let link = format!("/api/confirm/{}", confirmation_token(&app, &club));
+    let (status, body) = app.request(Method::POST, &link, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "inactive");
+    assert!(body["confirmedAt"].is_string());
+
+    let confirmed = clubs::table
+        .find(club.id)
+        .first::<Club>(conn)
+        .await
+        .unwrap();
+    assert_eq!(confirmed.updated_at, club.updated_at);
+}
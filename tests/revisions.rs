@@ -0,0 +1,210 @@
+//! Profile revision history.
+
+mod common;
+
+use axum::http::{Method, StatusCode};
+use cca_club_hub::models::{Club, User};
+use common::TestApp;
+use serde_json::{json, Value};
+
+async fn edit(app: &TestApp, user: &User, club: &Club, about: &str, website: Option<&str>) {
+    let (status, body) = app
+        .request_as(
+            user,
+            Method::POST,
+            &format!("/api/edit/{}/info", club.username),
+            Some(json!({
+                "clubName": club.club_name,
+                "description": club.description,
+                "about": about,
+                "meetTime": club.meet_time,
+                "categories": [],
+                "socials": { "website": website },
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["published"], true);
+}
+
+async fn revisions(app: &TestApp, user: &User, club: &Club) -> Vec<Value> {
+    let (status, body) = app
+        .request_as(
+            user,
+            Method::GET,
+            &format!("/api/edit/{}/revisions", club.username),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    body.as_array().unwrap().clone()
+}
+
+#[tokio::test]
+async fn edits_are_recorded_and_compared() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let (club, president) = app.club("correct horse").await;
+    edit(&app, &president, &club, "first", None).await;
+    edit(
+        &app,
+        &president,
+        &club,
+        "second",
+        Some("https://example.com"),
+    )
+    .await;
+
+    let revisions = revisions(&app, &president, &club).await;
+    assert_eq!(revisions.len(), 3);
+    assert_eq!(revisions[0]["about"], "second");
+    assert_eq!(revisions[0]["editedBy"], president.username.as_str());
+    // the profile as it was before the first edit
+    assert_eq!(revisions[2]["about"], "");
+    assert_eq!(revisions[2]["editedBy"], Value::Null);
+
+    let (status, body) = app
+        .request_as(
+            &president,
+            Method::GET,
+            &format!(
+                "/api/edit/{}/revisions/diff?from={}&to={}",
+                club.username, revisions[1]["id"], revisions[0]["id"]
+            ),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body,
+        json!([
+            { "field": "about", "from": "first", "to": "second" },
+            { "field": "website", "from": null, "to": "https://example.com" },
+        ])
+    );
+
+    let (_, other_president) = app.club("correct horse").await;
+    let (status, _) = app
+        .request_as(
+            &other_president,
+            Method::GET,
+            &format!("/api/edit/{}/revisions", club.username),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn reverts_are_new_revisions() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let (club, president) = app.club("correct horse").await;
+    let (other_club, other_president) = app.club("correct horse").await;
+    edit(&app, &president, &club, "first", None).await;
+    edit(
+        &app,
+        &president,
+        &club,
+        "second",
+        Some("https://example.com"),
+    )
+    .await;
+    edit(&app, &other_president, &other_club, "other", None).await;
+    let first = revisions(&app, &president, &club).await[1]["id"].clone();
+    let others = revisions(&app, &other_president, &other_club).await[0]["id"].clone();
+
+    let (status, _) = app
+        .request_as(
+            &president,
+            Method::POST,
+            &format!("/api/edit/{}/revisions/{others}/revert", club.username),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, body) = app
+        .request_as(
+            &president,
+            Method::POST,
+            &format!("/api/edit/{}/revisions/{first}/revert", club.username),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["published"], true);
+
+    let (_, profile) = app
+        .request(
+            Method::GET,
+            &format!("/api/club/info/{}", club.username),
+            None,
+        )
+        .await;
+    assert_eq!(profile["about"], "first");
+    assert_eq!(profile["socials"]["website"], Value::Null);
+
+    let revisions = revisions(&app, &president, &club).await;
+    assert_eq!(revisions.len(), 4);
+    assert_eq!(revisions[0]["revertedFrom"], first);
+    assert_eq!(revisions[0]["about"], "first");
+}
+
+#[tokio::test]
+async fn directory_imports_are_recorded() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let (club, president) = app.club("correct horse").await;
+    let import = |row: Value| {
+        let app = &app;
+        async move {
+            let (status, _) = app
+                .request_with_token(
+                    "admin",
+                    Method::POST,
+                    "/api/admin/directory/import?format=json",
+                    Some(json!([row])),
+                )
+                .await;
+            assert_eq!(status, StatusCode::OK);
+        }
+    };
+    let row = json!({ "username": club.username, "email": club.email, "name": club.club_name });
+
+    let mut imported = row.clone();
+    imported["about"] = json!("imported");
+    import(imported).await;
+    // only the profile has a history
+    let mut featured = row;
+    featured["featured"] = json!(true);
+    import(featured).await;
+
+    let history = revisions(&app, &president, &club).await;
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0]["about"], "imported");
+    assert_eq!(history[0]["editedBy"], Value::Null);
+    assert_eq!(history[1]["about"], "");
+
+    edit(&app, &president, &club, "edited", None).await;
+    let history = revisions(&app, &president, &club).await;
+    let (status, body) = app
+        .request_as(
+            &president,
+            Method::GET,
+            &format!(
+                "/api/edit/{}/revisions/diff?from={}&to={}",
+                club.username, history[1]["id"], history[0]["id"]
+            ),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body,
+        json!([{ "field": "about", "from": "imported", "to": "edited" }])
+    );
+}
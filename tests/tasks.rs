@@ -0,0 +1,115 @@
+//! The background task supervisor and the email outbox it runs.
+
+use cca_club_hub::{
+    email::{self, Outbox},
+    tasks::{self, Supervisor},
+};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Sets its flag when dropped, e.g. when the task holding it is aborted.
+struct DropFlag(Arc<AtomicBool>);
+
+impl Drop for DropFlag {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn panicking_jobs_are_restarted() {
+    let runs = Arc::new(AtomicUsize::new(0));
+    let mut supervisor = Supervisor::new();
+    let job_runs = runs.clone();
+    supervisor.spawn("flaky", move |mut shutdown| {
+        let runs = job_runs.clone();
+        async move {
+            if runs.fetch_add(1, Ordering::SeqCst) == 0 {
+                panic!("first run fails");
+            }
+            shutdown.cancelled().await;
+        }
+    });
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert_eq!(runs.load(Ordering::SeqCst), 1);
+    tokio::time::sleep(Duration::from_secs(10)).await;
+    assert_eq!(runs.load(Ordering::SeqCst), 2);
+
+    supervisor.shutdown(Duration::from_secs(1)).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn shutdown_cancels_jobs_and_aborts_stuck_ones() {
+    let mut supervisor = Supervisor::new();
+    let (ticks, stopped, aborted) = (
+        Arc::new(AtomicUsize::new(0)),
+        Arc::new(AtomicBool::new(false)),
+        Arc::new(AtomicBool::new(false)),
+    );
+
+    let (job_ticks, job_stopped) = (ticks.clone(), stopped.clone());
+    supervisor.spawn("ticker", move |shutdown| {
+        let (ticks, stopped) = (job_ticks.clone(), job_stopped.clone());
+        async move {
+            tasks::run_every(shutdown, Duration::from_secs(60), || {
+                ticks.fetch_add(1, Ordering::SeqCst);
+                async {}
+            })
+            .await;
+            stopped.store(true, Ordering::SeqCst);
+        }
+    });
+    let job_aborted = aborted.clone();
+    supervisor.spawn("stuck", move |_| {
+        let flag = DropFlag(job_aborted.clone());
+        async move {
+            let _flag = flag;
+            std::future::pending::<()>().await
+        }
+    });
+
+    tokio::time::sleep(Duration::from_secs(150)).await;
+    assert_eq!(ticks.load(Ordering::SeqCst), 3);
+    assert!(!aborted.load(Ordering::SeqCst));
+
+    supervisor.shutdown(Duration::from_secs(5)).await;
+    // the job runs in a task of its own, dropped the next time the runtime gets to it
+    tokio::task::yield_now().await;
+    assert!(stopped.load(Ordering::SeqCst));
+    assert!(aborted.load(Ordering::SeqCst));
+}
+
+#[tokio::test(start_paused = true)]
+async fn queued_emails_are_sent_before_shutdown_finishes() {
+    let outbox = Outbox::new();
+    let mut supervisor = Supervisor::new();
+    let queued = outbox.queued();
+    supervisor.spawn("email outbox", move |shutdown| {
+        email::send_queued(queued.clone(), shutdown)
+    });
+
+    let sent = Arc::new(AtomicUsize::new(0));
+    let send = |outbox: &Outbox| {
+        let sent = sent.clone();
+        outbox.send("test", async move {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            sent.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        });
+    };
+    send(&outbox);
+    send(&outbox);
+    supervisor.cancel();
+    // a request that is still finishing can queue more after shutdown started
+    send(&outbox);
+    drop(outbox);
+
+    supervisor.shutdown(Duration::from_secs(10)).await;
+    assert_eq!(sent.load(Ordering::SeqCst), 3);
+}
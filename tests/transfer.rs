@@ -0,0 +1,220 @@
+//! Handing a club over to a new president.
+
+mod common;
+
+use axum::http::{Method, StatusCode};
+use cca_club_hub::{api::transfer, auth, schema::clubs};
+use common::TestApp;
+use diesel::QueryDsl;
+use diesel_async::RunQueryDsl;
+use serde_json::json;
+use std::time::Duration;
+
+/// The token in the link of the email sent after the first `sent`, waiting for it since
+/// transfer emails are sent in the background.
+async fn transfer_token(app: &TestApp, sent: usize) -> String {
+    app.mailer.wait_for(sent + 1).await;
+    let emails = app.mailer.0.lock().unwrap();
+    let email = String::from_utf8(emails[sent].formatted()).unwrap();
+    let start = email.find("/transfer/").unwrap() + "/transfer/".len();
+    email[start..]
+        .chars()
+        .take_while(|c| !c.is_whitespace())
+        .collect()
+}
+
+#[tokio::test]
+async fn transfers_switch_the_president_and_contact_email() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let (club, president) = app.club("correct horse").await;
+    let old_token = auth::generate_jwt(
+        &app.state.keys,
+        app.state.clock.as_ref(),
+        &president,
+        vec![club.id],
+        Duration::from_secs(60 * 60),
+    )
+    .unwrap();
+
+    let sent = app.mailer.count();
+    let (status, body) = app
+        .request_as(
+            &president,
+            Method::POST,
+            &format!("/api/officers/{}/transfer", club.username),
+            Some(json!({ "email": "next@example.com" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["requestedBy"], president.username.as_str());
+    let token = transfer_token(&app, sent).await;
+
+    let (status, body) = app
+        .request(Method::GET, &format!("/api/transfers/{token}"), None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["newEmail"], "next@example.com");
+
+    let username = format!("next-{}", club.username);
+    let (status, body) = app
+        .request(
+            Method::POST,
+            &format!("/api/transfers/{token}/register"),
+            Some(json!({ "username": username, "password": "a brand new one" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let new_token = body["token"].as_str().unwrap().to_string();
+
+    let conn = &mut app.state.pool.get().await.unwrap();
+    let email: String = clubs::table
+        .find(club.id)
+        .select(clubs::email)
+        .first(conn)
+        .await
+        .unwrap();
+    assert_eq!(email, "next@example.com");
+
+    // the old president is logged out and no longer an officer
+    assert!(auth::validate_jwt(&app.state.keys, app.state.clock.as_ref(), &old_token).is_ok());
+    let (status, _) = app
+        .request_with_token(&old_token, Method::GET, "/api/auth/2fa/status", None)
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        auth::officer_clubs(conn, president.id).await.unwrap(),
+        Vec::<i32>::new()
+    );
+
+    let (status, body) = app
+        .request_with_token(
+            &new_token,
+            Method::GET,
+            &format!("/api/officers/{}/transfers", club.username),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body[0]["from"], president.username.as_str());
+    assert_eq!(body[0]["to"], username.as_str());
+    assert_eq!(body[0]["oldEmail"], club.email.as_str());
+    assert!(body[0]["completedAt"].is_string());
+
+    // the link only works once
+    let (status, _) = app
+        .request(Method::GET, &format!("/api/transfers/{token}"), None)
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn admins_can_transfer_to_an_existing_user() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let (club, president) = app.club("correct horse").await;
+    let user = app.user_without_clubs("correct horse").await;
+
+    let sent = app.mailer.count();
+    let nominated = transfer::nominate(&app.state, &club.username, user.email.clone(), None).await;
+    assert!(matches!(nominated, Ok(ref t) if t.requested_by.is_none()));
+    let cancelled_token = transfer_token(&app, sent).await;
+    let sent = app.mailer.count();
+    // nominating again replaces the pending transfer
+    assert!(
+        transfer::nominate(&app.state, &club.username, user.email.clone(), None)
+            .await
+            .is_ok()
+    );
+    let token = transfer_token(&app, sent).await;
+
+    let (status, _) = app
+        .request_as(
+            &user,
+            Method::POST,
+            &format!("/api/transfers/{cancelled_token}/accept"),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = app
+        .request_as(
+            &president,
+            Method::POST,
+            &format!("/api/transfers/{token}/accept"),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, _) = app
+        .request_as(
+            &user,
+            Method::POST,
+            &format!("/api/transfers/{token}/accept"),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = app
+        .request_as(
+            &user,
+            Method::GET,
+            &format!("/api/officers/{}", club.username),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body,
+        json!([{ "username": user.username, "email": user.email, "role": "president" }])
+    );
+}
+
+#[tokio::test]
+async fn only_presidents_start_and_cancel_transfers() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let (club, president) = app.club("correct horse").await;
+    let (_, other_president) = app.club("correct horse").await;
+    let transfer = format!("/api/officers/{}/transfer", club.username);
+
+    let (status, _) = app
+        .request_as(
+            &other_president,
+            Method::POST,
+            &transfer,
+            Some(json!({ "email": "next@example.com" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = app
+        .request_as(&president, Method::DELETE, &transfer, None)
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let sent = app.mailer.count();
+    let (status, _) = app
+        .request_as(
+            &president,
+            Method::POST,
+            &transfer,
+            Some(json!({ "email": "next@example.com" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let token = transfer_token(&app, sent).await;
+    let (status, _) = app
+        .request_as(&president, Method::DELETE, &transfer, None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = app
+        .request(Method::GET, &format!("/api/transfers/{token}"), None)
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,263 @@
+//! TOTP codes and two-factor logins.
+
+mod common;
+
+use axum::http::{Method, StatusCode};
+use cca_club_hub::{api::two_factor, models::User, totp};
+use common::TestApp;
+use serde_json::{json, Value};
+
+// the SHA1 secret from the test vectors in RFC 6238 appendix B
+const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+#[test]
+fn codes_match_rfc_6238() {
+    for (timestamp, expected) in [
+        (59, 287082),
+        (1111111109, 81804),
+        (1234567890, 5924),
+        (2000000000, 279037),
+    ] {
+        assert_eq!(
+            totp::code(RFC_SECRET, totp::step_at(timestamp)),
+            expected,
+            "at {timestamp}"
+        );
+    }
+}
+
+#[test]
+fn codes_only_work_once_and_around_now() {
+    let secret = totp::base32_encode(RFC_SECRET);
+    let step = totp::step_at(1111111109);
+
+    assert_eq!(totp::verify(&secret, "081 804", 1111111109, 0), Some(step));
+    // a step late still works, but not once that step has been used
+    assert_eq!(
+        totp::verify(&secret, "081804", 1111111109 + 30, 0),
+        Some(step)
+    );
+    assert_eq!(totp::verify(&secret, "081804", 1111111109, step), None);
+    assert_eq!(totp::verify(&secret, "081804", 1111111109 + 60, 0), None);
+    assert_eq!(totp::verify(&secret, "81804", 1111111109, 0), None);
+}
+
+#[test]
+fn base32_round_trips() {
+    // RFC 4648 section 10, without padding
+    assert_eq!(totp::base32_encode(b"foobar"), "MZXW6YTBOI");
+    assert_eq!(totp::base32_decode("mzxw6ytboi======").unwrap(), b"foobar");
+    assert_eq!(totp::base32_decode("MZXW6YTBOI1"), None);
+
+    let secret = totp::generate_secret();
+    assert_eq!(totp::base32_decode(&secret).unwrap().len(), 20);
+}
+
+fn current_code(secret: &str, steps_ahead: u64) -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let secret = totp::base32_decode(secret).unwrap();
+    format!(
+        "{:06}",
+        totp::code(&secret, totp::step_at(now) + steps_ahead)
+    )
+}
+
+/// Enrolls and confirms two-factor authentication, returning the secret and recovery codes.
+async fn enable(app: &TestApp, user: &User) -> (String, Vec<String>) {
+    let (status, body) = app
+        .request_as(
+            user,
+            Method::POST,
+            "/api/auth/2fa/enroll",
+            Some(json!({ "password": "correct horse" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let secret = body["secret"].as_str().unwrap().to_string();
+    assert!(body["uri"].as_str().unwrap().contains(&secret));
+    assert!(body["qrSvg"].as_str().unwrap().contains("<svg"));
+
+    let (status, body) = app
+        .request_as(
+            user,
+            Method::POST,
+            "/api/auth/2fa/confirm",
+            Some(json!({ "code": current_code(&secret, 0) })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let codes = body["recoveryCodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|code| code.as_str().unwrap().to_string())
+        .collect();
+    (secret, codes)
+}
+
+async fn login(app: &TestApp, user: &User) -> (StatusCode, Value) {
+    app.request(
+        Method::POST,
+        "/api/auth/login",
+        Some(json!({ "username": user.username, "password": "correct horse" })),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn login_needs_a_code_once_enabled() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let user = app.user("correct horse").await;
+    let (secret, recovery_codes) = enable(&app, &user).await;
+    assert_eq!(recovery_codes.len(), 10);
+
+    let (status, body) = login(&app, &user).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["twoFactorRequired"], true);
+    assert!(body["token"].is_null());
+    let challenge = body["challenge"].as_str().unwrap();
+
+    let (status, _) = app
+        .request(
+            Method::POST,
+            "/api/auth/2fa/login",
+            Some(json!({ "challenge": challenge, "code": "000000" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // the code used to confirm can't be used again, so take the next one
+    let code = current_code(&secret, 1);
+    let (status, body) = app
+        .request(
+            Method::POST,
+            "/api/auth/2fa/login",
+            Some(json!({ "challenge": challenge, "code": code })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["token"].is_string());
+
+    let (status, _) = app
+        .request(
+            Method::POST,
+            "/api/auth/2fa/login",
+            Some(json!({ "challenge": challenge, "code": code })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // recovery codes work once each, dash or not
+    let recovery_code = recovery_codes[0].to_uppercase().replace('-', "");
+    for expected in [StatusCode::OK, StatusCode::UNAUTHORIZED] {
+        let (status, _) = app
+            .request(
+                Method::POST,
+                "/api/auth/2fa/login",
+                Some(json!({ "challenge": challenge, "code": recovery_code })),
+            )
+            .await;
+        assert_eq!(status, expected);
+    }
+
+    let (status, body) = app
+        .request_as(&user, Method::GET, "/api/auth/2fa/status", None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "enabled": true, "recoveryCodesLeft": 9 }));
+}
+
+#[tokio::test]
+async fn challenges_and_tokens_cant_stand_in_for_each_other() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let user = app.user("correct horse").await;
+    enable(&app, &user).await;
+
+    let (_, body) = login(&app, &user).await;
+    let challenge = body["challenge"].as_str().unwrap().to_string();
+
+    let token = cca_club_hub::auth::generate_jwt(
+        &app.state.keys,
+        app.state.clock.as_ref(),
+        &user,
+        Vec::new(),
+        std::time::Duration::from_secs(60),
+    )
+    .unwrap();
+    let (status, _) = app
+        .request(
+            Method::POST,
+            "/api/auth/2fa/login",
+            Some(json!({ "challenge": token, "code": "000000" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    assert!(cca_club_hub::auth::validate_jwt(
+        &app.state.keys,
+        app.state.clock.as_ref(),
+        &challenge
+    )
+    .is_err());
+}
+
+#[tokio::test]
+async fn admins_can_reset_two_factor() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let user = app.user("correct horse").await;
+    enable(&app, &user).await;
+
+    assert!(matches!(
+        two_factor::reset(&app.state, &user.username).await,
+        Ok(true)
+    ));
+    assert!(matches!(
+        two_factor::reset(&app.state, &user.username).await,
+        Ok(false)
+    ));
+
+    let (status, body) = login(&app, &user).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["token"].is_string());
+}
+
+#[tokio::test]
+async fn disabling_needs_the_password_and_a_code() {
+    let Some(app) = common::test_app().await else {
+        return;
+    };
+    let user = app.user("correct horse").await;
+    let (secret, _) = enable(&app, &user).await;
+
+    let (status, _) = app
+        .request_as(
+            &user,
+            Method::POST,
+            "/api/auth/2fa/disable",
+            Some(json!({ "password": "battery staple", "code": current_code(&secret, 1) })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = app
+        .request_as(
+            &user,
+            Method::POST,
+            "/api/auth/2fa/disable",
+            Some(json!({ "password": "correct horse", "code": current_code(&secret, 1) })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, body) = login(&app, &user).await;
+    assert!(body["token"].is_string());
+}